@@ -0,0 +1,222 @@
+use nalgebra_glm::*;
+
+use crate::scene::SceneObject;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    // Maps progress in 0..1 to eased progress in 0..1
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+pub trait Interpolate: Copy {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        lerp(self, other, t)
+    }
+}
+
+// `easing` shapes the way into this keyframe from the one before it
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+    pub easing: Easing,
+}
+
+// Values keyed over time, in seconds. Before the first keyframe the track holds its first value,
+// after the last one it holds the last value, or starts over if it loops.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+    pub looping: bool,
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new(looping: bool) -> Self {
+        Self {
+            keyframes: vec![],
+            looping,
+        }
+    }
+
+    // Keyframes can be added in any order
+    pub fn key(mut self, time: f32, value: T, easing: Easing) -> Self {
+        let index = self.keyframes.partition_point(|k| k.time <= time);
+        self.keyframes.insert(
+            index,
+            Keyframe {
+                time,
+                value,
+                easing,
+            },
+        );
+        self
+    }
+
+    // Two keyframes, from `from` at 0 to `to` at `duration`
+    pub fn tween(from: T, to: T, duration: f32, easing: Easing) -> Self {
+        Self::new(false)
+            .key(0.0, from, Easing::Linear)
+            .key(duration, to, easing)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let first = self.keyframes.first()?;
+        let duration = self.duration();
+        let time = if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        };
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return Some(first.value);
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes.last().map(|k| k.value);
+        }
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = (time - from.time) / (to.time - from.time);
+        Some(from.value.interpolate(&to.value, to.easing.apply(t)))
+    }
+}
+
+// Tweens an object's surface parameters over time. Properties without a track are left alone.
+#[derive(Clone, Debug)]
+pub struct MaterialAnimation {
+    pub opacity: Option<Track<f32>>,
+    pub emissive: Option<Track<f32>>,
+    pub tint: Option<Track<Vec3>>,
+    elapsed: f32,
+}
+
+impl MaterialAnimation {
+    pub fn new() -> Self {
+        Self {
+            opacity: None,
+            emissive: None,
+            tint: None,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn fade_in(duration: f32) -> Self {
+        Self {
+            opacity: Some(Track::tween(0.0, 1.0, duration, Easing::EaseOut)),
+            ..Self::new()
+        }
+    }
+
+    // Glows in `color` and back, once every `period` seconds, for as long as it's updated
+    pub fn pulse(color: Vec3, intensity: f32, period: f32) -> Self {
+        let white = vec3(1.0, 1.0, 1.0);
+        Self {
+            emissive: Some(
+                Track::new(true)
+                    .key(0.0, 0.0, Easing::Linear)
+                    .key(period * 0.5, intensity, Easing::EaseInOut)
+                    .key(period, 0.0, Easing::EaseInOut),
+            ),
+            tint: Some(
+                Track::new(true)
+                    .key(0.0, white, Easing::Linear)
+                    .key(period * 0.5, color, Easing::EaseInOut)
+                    .key(period, white, Easing::EaseInOut),
+            ),
+            ..Self::new()
+        }
+    }
+
+    // Looping animations never finish
+    pub fn is_finished(&self) -> bool {
+        let tracks = [
+            self.opacity.as_ref().map(|t| (t.looping, t.duration())),
+            self.emissive.as_ref().map(|t| (t.looping, t.duration())),
+            self.tint.as_ref().map(|t| (t.looping, t.duration())),
+        ];
+        tracks
+            .iter()
+            .flatten()
+            .all(|(looping, duration)| !looping && self.elapsed >= *duration)
+    }
+
+    pub fn update(&mut self, dt: f32, object: &mut SceneObject) {
+        self.elapsed += dt;
+        let surface = object.get_surface_mut();
+        if let Some(opacity) = self.opacity.as_ref().and_then(|t| t.sample(self.elapsed)) {
+            surface.opacity = opacity;
+        }
+        if let Some(emissive) = self.emissive.as_ref().and_then(|t| t.sample(self.elapsed)) {
+            surface.emissive = emissive;
+        }
+        if let Some(tint) = self.tint.as_ref().and_then(|t| t.sample(self.elapsed)) {
+            surface.tint = tint;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easings_keep_endpoints() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+    }
+
+    #[test]
+    fn track_holds_ends_and_loops() {
+        let track = Track::new(false)
+            .key(2.0, 10.0, Easing::Linear)
+            .key(1.0, 0.0, Easing::Linear);
+        assert_eq!(track.sample(0.0), Some(0.0));
+        assert_eq!(track.sample(1.5), Some(5.0));
+        assert_eq!(track.sample(3.0), Some(10.0));
+
+        let looping = Track {
+            looping: true,
+            ..track
+        };
+        assert_eq!(looping.sample(3.5), Some(5.0));
+        assert_eq!(Track::<f32>::new(false).sample(1.0), None);
+    }
+}
@@ -7,6 +7,8 @@ use glfw::Key;
 use nalgebra_glm::*;
 
 use crate::controls::{Controller, SignalHandler, SignalType, Slot};
+use crate::data;
+use crate::spatial::Aabb;
 
 const ANGLE_LOWER_BOUND: f32 = 0.001;
 
@@ -18,6 +20,13 @@ pub struct Camera {
     yaw: f32,
     roll: f32,
     fov: f32,
+    near: f32,
+    far: f32,
+    reversed_z: bool,
+    log_depth: bool,
+    up: Vec3,
+    reflection: Mat4,
+    projection_version: u32, // bumped whenever the projection changes
 }
 
 impl Camera {
@@ -38,15 +47,187 @@ impl Camera {
             yaw,
             roll: 0.0,
             fov: 1.0,
+            near: 0.1,
+            far: 100.0,
+            reversed_z: false,
+            log_depth: false,
+            up: vec3(0.0, 1.0, 0.0),
+            reflection: Mat4::identity(),
+            projection_version: 0,
         }
     }
 
+    // Fixed camera for offscreen passes, e.g. one cubemap face. Keeps this camera's depth setup.
+    pub fn facing(&self, pos: Vec3, direction: Vec3, up: Vec3, fov: f32) -> Self {
+        Camera {
+            pos,
+            direction: normalize(&direction),
+            pitch: 0.0,
+            yaw: 0.0,
+            roll: 0.0,
+            fov,
+            up,
+            reflection: Mat4::identity(),
+            ..*self
+        }
+    }
+
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        if (near, far) != (self.near, self.far) {
+            self.near = near;
+            self.far = far;
+            self.projection_changed();
+        }
+    }
+    pub fn get_clip_planes(&self) -> (f32, f32) {
+        (self.near, self.far)
+    }
+
+    // Has to agree with `data::set_reversed_depth`, which sets up the matching GL state
+    pub fn set_reversed_z(&mut self, reversed: bool) {
+        if reversed != self.reversed_z {
+            self.reversed_z = reversed;
+            self.projection_changed();
+        }
+    }
+    pub fn is_reversed_z(&self) -> bool {
+        self.reversed_z
+    }
+
+    // Fallback for contexts without clip control: shaders write a depth proportional to the log of
+    // the distance instead. Ignored while reversed-Z is on.
+    pub fn set_logarithmic_depth(&mut self, log_depth: bool) {
+        if log_depth != self.log_depth {
+            self.log_depth = log_depth;
+            self.projection_changed();
+        }
+    }
+    pub fn is_logarithmic_depth(&self) -> bool {
+        self.log_depth && !self.reversed_z
+    }
+
+    // Value of the `logDepthCoef` uniform, 0 when logarithmic depth is off
+    pub fn log_depth_coefficient(&self) -> f32 {
+        if self.is_logarithmic_depth() {
+            2.0 / (self.far + 1.0).log2()
+        } else {
+            0.0
+        }
+    }
+
+    fn projection_changed(&mut self) {
+        self.projection_version = self.projection_version.wrapping_add(1);
+    }
+
+    // Changes with anything that goes into `projection` besides the aspect ratio. Compare it
+    // against the one a resource was built for, or use a `ProjectionWatch`.
+    pub fn get_projection_version(&self) -> u32 {
+        self.projection_version
+    }
+
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        if !self.reversed_z {
+            perspective(aspect, self.fov, self.near, self.far)
+        } else if data::is_depth_zero_to_one() {
+            reversed_perspective_rh_zo(aspect, self.fov, self.near, self.far)
+        } else {
+            scaling(&vec3(1.0, 1.0, -1.0)) * perspective(aspect, self.fov, self.near, self.far)
+        }
+    }
+
+    // Clip space depth of the far plane, for things drawn behind everything else like skyboxes
+    pub fn far_clip_depth(&self) -> f32 {
+        match (self.reversed_z, data::is_depth_zero_to_one()) {
+            (false, _) => 1.0,
+            (true, true) => 0.0,
+            (true, false) => -1.0,
+        }
+    }
+
+    // Clip space depth of the near plane, which is 1 whenever depth is reversed
+    pub fn near_clip_depth(&self) -> f32 {
+        if self.reversed_z {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    // View space distance of a depth buffer value, whichever convention produced it
+    pub fn linear_depth(&self, depth: f32) -> f32 {
+        let (near, far) = (self.near, self.far);
+        if self.is_logarithmic_depth() {
+            (far + 1.0).powf(depth) - 1.0
+        } else if !self.reversed_z {
+            let ndc = depth * 2.0 - 1.0;
+            2.0 * near * far / (far + near - ndc * (far - near))
+        } else if data::is_depth_zero_to_one() {
+            near * far / (near + depth * (far - near))
+        } else {
+            let ndc = 1.0 - depth * 2.0;
+            2.0 * near * far / (far + near - ndc * (far - near))
+        }
+    }
+
+    // World space corners of the view frustum between the distances `near` and `far`, near plane
+    // first. Ignores reflections.
+    pub fn frustum_corners(&self, aspect: f32, near: f32, far: f32) -> [Vec3; 8] {
+        let forward = normalize(&self.direction);
+        let right = normalize(&cross(&forward, &self.up));
+        let up = cross(&right, &forward);
+        let tan_half_fov = (self.fov / 2.0).tan();
+        let mut corners = [Vec3::zeros(); 8];
+        for (i, distance) in [near, far].iter().enumerate() {
+            let center = self.pos + forward * *distance;
+            let half_height = up * tan_half_fov * *distance;
+            let half_width = right * tan_half_fov * *distance * aspect;
+            corners[i * 4] = center - half_width - half_height;
+            corners[i * 4 + 1] = center + half_width - half_height;
+            corners[i * 4 + 2] = center + half_width + half_height;
+            corners[i * 4 + 3] = center - half_width + half_height;
+        }
+        corners
+    }
+
+    // Moves back along the view direction until all of `aabb` is in view, with `margin` as a
+    // fraction of its size to spare. Never so far that the back of the box goes beyond the far
+    // plane, so boxes too large for that are only partly in view.
+    pub fn frame_aabb(&mut self, aabb: &Aabb, margin: f32) {
+        let sphere = aabb.bounding_sphere();
+        let radius = sphere.radius.max(ANGLE_LOWER_BOUND) * (1.0 + margin.max(0.0));
+        let distance = (radius / (self.fov / 2.0).sin())
+            .min(self.far - sphere.radius)
+            .max(self.near);
+        self.pos = sphere.center - normalize(&self.direction) * distance;
+    }
+
     pub fn look_at(&self) -> Mat4 {
         look_at(
             &self.pos,
             &(self.direction + self.pos),
-            &vec3(0.0, 1.0, 0.0),
-        )
+            &self.up,
+        ) * self.reflection
+    }
+
+    // Same camera seeing the world mirrored about the plane through `point` with `normal`.
+    // Reflections flip the triangle winding, so front faces must be drawn clockwise with it.
+    pub fn reflected(&self, point: &Vec3, normal: &Vec3) -> Camera {
+        let n = normalize(normal);
+        let d = dot(&n, point);
+        let mut plane_reflection = Mat4::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                plane_reflection[(i, j)] -= 2.0 * n[i] * n[j];
+            }
+            plane_reflection[(i, 3)] = 2.0 * d * n[i];
+        }
+        let mut reflected = *self;
+        reflected.reflection = self.reflection * plane_reflection;
+        reflected
+    }
+
+    pub fn is_reflected(&self) -> bool {
+        determinant(&mat4_to_mat3(&self.reflection)) < 0.0
     }
 
     pub fn translate(&mut self, offset: Vec3) {
@@ -113,7 +294,10 @@ impl Camera {
     }
 
     pub fn change_fov(&mut self, offset: f32) {
-        self.fov += offset.to_radians();
+        if offset != 0.0 {
+            self.fov += offset.to_radians();
+            self.projection_changed();
+        }
     }
     pub fn get_fov(&self) -> f32 {
         self.fov
@@ -127,6 +311,30 @@ impl Camera {
     }
 }
 
+// Tells a system depending on a camera's projection (history buffers, kernels sized by the field
+// of view, cascades split along the clip range, layouts) when it has to rebuild
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProjectionWatch {
+    seen: Option<(u32, f32)>, // version and aspect ratio
+}
+
+impl ProjectionWatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // True the first time it's asked, and whenever the projection changed since the last time
+    pub fn changed(&mut self, camera: &Camera, aspect: f32) -> bool {
+        let current = Some((camera.get_projection_version(), aspect));
+        std::mem::replace(&mut self.seen, current) != current
+    }
+
+    // Makes the next `changed` report a change, e.g. after the dependent resource was dropped
+    pub fn reset(&mut self) {
+        self.seen = None;
+    }
+}
+
 pub struct CameraController {
     pub inv_vertical: bool,
     pub trans_speed: f32,
@@ -160,6 +368,11 @@ impl<'a> CameraController {
         self.cycle_time = cycle_time;
     }
 
+    // Until the movement keys are pressed again
+    pub fn stop(&mut self) {
+        self.positive_delta_mov = Vec3::zeros();
+        self.negative_delta_mov = Vec3::zeros();
+    }
     pub fn on_key_pressed(&mut self, keycode: Keycode) {
         match keycode {
             Keycode::D => self.positive_delta_mov.x = self.trans_speed,
@@ -185,6 +398,9 @@ impl<'a> CameraController {
     pub fn on_mouse_moved(&mut self, x: i32, y: i32) {
         self.delta_rot += vec3(-x as f32, y as f32, 0.0) * self.rot_speed;
     }
+    pub fn on_mouse_reset(&mut self) {
+        self.delta_rot *= 0.0;
+    }
     pub fn on_mouse_scrolled(&mut self, y: i32) {
         self.delta_zoom += y as f32 * self.zoom_speed;
     }
@@ -197,6 +413,7 @@ impl<'a> Slot for CameraController {
             SignalType::KeyReleased(key) => self.on_key_released(key),
             SignalType::MouseMoved(x, y) => self.on_mouse_moved(x, y),
             SignalType::MouseScrolled(y) => self.on_mouse_scrolled(y),
+            SignalType::MouseReset => self.on_mouse_reset(),
             _ => (),
         }
     }
@@ -220,3 +437,109 @@ impl<'a> Controller<'a, Camera, CameraController> for Rc<RefCell<CameraControlle
         self_obj.delta_zoom = 0.0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Window depth of a point `distance` units in front of the camera, with a [-1, 1] clip range
+    fn window_depth(camera: &Camera, distance: f32) -> f32 {
+        let clip = camera.projection(1.0) * vec4(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w * 0.5 + 0.5
+    }
+
+    #[test]
+    fn linear_depth_inverts_projection() {
+        let mut camera = Camera::new(vec3(0.0, 0.0, -2.0));
+        camera.set_clip_planes(0.1, 300.0);
+        for reversed in [false, true] {
+            camera.set_reversed_z(reversed);
+            for distance in [0.5, 10.0, 250.0] {
+                let depth = window_depth(&camera, distance);
+                let linear = camera.linear_depth(depth);
+                assert!(
+                    (linear - distance).abs() / distance < 1e-3,
+                    "{linear} != {distance}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn linear_depth_inverts_logarithmic_depth() {
+        let mut camera = Camera::new(vec3(0.0, 0.0, -2.0));
+        camera.set_clip_planes(0.1, 300.0);
+        camera.set_logarithmic_depth(true);
+        let coefficient = camera.log_depth_coefficient();
+        for distance in [0.5f32, 10.0, 250.0] {
+            // what the fragment shader writes, with w being the view space distance
+            let depth = (1.0 + distance).log2() * coefficient * 0.5;
+            let linear = camera.linear_depth(depth);
+            assert!((linear - distance).abs() / distance < 1e-3);
+        }
+        assert!(((1.0f32 + 300.0).log2() * coefficient * 0.5 - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn watch_sees_projection_changes_only() {
+        let mut camera = Camera::new(vec3(0.0, 0.0, -2.0));
+        let mut watch = ProjectionWatch::new();
+        assert!(watch.changed(&camera, 1.0));
+        assert!(!watch.changed(&camera, 1.0));
+
+        camera.translate_frontal(1.0);
+        camera.rotate_yaw(10.0);
+        camera.change_fov(0.0);
+        assert!(!watch.changed(&camera, 1.0));
+
+        camera.change_fov(5.0);
+        assert!(watch.changed(&camera, 1.0));
+        camera.set_clip_planes(0.5, 50.0);
+        assert!(watch.changed(&camera, 1.0));
+        assert!(watch.changed(&camera, 2.0));
+        assert!(!watch.changed(&camera, 2.0));
+    }
+
+    #[test]
+    fn framed_box_is_in_view() {
+        let mut camera = Camera::new(vec3(0.0, 0.0, -2.0));
+        camera.rotate_yaw(40.0);
+        let aabb = Aabb::new(vec3(3.0, -1.0, 2.0), vec3(5.0, 2.0, 4.0));
+        camera.frame_aabb(&aabb, 0.1);
+        let view_projection = camera.projection(1.0) * camera.look_at();
+        for corner in aabb.corners() {
+            let clip = view_projection * corner.push(1.0);
+            let ndc = clip.xyz() / clip.w;
+            assert!(
+                clip.w > 0.0 && ndc.x.abs() < 1.0 && ndc.y.abs() < 1.0,
+                "{ndc}"
+            );
+        }
+        let center = view_projection * aabb.center().push(1.0);
+        assert!((center.xy() / center.w).norm() < 1e-4);
+    }
+
+    #[test]
+    fn framing_stays_inside_the_far_plane() {
+        let mut camera = Camera::new(vec3(0.0, 0.0, -2.0));
+        camera.set_clip_planes(0.1, 100.0);
+        let aabb = Aabb::new(vec3(-40.0, -40.0, -40.0), vec3(40.0, 40.0, 40.0));
+        camera.frame_aabb(&aabb, 0.1);
+        let farthest = aabb
+            .corners()
+            .iter()
+            .map(|corner| distance(corner, &camera.get_pos()))
+            .fold(0.0, f32::max);
+        assert!(farthest <= 100.0 + 1e-3, "{farthest}");
+    }
+
+    #[test]
+    fn reversed_z_puts_near_plane_at_one() {
+        let mut camera = Camera::new(vec3(0.0, 0.0, -2.0));
+        camera.set_clip_planes(0.1, 100.0);
+        camera.set_reversed_z(true);
+        assert!((window_depth(&camera, 0.1) - 1.0).abs() < 1e-4);
+        assert!(window_depth(&camera, 100.0).abs() < 1e-4);
+        assert!(window_depth(&camera, 1.0) > window_depth(&camera, 2.0));
+    }
+}
@@ -0,0 +1,92 @@
+use std::f32::consts::FRAC_PI_2;
+use std::ffi::c_void;
+use std::path::Path;
+
+use gl33::gl_enumerations::*;
+use gl33::global_loader::*;
+use image::{ImageBuffer, Rgba};
+use nalgebra_glm::*;
+
+use crate::camera::Camera;
+use crate::data::{Framebuffer, RenderTarget, UniformBuffer};
+use crate::debug::DebugGroup;
+use crate::scene::Scene;
+
+// Same order and names the skybox loader expects, with the usual GL cubemap orientation
+const FACES: [(&str, [f32; 3], [f32; 3]); 6] = [
+    ("right", [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ("left", [-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ("top", [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ("bottom", [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ("front", [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ("back", [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+// Renders `scene` from `position` into the six faces of a cubemap and saves them as
+// `<face>.png` in `directory`, ready to be loaded back as a skybox
+pub fn capture_cubemap(
+    scene: &mut Scene,
+    ubo: &UniformBuffer,
+    position: Vec3,
+    size: u32,
+    directory: &Path,
+) -> Result<(), String> {
+    std::fs::create_dir_all(directory)
+        .map_err(|e| format!("Couldn't create {}: {}", directory.display(), e))?;
+    let target = RenderTarget::new((size, size)).ok_or("Couldn't make the capture target")?;
+    let camera = scene.camera;
+    // faces are stills, the main camera's last frame says nothing about their motion
+    let previous_view_projection = scene.previous_view_projection.take();
+    // and hooks are for the main view
+    let hooks = scene.hooks.take();
+
+    let mut result = Ok(());
+    for (name, direction, up) in FACES {
+        scene.camera = camera.facing(
+            position,
+            make_vec3(&direction),
+            make_vec3(&up),
+            FRAC_PI_2,
+        );
+        let group = DebugGroup::new(&format!("Cubemap capture ({})", name));
+        let pixels = render_face(scene, ubo, &target);
+        drop(group);
+        // Rows come back bottom first, which is what cubemap faces expect at the top
+        let face = ImageBuffer::<Rgba<u8>, _>::from_raw(size, size, pixels).unwrap();
+        let path = directory.join(format!("{}.png", name));
+        if let Err(e) = face.save(&path) {
+            result = Err(format!("Couldn't save {}: {}", path.display(), e));
+            break;
+        }
+    }
+
+    scene.camera = camera;
+    scene.previous_view_projection = previous_view_projection;
+    scene.hooks = hooks;
+    result
+}
+
+fn render_face(scene: &mut Scene, ubo: &UniformBuffer, target: &RenderTarget) -> Vec<u8> {
+    let size = target.get_size();
+    let mut pixels = vec![0u8; (size.0 * size.1 * 4) as usize];
+    target.bind();
+    unsafe {
+        glViewport(0, 0, size.0 as i32, size.1 as i32);
+        glClear(GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT | GL_STENCIL_BUFFER_BIT);
+        glEnable(GL_DEPTH_TEST);
+    }
+    scene.compose(ubo);
+    unsafe {
+        glReadPixels(
+            0,
+            0,
+            size.0 as i32,
+            size.1 as i32,
+            GL_RGBA,
+            GL_UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut c_void,
+        );
+    }
+    Framebuffer::clear_binding();
+    pixels
+}
@@ -0,0 +1,155 @@
+use std::{cell::RefCell, rc::Rc};
+
+use beryllium::Keycode;
+
+use crate::controls::{Controller, SignalType, Slot};
+
+const MIN_SCALE: f32 = 1.0 / 16.0;
+const MAX_SCALE: f32 = 16.0;
+
+// Engine time, in seconds. It can be slowed down, sped up or paused, and everything animated by it
+// (instances, material animations, scripts and shaders) follows along. Real time keeps going for
+// things that shouldn't stop with it, like the camera.
+pub struct Clock {
+    time: f32,
+    delta: f32,
+    real_time: f32,
+    scale: f32,
+    paused: bool,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            time: 0.0,
+            delta: 0.0,
+            real_time: 0.0,
+            scale: 1.0,
+            paused: false,
+        }
+    }
+
+    // Once per frame, with the real time the frame took
+    pub fn advance(&mut self, real_delta: f32) {
+        let real_delta = real_delta.max(0.0);
+        self.real_time += real_delta;
+        self.delta = if self.paused {
+            0.0
+        } else {
+            real_delta * self.scale
+        };
+        self.time += self.delta;
+    }
+
+    pub fn get_time(&self) -> f32 {
+        self.time
+    }
+
+    // Engine time the last frame took, 0 while paused
+    pub fn get_delta(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn get_real_time(&self) -> f32 {
+        self.real_time
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    pub fn get_scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+// Pause toggles the clock, page up and page down double and halve its speed
+pub struct ClockController {
+    paused: bool,
+    scale_steps: i32,
+}
+
+impl ClockController {
+    pub fn new() -> Rc<RefCell<ClockController>> {
+        Rc::new(RefCell::new(Self {
+            paused: false,
+            scale_steps: 0,
+        }))
+    }
+    pub fn on_key_pressed(&mut self, keycode: Keycode) {
+        match keycode {
+            Keycode::PAUSE => self.paused = !self.paused,
+            Keycode::PAGEUP => self.scale_steps += 1,
+            Keycode::PAGEDOWN => self.scale_steps -= 1,
+            _ => (),
+        }
+    }
+}
+
+impl Slot for ClockController {
+    fn on_signal(&mut self, signal: SignalType) {
+        match signal {
+            SignalType::KeyPressed(key) => self.on_key_pressed(key),
+            _ => (),
+        }
+    }
+}
+
+impl<'a> Controller<'a, Clock, ClockController> for Rc<RefCell<ClockController>> {
+    fn update_control_parameters(&self, update: &'a mut (dyn FnMut(&mut ClockController))) {
+        update(&mut (**self).borrow_mut());
+    }
+    fn process_signals(&'a self, obj: &mut Clock) {
+        let mut self_obj = (**self).borrow_mut();
+        obj.set_paused(self_obj.paused);
+        let steps = std::mem::take(&mut self_obj.scale_steps);
+        if steps != 0 {
+            obj.set_scale(obj.get_scale() * 2.0f32.powi(steps));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_and_paused_time_leave_real_time_alone() {
+        let mut clock = Clock::new();
+        clock.set_scale(0.5);
+        clock.advance(0.2);
+        assert_eq!(clock.get_delta(), 0.1);
+        clock.set_paused(true);
+        clock.advance(0.2);
+        assert_eq!(clock.get_delta(), 0.0);
+        assert_eq!(clock.get_time(), 0.1);
+        assert!((clock.get_real_time() - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn controller_pauses_and_changes_speed() {
+        let controller = ClockController::new();
+        let mut clock = Clock::new();
+        for key in [Keycode::PAUSE, Keycode::PAGEUP, Keycode::PAGEUP] {
+            controller
+                .borrow_mut()
+                .on_signal(SignalType::KeyPressed(key));
+        }
+        controller.process_signals(&mut clock);
+        assert!(clock.is_paused());
+        assert_eq!(clock.get_scale(), 4.0);
+
+        // the speed steps are applied once, pausing stays until toggled back
+        controller.process_signals(&mut clock);
+        assert!(clock.is_paused());
+        assert_eq!(clock.get_scale(), 4.0);
+    }
+}
@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+
+use crate::textures::{ColorSpace, Material, Texture2D, TextureType};
+use crate::vfs;
+
+const SWATCH_SIZE: u32 = 128;
+const SWATCH_GAP: u32 = 4;
+const SHEET_BACKGROUND: Rgba<u8> = Rgba([46, 46, 46, 255]);
+
+// Names that give away what a map holds, checked against the file name only
+const LINEAR_HINTS: [&str; 9] = [
+    "spec", "normal", "nrm", "mask", "rough", "metal", "height", "_ao", "gloss",
+];
+const SRGB_HINTS: [&str; 4] = ["diff", "albedo", "basecolor", "color"];
+
+// What a material samples a map for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapRole {
+    Diffuse,
+    Specular,
+    Transmission,
+    SplatMask,
+    SplatAlbedo,
+    SplatNormal,
+}
+
+impl MapRole {
+    pub fn expected_color_space(self) -> ColorSpace {
+        match self {
+            MapRole::Diffuse | MapRole::Transmission | MapRole::SplatAlbedo => ColorSpace::Srgb,
+            MapRole::Specular | MapRole::SplatMask | MapRole::SplatNormal => ColorSpace::Linear,
+        }
+    }
+}
+
+fn hinted_color_space(path: &str) -> Option<ColorSpace> {
+    let name = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())?;
+    if LINEAR_HINTS.iter().any(|hint| name.contains(hint)) {
+        Some(ColorSpace::Linear)
+    } else if SRGB_HINTS.iter().any(|hint| name.contains(hint)) {
+        Some(ColorSpace::Srgb)
+    } else {
+        None
+    }
+}
+
+// A loaded texture, how it's sampled and how it probably should be
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureCheck {
+    pub path: String,
+    pub role: MapRole,
+    pub actual: ColorSpace,
+    pub hinted: Option<ColorSpace>, // by the file name
+}
+
+impl TextureCheck {
+    pub fn new(path: &str, role: MapRole, actual: ColorSpace) -> Self {
+        Self {
+            path: path.to_string(),
+            role,
+            actual,
+            hinted: hinted_color_space(path),
+        }
+    }
+
+    // The role decides, the name only breaks the tie when the role is happy with what was loaded
+    pub fn suggested(&self) -> ColorSpace {
+        let expected = self.role.expected_color_space();
+        if self.actual != expected {
+            expected
+        } else {
+            self.hinted.unwrap_or(expected)
+        }
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        self.suggested() != self.actual
+    }
+
+    pub fn describe(&self) -> String {
+        let verdict = if self.is_suspicious() {
+            format!("should probably be {:?}", self.suggested())
+        } else {
+            "ok".to_string()
+        };
+        format!(
+            "{} ({:?} map): loaded as {:?}, {}",
+            self.path, self.role, self.actual, verdict
+        )
+    }
+}
+
+fn material_maps(material: &Material) -> Vec<(MapRole, &Texture2D)> {
+    let mut maps = vec![];
+    maps.extend(
+        material
+            .get_diffuse_maps()
+            .iter()
+            .map(|map| (MapRole::Diffuse, map)),
+    );
+    maps.extend(
+        material
+            .get_specular_maps()
+            .iter()
+            .map(|map| (MapRole::Specular, map)),
+    );
+    if let Some(map) = &material.get_transmission().map {
+        maps.push((MapRole::Transmission, map));
+    }
+    if let Some(splat) = material.get_splat_map() {
+        maps.push((MapRole::SplatMask, &splat.mask));
+        for layer in &splat.layers {
+            maps.push((MapRole::SplatAlbedo, &layer.albedo));
+            if let Some(normal) = &layer.normal {
+                maps.push((MapRole::SplatNormal, normal));
+            }
+        }
+    }
+    maps
+}
+
+// Every texture loaded from a file, once per role it's used in. Generated ones (solid colors,
+// render targets) have no file to compare against and are left out.
+pub fn audit<'a>(materials: impl IntoIterator<Item = &'a Material>) -> Vec<TextureCheck> {
+    let mut checks: Vec<TextureCheck> = vec![];
+    for material in materials {
+        for (role, map) in material_maps(material) {
+            if map.get_path().is_empty() {
+                continue;
+            }
+            let check = TextureCheck::new(map.get_path(), role, map.get_color_space());
+            if !checks.contains(&check) {
+                checks.push(check);
+            }
+        }
+    }
+    checks
+}
+
+pub fn report(checks: &[TextureCheck]) -> String {
+    let suspicious = checks.iter().filter(|check| check.is_suspicious()).count();
+    let mut out = format!(
+        "Texture color spaces: {} checked, {} suspicious\n",
+        checks.len(),
+        suspicious
+    );
+    for check in checks {
+        out += &format!("  {}\n", check.describe());
+    }
+    out
+}
+
+// A texel channel as it ends up on the monitor when sampled in `space`, once the screen corrects
+// it with `gamma`
+fn displayed(value: u8, space: ColorSpace, gamma: f32) -> u8 {
+    let stored = value as f32 / 255.0;
+    let linear = match space {
+        ColorSpace::Srgb if stored <= 0.04045 => stored / 12.92,
+        ColorSpace::Srgb => ((stored + 0.055) / 1.055).powf(2.4),
+        ColorSpace::Linear => stored,
+    };
+    (linear.powf(1.0 / gamma) * 255.0).round() as u8
+}
+
+fn swatch(image: &RgbaImage, space: ColorSpace, gamma: f32) -> RgbaImage {
+    let mut swatch = imageops::resize(image, SWATCH_SIZE, SWATCH_SIZE, FilterType::Triangle);
+    for pixel in swatch.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = displayed(pixel[channel], space, gamma);
+        }
+    }
+    swatch
+}
+
+// One row per suspicious texture: on the left as it's sampled now, on the right as it would be
+// in the suggested color space. A map that looks right only on the right is loaded wrong. The
+// colors are final, as a screen correcting with `gamma` would show them.
+pub fn comparison_sheet(checks: &[TextureCheck], gamma: f32) -> Result<Option<RgbaImage>, String> {
+    let suspicious: Vec<_> = checks
+        .iter()
+        .filter(|check| check.is_suspicious())
+        .collect();
+    if suspicious.is_empty() {
+        return Ok(None);
+    }
+    let width = SWATCH_SIZE * 2 + SWATCH_GAP * 3;
+    let height = (SWATCH_SIZE + SWATCH_GAP) * suspicious.len() as u32 + SWATCH_GAP;
+    let mut sheet = RgbaImage::from_pixel(width, height, SHEET_BACKGROUND);
+    for (row, check) in suspicious.iter().enumerate() {
+        let file = vfs::read(Path::new(&check.path))?;
+        let image = image::load_from_memory(&file)
+            .map_err(|e| format!("Couldn't decode {}: {}", check.path, e))?
+            .to_rgba8();
+        let y = (SWATCH_GAP + (SWATCH_SIZE + SWATCH_GAP) * row as u32) as i64;
+        let left = SWATCH_GAP as i64;
+        let right = (SWATCH_GAP * 2 + SWATCH_SIZE) as i64;
+        imageops::replace(&mut sheet, &swatch(&image, check.actual, gamma), left, y);
+        imageops::replace(
+            &mut sheet,
+            &swatch(&image, check.suggested(), gamma),
+            right,
+            y,
+        );
+    }
+    Ok(Some(sheet))
+}
+
+pub fn export_sheet(sheet: &RgbaImage, path: &Path) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("Couldn't create {}: {}", directory.display(), e))?;
+    }
+    sheet
+        .save(path)
+        .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+}
+
+// The sheet as a texture, whose texels are shown on screen as they are
+pub fn sheet_texture(sheet: &RgbaImage) -> Result<Texture2D, String> {
+    // textures start from the bottom row
+    let flipped = imageops::flip_vertical(sheet);
+    let texture = Texture2D::new(TextureType::Data);
+    texture.upload_rgba8(flipped.width(), flipped.height(), flipped.as_raw())?;
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specular_maps_loaded_as_srgb_are_flagged() {
+        let check = TextureCheck::new(
+            "textures/crate_spec.png",
+            MapRole::Specular,
+            ColorSpace::Srgb,
+        );
+        assert!(check.is_suspicious());
+        assert_eq!(check.suggested(), ColorSpace::Linear);
+        // and they come out darker than they should
+        assert!(displayed(128, ColorSpace::Srgb, 2.2) < displayed(128, ColorSpace::Linear, 2.2));
+    }
+
+    #[test]
+    fn names_break_ties_the_role_doesnt_see() {
+        let mask = TextureCheck::new("textures/leaf_mask.png", MapRole::Diffuse, ColorSpace::Srgb);
+        assert_eq!(mask.hinted, Some(ColorSpace::Linear));
+        assert!(mask.is_suspicious());
+
+        let albedo = TextureCheck::new(
+            "textures/rock_albedo.png",
+            MapRole::Diffuse,
+            ColorSpace::Srgb,
+        );
+        assert!(!albedo.is_suspicious());
+        let plain = TextureCheck::new("textures/window.png", MapRole::Specular, ColorSpace::Linear);
+        assert_eq!(plain.hinted, None);
+        assert!(!plain.is_suspicious());
+    }
+
+    #[test]
+    fn linear_values_only_get_the_display_gamma() {
+        for value in [0, 10, 128, 255] {
+            let linear = value as f32 / 255.0;
+            let shown = displayed(value, ColorSpace::Linear, 2.2) as f32 / 255.0;
+            assert!((shown - linear.powf(1.0 / 2.2)).abs() < 1.0 / 255.0);
+        }
+        assert_eq!(displayed(255, ColorSpace::Srgb, 2.2), 255);
+        assert_eq!(displayed(0, ColorSpace::Srgb, 2.2), 0);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::vfs;
+
+// Settings kept between runs, as `key = value` lines. Keys nobody asks for are written back as
+// they were, so a file can be shared between versions that know different settings.
+pub struct Config {
+    path: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl Config {
+    // A missing file is just an empty config, it's created on the first save
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => BTreeMap::new(),
+        };
+        Self { path, entries }
+    }
+
+    // Settings that ship with the assets, like a scene file, read through the VFS. Reported and
+    // left empty if they can't be read.
+    pub fn load_asset<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = match vfs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) => {
+                eprintln!("{}", e);
+                BTreeMap::new()
+            }
+        };
+        Self { path, entries }
+    }
+
+    fn parse(text: &str) -> BTreeMap<String, String> {
+        let mut entries = BTreeMap::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    entries.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                None => eprintln!("Ignoring config line without a value: {}", line),
+            }
+        }
+        entries
+    }
+
+    fn serialize(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(key, value)| format!("{} = {}\n", key, value))
+            .collect()
+    }
+
+    // None if the key is missing or doesn't parse as a `T`
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.entries.get(key)?.parse().ok()
+    }
+
+    pub fn set<T: Display>(&mut self, key: &str, value: T) {
+        self.entries.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        fs::write(&self.path, self.serialize())
+            .map_err(|e| format!("Couldn't save config {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_text() {
+        let mut config = Config {
+            path: PathBuf::new(),
+            entries: Config::parse("# comment\nscreen.gamma = 2.4\n\nbroken line\nother=x\n"),
+        };
+        assert_eq!(config.get::<f32>("screen.gamma"), Some(2.4));
+        assert_eq!(config.get::<f32>("other"), None);
+        config.set("screen.exposure", -0.5);
+        let reparsed = Config::parse(&config.serialize());
+        assert_eq!(reparsed, config.entries);
+        assert_eq!(reparsed["screen.exposure"], "-0.5");
+    }
+}
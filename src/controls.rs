@@ -5,7 +5,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use beryllium::{Event, KeyInfo, KeyboardEvent, Keycode, SDL};
+use beryllium::{Event, KeyInfo, KeyboardEvent, Keycode, WindowEventEnum, SDL};
 
 pub trait Slot {
     fn on_signal(&mut self, signal: SignalType);
@@ -14,11 +14,18 @@ pub trait Slot {
 pub struct SignalHandler<'a> {
     sdl: &'a SDL,
     slots: Vec<Weak<RefCell<dyn Slot>>>,
+    mouse_captured: bool,
+    skip_motion: bool, // the first motion after a capture change carries the warp delta
 }
 
 impl<'a> SignalHandler<'a> {
     pub fn new(sdl: &'a SDL) -> Self {
-        Self { sdl, slots: vec![] }
+        Self {
+            sdl,
+            slots: vec![],
+            mouse_captured: true,
+            skip_motion: true,
+        }
     }
     pub fn connect(&mut self, slot: Weak<RefCell<dyn Slot>>) {
         self.slots.push(slot);
@@ -30,7 +37,22 @@ impl<'a> SignalHandler<'a> {
                 .on_signal(signal_value);
         }
     }
-    pub fn wait_event(&self) {
+    pub fn set_mouse_captured(&mut self, captured: bool) {
+        let _ = self.sdl.set_relative_mouse_mode(captured);
+        self.mouse_captured = captured;
+        self.reset_mouse();
+    }
+    pub fn toggle_mouse_capture(&mut self) {
+        self.set_mouse_captured(!self.mouse_captured);
+    }
+    pub fn is_mouse_captured(&self) -> bool {
+        self.mouse_captured
+    }
+    fn reset_mouse(&mut self) {
+        self.skip_motion = true;
+        self.emit(SignalType::MouseReset);
+    }
+    pub fn wait_event(&mut self) {
         // let frame_start = self.sdl.get_ticks();
         let mut new_keys_state = HashMap::new();
         while let Some(event) = self.sdl.poll_events().and_then(Result::ok) {
@@ -38,16 +60,35 @@ impl<'a> SignalHandler<'a> {
                 Event::Quit(_) => {
                     self.emit(SignalType::Quit);
                 }
+                Event::Window(window_event) => match window_event.event {
+                    WindowEventEnum::FocusGained => self.reset_mouse(),
+                    WindowEventEnum::FocusLost => self.reset_mouse(),
+                    _ => (),
+                },
                 Event::Keyboard(key_event) => {
                     let keycode = key_event.key.keycode;
                     let pressed = key_event.is_pressed;
                     new_keys_state.insert(keycode, pressed);
                 }
                 Event::MouseMotion(motion_event) => {
-                    self.emit(SignalType::MouseMoved(
-                        motion_event.y_delta,
-                        motion_event.x_delta,
-                    ));
+                    if self.skip_motion {
+                        self.skip_motion = false;
+                    } else if self.mouse_captured {
+                        self.emit(SignalType::MouseMoved(
+                            motion_event.y_delta,
+                            motion_event.x_delta,
+                        ));
+                    }
+                }
+                Event::MouseButton(button_event) => {
+                    // clicks only mean something when there's a cursor to point with
+                    if button_event.is_pressed && !self.mouse_captured {
+                        self.emit(SignalType::MouseClicked(
+                            MouseButton::from(button_event.button),
+                            button_event.x,
+                            button_event.y,
+                        ));
+                    }
                 }
                 Event::MouseWheel(wheel_event) => {
                     self.emit(SignalType::MouseScrolled(wheel_event.y_delta));
@@ -65,12 +106,33 @@ impl<'a> SignalHandler<'a> {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    Other,
+}
+
+impl From<beryllium::MouseButton> for MouseButton {
+    fn from(button: beryllium::MouseButton) -> Self {
+        match button {
+            beryllium::MouseButton::Left => MouseButton::Left,
+            beryllium::MouseButton::Middle => MouseButton::Middle,
+            beryllium::MouseButton::Right => MouseButton::Right,
+            _ => MouseButton::Other,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum SignalType {
     KeyPressed(Keycode),
     KeyReleased(Keycode),
     MouseMoved(i32, i32),
+    MouseClicked(MouseButton, i32, i32), // window coordinates, origin at the top left
     MouseScrolled(i32),
+    MouseReset,
     Quit,
 }
 
@@ -1,6 +1,9 @@
+use std::cell::{Cell, RefCell};
 use std::ffi::c_void;
+use std::mem::size_of;
 use std::path::Path;
 use std::ptr::null;
+use std::rc::Rc;
 
 use beryllium::GlWindow;
 use bytemuck::offset_of;
@@ -15,6 +18,7 @@ use crate::meshes::Vertex;
 use crate::textures::{Texture2D, Texture2DMultisample, TextureType};
 
 const SAMPLES: u32 = 16;
+const UBO_FRAMES: usize = 3;
 
 // I really don't like the way this file is right now.
 
@@ -38,6 +42,10 @@ impl VertexArray {
     pub fn clear_binding() {
         glBindVertexArray(0)
     }
+
+    pub fn delete(self) {
+        unsafe { glDeleteVertexArrays(1, &self.0) }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -68,6 +76,10 @@ impl Buffer {
     pub fn clear_binding(ty: BufferType) {
         unsafe { glBindBuffer(GLenum(ty as u32), 0) }
     }
+
+    pub fn delete(self) {
+        unsafe { glDeleteBuffers(1, &self.0) }
+    }
 }
 
 pub fn buffer_data(ty: BufferType, data: &[u8], usage: GLenum) {
@@ -81,6 +93,342 @@ pub fn buffer_data(ty: BufferType, data: &[u8], usage: GLenum) {
     }
 }
 
+// Allocates (or orphans) the bound buffer's storage without uploading anything
+pub fn allocate_buffer(ty: BufferType, size: usize, usage: GLenum) {
+    unsafe {
+        glBufferData(GLenum(ty as u32), size.try_into().unwrap(), null(), usage);
+    }
+}
+
+pub fn map_buffer_range(
+    ty: BufferType,
+    offset: usize,
+    length: usize,
+    access: GLbitfield,
+) -> *mut c_void {
+    unsafe {
+        glMapBufferRange(
+            GLenum(ty as u32),
+            offset.try_into().unwrap(),
+            length.try_into().unwrap(),
+            access,
+        )
+    }
+}
+
+pub fn unmap_buffer(ty: BufferType) -> bool {
+    unsafe { glUnmapBuffer(GLenum(ty as u32)) != 0 }
+}
+
+// glClipControl is core since 4.5, beyond what the loader covers, so it's fetched by hand
+type ClipControl = extern "system" fn(u32, u32);
+const GL_LOWER_LEFT: u32 = 0x8CA1;
+const GL_NEGATIVE_ONE_TO_ONE: u32 = 0x935E;
+const GL_ZERO_TO_ONE: u32 = 0x935F;
+
+thread_local! {
+    static CLIP_CONTROL: Cell<Option<ClipControl>> = Cell::new(None);
+    static DEPTH_ZERO_TO_ONE: Cell<bool> = Cell::new(false);
+    static DEPTH_REVERSED: Cell<bool> = Cell::new(false);
+}
+
+pub fn load_clip_control(win: &GlWindow) -> bool {
+    let clip_control = unsafe { win.get_proc_address(b"glClipControl\0".as_ptr() as *const i8) };
+    if clip_control.is_null() {
+        return false;
+    }
+    CLIP_CONTROL.with(|f| f.set(Some(unsafe { std::mem::transmute(clip_control) })));
+    true
+}
+
+// Reversed depth maps the far plane to 0 and the near plane to 1, which spreads float precision
+// evenly over the distance. It only pays off fully with a [0, 1] clip range, so that's switched
+// on too when glClipControl is there; without it the usual [-1, 1] range is kept.
+pub fn set_reversed_depth(reversed: bool) {
+    let zero_to_one = reversed
+        && CLIP_CONTROL.with(|f| match f.get() {
+            Some(clip_control) => {
+                clip_control(GL_LOWER_LEFT, GL_ZERO_TO_ONE);
+                true
+            }
+            None => false,
+        });
+    if !zero_to_one {
+        CLIP_CONTROL.with(|f| {
+            if let Some(clip_control) = f.get() {
+                clip_control(GL_LOWER_LEFT, GL_NEGATIVE_ONE_TO_ONE);
+            }
+        });
+    }
+    DEPTH_ZERO_TO_ONE.with(|f| f.set(zero_to_one));
+    DEPTH_REVERSED.with(|f| f.set(reversed));
+    unsafe {
+        glClearDepth(if reversed { 0.0 } else { 1.0 });
+    }
+}
+
+pub fn is_depth_zero_to_one() -> bool {
+    DEPTH_ZERO_TO_ONE.with(|f| f.get())
+}
+
+// What a pass clears its target to before drawing, None leaving that buffer as it is. Depth is
+// given as if it weren't reversed, so 1.0 is the far plane either way. Only the first color
+// attachment is cleared, extra ones like the velocity are up to their owner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClearOptions {
+    pub color: Option<Vec4>,
+    pub depth: Option<f32>,
+    pub stencil: Option<i32>,
+}
+
+impl ClearOptions {
+    // What a pass starting from scratch needs
+    pub fn all(color: Vec4) -> Self {
+        Self {
+            color: Some(color),
+            depth: Some(1.0),
+            stencil: Some(0),
+        }
+    }
+
+    // For overlays drawn over what's already there
+    pub fn none() -> Self {
+        Self {
+            color: None,
+            depth: None,
+            stencil: None,
+        }
+    }
+
+    // Keeps the color, for passes that draw over it with depth testing of their own
+    pub fn depth_stencil() -> Self {
+        Self {
+            depth: Some(1.0),
+            stencil: Some(0),
+            ..Self::none()
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.color.is_none() && self.depth.is_none() && self.stencil.is_none()
+    }
+
+    // Clears the bound framebuffer. The clear values and write masks are left as they were,
+    // except for the depth and stencil masks, which have to be on to clear those buffers.
+    pub fn apply(&self) {
+        unsafe {
+            if let Some(color) = self.color {
+                glClearBufferfv(GL_COLOR, 0, color.as_ptr());
+            }
+            if self.depth.is_some() {
+                glDepthMask(GL_TRUE.0 as u8);
+            }
+            if self.stencil.is_some() {
+                match get_stencil() {
+                    Some(state) => set_stencil(StencilState {
+                        write_mask: 0xFF,
+                        ..state
+                    }),
+                    None => glStencilMask(0xFF),
+                }
+            }
+            let depth = self.depth.map(|depth| {
+                let depth = depth.clamp(0.0, 1.0);
+                if DEPTH_REVERSED.with(|f| f.get()) {
+                    1.0 - depth
+                } else {
+                    depth
+                }
+            });
+            match (depth, self.stencil) {
+                (Some(depth), Some(stencil)) => {
+                    glClearBufferfi(GL_DEPTH_STENCIL, 0, depth, stencil)
+                }
+                (Some(depth), None) => glClearBufferfv(GL_DEPTH, 0, &depth),
+                (None, Some(stencil)) => glClearBufferiv(GL_STENCIL, 0, &stencil),
+                (None, None) => (),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+// Which way the vertices of a front face go around, as seen from the front
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winding {
+    CounterClockwise,
+    Clockwise,
+}
+
+impl Winding {
+    pub fn flipped(self) -> Self {
+        match self {
+            Winding::CounterClockwise => Winding::Clockwise,
+            Winding::Clockwise => Winding::CounterClockwise,
+        }
+    }
+}
+
+thread_local! {
+    // culling as last set through `set_face_culling`, None when unknown
+    static FACE_CULLING: Cell<Option<(CullMode, Winding)>> = Cell::new(None);
+    static MIRRORED_WINDING: Cell<bool> = Cell::new(false);
+}
+
+// Only makes the GL calls for what changed since the last time. Code that touches culling or the
+// front face directly should call `invalidate_face_culling` afterwards.
+pub fn set_face_culling(mode: CullMode, winding: Winding) {
+    let winding = if MIRRORED_WINDING.with(|f| f.get()) {
+        winding.flipped()
+    } else {
+        winding
+    };
+    let previous = FACE_CULLING.with(|f| f.replace(Some((mode, winding))));
+    if previous.map(|(m, _)| m) != Some(mode) {
+        unsafe {
+            match mode {
+                CullMode::None => glDisable(GL_CULL_FACE),
+                CullMode::Back => {
+                    glEnable(GL_CULL_FACE);
+                    glCullFace(GL_BACK);
+                }
+                CullMode::Front => {
+                    glEnable(GL_CULL_FACE);
+                    glCullFace(GL_FRONT);
+                }
+            }
+        }
+    }
+    if previous.map(|(_, w)| w) != Some(winding) {
+        let front_face = match winding {
+            Winding::CounterClockwise => GL_CCW,
+            Winding::Clockwise => GL_CW,
+        };
+        unsafe {
+            glFrontFace(front_face);
+        }
+    }
+}
+
+pub fn invalidate_face_culling() {
+    FACE_CULLING.with(|f| f.set(None));
+}
+
+// Flips the winding of everything drawn until it's turned off again, for reflected cameras
+pub fn set_mirrored_winding(mirrored: bool) {
+    MIRRORED_WINDING.with(|f| f.set(mirrored));
+}
+
+// Everything the stencil test depends on. The same test and ops are used for front and back
+// faces, so stencil users don't care about winding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilState {
+    pub enabled: bool,
+    pub func: GLenum,
+    pub reference: i32,
+    pub read_mask: u32,
+    pub write_mask: u32,
+    pub fail: GLenum,       // when the stencil test fails
+    pub depth_fail: GLenum, // when it passes but the depth test doesn't
+    pub pass: GLenum,
+}
+
+impl StencilState {
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::write(0)
+        }
+    }
+
+    // Marks whatever is drawn with `reference`
+    pub fn write(reference: i32) -> Self {
+        Self {
+            enabled: true,
+            func: GL_ALWAYS,
+            reference,
+            read_mask: 0xFF,
+            write_mask: 0xFF,
+            fail: GL_KEEP,
+            depth_fail: GL_KEEP,
+            pass: GL_REPLACE,
+        }
+    }
+
+    // Only draws where nothing was marked with `reference`, leaving the marks as they are
+    pub fn outside(reference: i32) -> Self {
+        Self {
+            func: GL_NOTEQUAL,
+            write_mask: 0x00,
+            ..Self::write(reference)
+        }
+    }
+}
+
+thread_local! {
+    // stencil state as last set through `set_stencil`, None when unknown
+    static STENCIL: Cell<Option<StencilState>> = Cell::new(None);
+    static STENCIL_STACK: RefCell<Vec<Option<StencilState>>> = RefCell::new(vec![]);
+}
+
+// Only makes the GL calls for what changed since the last time, like `set_face_culling`. Code
+// that touches the stencil state directly should call `invalidate_stencil` afterwards.
+pub fn set_stencil(state: StencilState) {
+    let previous = STENCIL.with(|f| f.replace(Some(state)));
+    unsafe {
+        if previous.map(|p| p.enabled) != Some(state.enabled) {
+            if state.enabled {
+                glEnable(GL_STENCIL_TEST);
+            } else {
+                glDisable(GL_STENCIL_TEST);
+            }
+        }
+        if previous.map(|p| (p.func, p.reference, p.read_mask))
+            != Some((state.func, state.reference, state.read_mask))
+        {
+            glStencilFunc(state.func, state.reference, state.read_mask);
+        }
+        if previous.map(|p| p.write_mask) != Some(state.write_mask) {
+            glStencilMask(state.write_mask);
+        }
+        if previous.map(|p| (p.fail, p.depth_fail, p.pass))
+            != Some((state.fail, state.depth_fail, state.pass))
+        {
+            glStencilOp(state.fail, state.depth_fail, state.pass);
+        }
+    }
+}
+
+pub fn get_stencil() -> Option<StencilState> {
+    STENCIL.with(|f| f.get())
+}
+
+pub fn invalidate_stencil() {
+    STENCIL.with(|f| f.set(None));
+}
+
+// Sets `state` until the matching `pop_stencil`, for passes that need their own stencil setup
+// without knowing what the surrounding code had
+pub fn push_stencil(state: StencilState) {
+    STENCIL_STACK.with(|stack| stack.borrow_mut().push(get_stencil()));
+    set_stencil(state);
+}
+
+pub fn pop_stencil() {
+    match STENCIL_STACK.with(|stack| stack.borrow_mut().pop()) {
+        Some(Some(previous)) => set_stencil(previous),
+        // what was there before wasn't known, so it can't be restored either
+        Some(None) => invalidate_stencil(),
+        None => eprintln!("pop_stencil called without a matching push_stencil"),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PolygonMode {
     Point = GL_POINT.0 as isize,
@@ -97,6 +445,8 @@ pub struct Framebuffer {
     id: u32,
     texture: Texture2DMultisample,
     rbo: Renderbuffer,
+    depth_texture: Option<Texture2DMultisample>,
+    velocity_texture: Option<Texture2DMultisample>,
 }
 
 impl Framebuffer {
@@ -112,16 +462,39 @@ impl Framebuffer {
                 id: fbo,
                 texture,
                 rbo,
+                depth_texture: None,
+                velocity_texture: None,
             })
         } else {
             None
         }
     }
 
+    // Depth and stencil go to a texture instead of the renderbuffer, so post effects can sample it
+    pub fn with_depth_texture() -> Option<Self> {
+        Self::with_attachments(true, false)
+    }
+
+    // `velocity` adds a second color attachment receiving the screen space motion of every pixel
+    pub fn with_attachments(depth_texture: bool, velocity: bool) -> Option<Self> {
+        let mut fbo = Self::new()?;
+        if depth_texture {
+            fbo.depth_texture = Some(Texture2DMultisample::new(SAMPLES));
+        }
+        if velocity {
+            fbo.velocity_texture = Some(Texture2DMultisample::new(SAMPLES));
+        }
+        Some(fbo)
+    }
+
     pub fn get_id(&self) -> u32 {
         self.id
     }
 
+    pub fn get_samples(&self) -> u32 {
+        self.texture.get_samples()
+    }
+
     pub fn check_status() -> GLenum {
         unsafe { glCheckFramebufferStatus(GL_FRAMEBUFFER) }
     }
@@ -133,6 +506,47 @@ impl Framebuffer {
         Self::clear_binding();
     }
 
+    // Attaches whichever depth/stencil storage the framebuffer was created with
+    pub fn setup(&self, window_size: (u32, u32)) {
+        if let Some(velocity) = &self.velocity_texture {
+            self.bind();
+            velocity.create_texture_with_format(window_size, GL_RG16F);
+            unsafe {
+                glFramebufferTexture2D(
+                    GL_FRAMEBUFFER,
+                    GL_COLOR_ATTACHMENT1,
+                    GL_TEXTURE_2D_MULTISAMPLE,
+                    velocity.get_id(),
+                    0,
+                );
+                let draw_buffers = [GL_COLOR_ATTACHMENT0, GL_COLOR_ATTACHMENT1];
+                glDrawBuffers(2, draw_buffers.as_ptr());
+            }
+            Self::clear_binding();
+        }
+        match &self.depth_texture {
+            Some(depth) => {
+                self.bind();
+                self.attach_texture(window_size);
+                depth.create_depth_stencil_texture(window_size);
+                unsafe {
+                    glFramebufferTexture2D(
+                        GL_FRAMEBUFFER,
+                        GL_DEPTH_STENCIL_ATTACHMENT,
+                        GL_TEXTURE_2D_MULTISAMPLE,
+                        depth.get_id(),
+                        0,
+                    );
+                }
+                if Self::check_status() != GL_FRAMEBUFFER_COMPLETE {
+                    panic!("Could not complete framebuffer!")
+                }
+                Self::clear_binding();
+            }
+            None => self.setup_with_renderbuffer(window_size),
+        }
+    }
+
     // If you want to render your whole screen to a texture of a smaller or larger size you need to
     // call glViewport again (before rendering to your framebuffer) with the new dimensions
     // of your texture, otherwise render commands will only fill part of the texture.
@@ -228,6 +642,24 @@ impl Framebuffer {
         &self.texture
     }
 
+    pub fn get_depth_texture(&self) -> Option<&Texture2DMultisample> {
+        self.depth_texture.as_ref()
+    }
+
+    pub fn get_velocity_texture(&self) -> Option<&Texture2DMultisample> {
+        self.velocity_texture.as_ref()
+    }
+
+    // glClear would fill the velocity with the clear color too
+    pub fn clear_velocity(&self) {
+        if self.velocity_texture.is_some() {
+            let zero = [0.0f32; 4];
+            unsafe {
+                glClearBufferfv(GL_COLOR, 1, zero.as_ptr());
+            }
+        }
+    }
+
     pub fn write_to_file(&self, path: &Path, size: (u32, u32)) {
         self.bind();
         self.blit(size);
@@ -260,6 +692,116 @@ impl Drop for Framebuffer {
         unsafe {
             glDeleteFramebuffers(1, &self.id);
         }
+        self.texture.delete();
+        self.rbo.delete();
+        for texture in [&self.depth_texture, &self.velocity_texture]
+            .into_iter()
+            .flatten()
+        {
+            texture.delete();
+        }
+    }
+}
+
+// Single-sample framebuffer whose color attachment is a regular texture, so whatever is rendered
+// into it can be sampled by materials
+#[derive(Debug)]
+pub struct RenderTarget {
+    id: u32,
+    texture: Texture2D,
+    rbo: Renderbuffer,
+    size: (u32, u32),
+}
+
+impl RenderTarget {
+    pub fn new(size: (u32, u32)) -> Option<Self> {
+        let mut fbo = 0;
+        unsafe {
+            glGenFramebuffers(1, &mut fbo);
+        }
+        if fbo == 0 {
+            return None;
+        }
+        let texture = Texture2D::new(TextureType::Attachment);
+        texture.allocate(size);
+        let rbo = Renderbuffer::new()?;
+        let target = Self {
+            id: fbo,
+            texture,
+            rbo,
+            size,
+        };
+
+        target.bind();
+        unsafe {
+            glFramebufferTexture2D(
+                GL_FRAMEBUFFER,
+                GL_COLOR_ATTACHMENT0,
+                GL_TEXTURE_2D,
+                target.texture.get_id(),
+                0,
+            );
+        }
+        target.rbo.bind();
+        Renderbuffer::create_depth_stencil_storage(size);
+        Renderbuffer::clear_binding();
+        unsafe {
+            glFramebufferRenderbuffer(
+                GL_FRAMEBUFFER,
+                GL_DEPTH_STENCIL_ATTACHMENT,
+                GL_RENDERBUFFER,
+                target.rbo.get_id(),
+            );
+        }
+        if Framebuffer::check_status() != GL_FRAMEBUFFER_COMPLETE {
+            panic!("Could not complete render target!")
+        }
+        Framebuffer::clear_binding();
+        Some(target)
+    }
+
+    pub fn bind(&self) {
+        unsafe { glBindFramebuffer(GL_FRAMEBUFFER, self.id) }
+    }
+
+    pub fn get_texture(&self) -> &Texture2D {
+        &self.texture
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    // Averages the samples of `source`'s color attachment into this target. Multisampled blits
+    // can't scale, so `source` must have the same size.
+    pub fn resolve(&self, source: &Framebuffer) {
+        unsafe {
+            glBindFramebuffer(GL_READ_FRAMEBUFFER, source.get_id());
+            glBindFramebuffer(GL_DRAW_FRAMEBUFFER, self.id);
+            glBlitFramebuffer(
+                0,
+                0,
+                self.size.0 as i32,
+                self.size.1 as i32,
+                0,
+                0,
+                self.size.0 as i32,
+                self.size.1 as i32,
+                GL_COLOR_BUFFER_BIT,
+                GL_NEAREST,
+            );
+        }
+        Framebuffer::clear_binding();
+    }
+}
+
+// The texture lives on in the materials that sample it, if any
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            glDeleteFramebuffers(1, &self.id);
+        }
+        self.rbo.delete();
     }
 }
 
@@ -293,6 +835,10 @@ impl Renderbuffer {
         unsafe { glBindRenderbuffer(GL_RENDERBUFFER, 0) }
     }
 
+    pub fn delete(self) {
+        unsafe { glDeleteRenderbuffers(1, &self.id) }
+    }
+
     pub fn create_depth_stencil_storage(window_size: (u32, u32)) {
         unsafe {
             glRenderbufferStorage(
@@ -317,10 +863,39 @@ impl Renderbuffer {
     }
 }
 
-#[derive(Clone, Copy)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct MatrixBlock {
+    model: Mat4,
+    view: Mat4,
+    projection: Mat4,
+    previous_view_projection: Mat4,
+    time: [f32; 4], // engine time, its last step, the drawn object's age and real time
+}
+
+unsafe impl Zeroable for MatrixBlock {}
+unsafe impl Pod for MatrixBlock {}
+
+struct UniformBufferState {
+    current: MatrixBlock,
+    pending: Vec<MatrixBlock>,
+    stride: usize,
+    blocks_per_frame: usize,
+    frame: usize,
+    cursor: usize,  // blocks handed out in this frame
+    written: usize, // blocks already in GPU memory
+    fences: [Option<GLsync>; UBO_FRAMES],
+}
+
+// Ring of per-frame regions holding one `MatrixBlock` per draw. Setters only touch a CPU copy;
+// blocks are queued with `push_block`, written with a single mapped write per `flush` and selected
+// with `glBindBufferRange`. A region is only reused once the fence of the frame that last used it
+// has signaled, so the writes never wait on the GPU.
+#[derive(Clone)]
 pub struct UniformBuffer {
     id: u32,
     binding: u32,
+    state: Rc<RefCell<UniformBufferState>>,
 }
 
 impl UniformBuffer {
@@ -330,7 +905,26 @@ impl UniformBuffer {
             glGenBuffers(1, &mut ubo);
         }
         if ubo != 0 {
-            Some(Self { id: ubo, binding })
+            let mut alignment = 0;
+            unsafe {
+                glGetIntegerv(GL_UNIFORM_BUFFER_OFFSET_ALIGNMENT, &mut alignment);
+            }
+            let alignment = (alignment as usize).max(1);
+            let stride = (size_of::<MatrixBlock>() + alignment - 1) / alignment * alignment;
+            Some(Self {
+                id: ubo,
+                binding,
+                state: Rc::new(RefCell::new(UniformBufferState {
+                    current: MatrixBlock::zeroed(),
+                    pending: vec![],
+                    stride,
+                    blocks_per_frame: 0,
+                    frame: 0,
+                    cursor: 0,
+                    written: 0,
+                    fences: [None; UBO_FRAMES],
+                })),
+            })
         } else {
             None
         }
@@ -348,54 +942,191 @@ impl UniformBuffer {
         unsafe { glBindBuffer(GL_UNIFORM_BUFFER, 0) }
     }
 
-    pub fn allocate(&self, size: isize) {
+    // Room for `blocks` draws in each of the frames in flight
+    pub fn allocate(&self, blocks: usize) {
+        let mut state = self.state.borrow_mut();
+        state.blocks_per_frame = blocks.max(1);
+        state.cursor = 0;
+        state.written = 0;
+        state.pending.clear();
         self.bind();
         unsafe {
-            glBufferData(GL_UNIFORM_BUFFER, size, null(), GL_STATIC_DRAW);
+            glBufferData(
+                GL_UNIFORM_BUFFER,
+                (state.stride * state.blocks_per_frame * UBO_FRAMES) as isize,
+                null(),
+                GL_STREAM_DRAW,
+            );
         }
         Self::clear_binding();
     }
 
+    // Binds the whole first block, for shaders that don't go through `bind_block`
     pub fn bind_base(&self) {
         unsafe {
             glBindBufferBase(GL_UNIFORM_BUFFER, self.binding, self.id);
         }
     }
 
-    pub fn set_model_mat(&self, model: &Mat4) {
-        self.bind();
+    // Moves on to the next region, waiting for the GPU only if it is still three frames behind
+    pub fn begin_frame(&self) {
+        let mut state = self.state.borrow_mut();
+        state.frame = (state.frame + 1) % UBO_FRAMES;
+        let frame = state.frame;
+        if let Some(fence) = state.fences[frame].take() {
+            unsafe {
+                glClientWaitSync(fence, GL_SYNC_FLUSH_COMMANDS_BIT, u64::MAX);
+                glDeleteSync(fence);
+            }
+        }
+        state.cursor = 0;
+        state.written = 0;
+        state.pending.clear();
+    }
+
+    pub fn end_frame(&self) {
+        let mut state = self.state.borrow_mut();
+        let frame = state.frame;
         unsafe {
-            glBufferSubData(
-                GL_UNIFORM_BUFFER,
-                0,
-                core::mem::size_of::<Mat4>().try_into().unwrap(),
-                model.as_ptr().cast(),
-            );
+            state.fences[frame] = Some(glFenceSync(
+                GL_SYNC_GPU_COMMANDS_COMPLETE,
+                GLbitfield(0),
+            ));
         }
-        Self::clear_binding();
+    }
+
+    pub fn set_model_mat(&self, model: &Mat4) {
+        self.state.borrow_mut().current.model = *model;
     }
     pub fn set_view_mat(&self, view: &Mat4) {
+        self.state.borrow_mut().current.view = *view;
+    }
+    pub fn set_projection_mat(&self, proj: &Mat4) {
+        self.state.borrow_mut().current.projection = *proj;
+    }
+    pub fn set_previous_view_projection_mat(&self, view_proj: &Mat4) {
+        self.state.borrow_mut().current.previous_view_projection = *view_proj;
+    }
+    // Once per frame, before anything is pushed
+    pub fn set_frame_time(&self, time: f32, delta: f32, real_time: f32) {
+        let mut state = self.state.borrow_mut();
+        state.current.time = [time, delta, 0.0, real_time];
+    }
+    // Per object, so shaders can animate from when it appeared
+    pub fn set_spawn_time(&self, spawn_time: f32) {
+        let mut state = self.state.borrow_mut();
+        state.current.time[2] = (state.current.time[0] - spawn_time).max(0.0);
+    }
+    // For draws that aren't of an object
+    pub fn clear_spawn_time(&self) {
+        self.state.borrow_mut().current.time[2] = 0.0;
+    }
+
+    // Queues the current matrices and returns the block to bind when drawing with them
+    pub fn push_block(&self) -> usize {
+        let mut state = self.state.borrow_mut();
+        if state.cursor == state.blocks_per_frame {
+            drop(state);
+            self.grow();
+            state = self.state.borrow_mut();
+        }
+        let block = state.current;
+        state.pending.push(block);
+        state.cursor += 1;
+        state.cursor - 1
+    }
+
+    // Writes every queued block in one go
+    pub fn flush(&self) {
+        let mut state = self.state.borrow_mut();
+        if state.pending.is_empty() {
+            return;
+        }
+        let base = (state.frame * state.blocks_per_frame + state.written) * state.stride;
+        let length = state.pending.len() * state.stride;
         self.bind();
+        let mapped = map_uniform_range(
+            base,
+            length,
+            GL_MAP_WRITE_BIT | GL_MAP_INVALIDATE_RANGE_BIT | GL_MAP_UNSYNCHRONIZED_BIT,
+        ) as *mut u8;
+        if mapped.is_null() {
+            for (i, block) in state.pending.iter().enumerate() {
+                unsafe {
+                    glBufferSubData(
+                        GL_UNIFORM_BUFFER,
+                        (base + i * state.stride) as isize,
+                        size_of::<MatrixBlock>() as isize,
+                        (block as *const MatrixBlock).cast(),
+                    );
+                }
+            }
+        } else {
+            for (i, block) in state.pending.iter().enumerate() {
+                unsafe {
+                    let dst = mapped.add(i * state.stride) as *mut MatrixBlock;
+                    dst.write_unaligned(*block);
+                }
+            }
+            unsafe {
+                glUnmapBuffer(GL_UNIFORM_BUFFER);
+            }
+        }
+        Self::clear_binding();
+        state.written += state.pending.len();
+        state.pending.clear();
+    }
+
+    pub fn bind_block(&self, block: usize) {
+        let state = self.state.borrow();
+        let offset = (state.frame * state.blocks_per_frame + block) * state.stride;
         unsafe {
-            glBufferSubData(
+            glBindBufferRange(
                 GL_UNIFORM_BUFFER,
-                64,
-                core::mem::size_of::<Mat4>().try_into().unwrap(),
-                view.as_ptr().cast(),
+                self.binding,
+                self.id,
+                offset as isize,
+                size_of::<MatrixBlock>() as isize,
             );
         }
-        Self::clear_binding();
     }
-    pub fn set_projection_mat(&self, proj: &Mat4) {
+
+    // Shorthand for a single draw with the current matrices
+    pub fn commit(&self) {
+        let block = self.push_block();
+        self.flush();
+        self.bind_block(block);
+    }
+
+    // Out of room: orphan the storage with twice the blocks per frame. Blocks that were already
+    // written belong to draws that were already issued, and those keep reading the old storage.
+    fn grow(&self) {
+        let mut state = self.state.borrow_mut();
+        eprintln!(
+            "Uniform buffer full at {} blocks per frame, growing",
+            state.blocks_per_frame
+        );
+        state.blocks_per_frame *= 2;
         self.bind();
         unsafe {
-            glBufferSubData(
+            glBufferData(
                 GL_UNIFORM_BUFFER,
-                128,
-                core::mem::size_of::<Mat4>().try_into().unwrap(),
-                proj.as_ptr().cast(),
+                (state.stride * state.blocks_per_frame * UBO_FRAMES) as isize,
+                null(),
+                GL_STREAM_DRAW,
             );
         }
         Self::clear_binding();
     }
 }
+
+fn map_uniform_range(offset: usize, length: usize, access: GLbitfield) -> *mut c_void {
+    unsafe {
+        glMapBufferRange(
+            GL_UNIFORM_BUFFER,
+            offset.try_into().unwrap(),
+            length.try_into().unwrap(),
+            access,
+        )
+    }
+}
@@ -0,0 +1,104 @@
+use std::cell::Cell;
+use std::ffi::CString;
+
+use beryllium::GlWindow;
+use renderdoc::{RenderDoc, V110};
+
+const GL_DEBUG_SOURCE_APPLICATION: u32 = 0x824A;
+
+// Debug groups are core since 4.3 (or KHR_debug), which the loader doesn't cover, so they're
+// fetched by hand and silently skipped if the driver doesn't have them
+type PushDebugGroup = extern "system" fn(u32, u32, i32, *const i8);
+type PopDebugGroup = extern "system" fn();
+
+thread_local! {
+    static PUSH_GROUP: Cell<Option<PushDebugGroup>> = Cell::new(None);
+    static POP_GROUP: Cell<Option<PopDebugGroup>> = Cell::new(None);
+}
+
+pub fn load(win: &GlWindow) {
+    unsafe {
+        let push = win.get_proc_address(b"glPushDebugGroup\0".as_ptr() as *const i8);
+        let pop = win.get_proc_address(b"glPopDebugGroup\0".as_ptr() as *const i8);
+        if !push.is_null() && !pop.is_null() {
+            PUSH_GROUP.with(|f| f.set(Some(std::mem::transmute::<_, PushDebugGroup>(push))));
+            POP_GROUP.with(|f| f.set(Some(std::mem::transmute::<_, PopDebugGroup>(pop))));
+        }
+    }
+}
+
+// Names the GL calls made while it's alive, so they show up grouped in RenderDoc and friends
+pub struct DebugGroup {
+    active: bool,
+}
+
+impl DebugGroup {
+    pub fn new(name: &str) -> Self {
+        let active = PUSH_GROUP.with(|f| match f.get() {
+            Some(push) => {
+                let name = CString::new(name).unwrap_or_default();
+                push(GL_DEBUG_SOURCE_APPLICATION, 0, -1, name.as_ptr());
+                true
+            }
+            None => false,
+        });
+        Self { active }
+    }
+}
+
+impl Drop for DebugGroup {
+    fn drop(&mut self) {
+        if self.active {
+            POP_GROUP.with(|f| {
+                if let Some(pop) = f.get() {
+                    pop();
+                }
+            });
+        }
+    }
+}
+
+// RenderDoc in-application API. Only available when the program was launched from (or injected
+// by) RenderDoc; otherwise capture requests are ignored.
+pub struct FrameCapture {
+    renderdoc: Option<RenderDoc<V110>>,
+    capturing: bool,
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        let renderdoc = RenderDoc::<V110>::new().ok();
+        if let Some(rd) = &renderdoc {
+            let (major, minor, patch) = rd.get_api_version();
+            println!("RenderDoc API {}.{}.{} attached", major, minor, patch);
+        }
+        Self {
+            renderdoc,
+            capturing: false,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.renderdoc.is_some()
+    }
+
+    pub fn begin_frame(&mut self, requested: bool) {
+        if !requested {
+            return;
+        }
+        match &mut self.renderdoc {
+            Some(rd) => {
+                rd.start_frame_capture(std::ptr::null(), std::ptr::null());
+                self.capturing = true;
+            }
+            None => eprintln!("Frame capture requested, but RenderDoc isn't attached"),
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        if let (true, Some(rd)) = (self.capturing, &mut self.renderdoc) {
+            rd.end_frame_capture(std::ptr::null(), std::ptr::null());
+            self.capturing = false;
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{cell::RefCell, rc::Rc};
+
+use beryllium::Keycode;
+use nalgebra_glm::*;
+
+use crate::controls::{Controller, MouseButton, SignalType, Slot};
+use crate::events::{EngineEvent, EventBus};
+use crate::lines::DebugLines;
+use crate::scene::{ObjectHandle, SceneObject};
+use crate::spatial::Aabb;
+
+const GRID_STEPS: [f32; 5] = [0.1, 0.25, 0.5, 1.0, 2.0];
+const DEFAULT_GRID_STEP: usize = 3;
+const GRID_CELLS: u32 = 10;
+const GRID_COLOR: Vec3 = Vec3::new(0.4, 0.4, 0.4);
+const MEASURE_COLOR: Vec3 = Vec3::new(1.0, 0.8, 0.0);
+
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    // Ray from `eye` through the window pixel `cursor` of the view rendered with `view_projection`
+    pub fn from_cursor(
+        view_projection: &Mat4,
+        eye: &Vec3,
+        cursor: (i32, i32),
+        viewport: (u32, u32),
+    ) -> Self {
+        let x = 2.0 * (cursor.0 as f32 + 0.5) / viewport.0 as f32 - 1.0;
+        let y = 1.0 - 2.0 * (cursor.1 as f32 + 0.5) / viewport.1 as f32;
+        // any depth inside the clip volume works, whatever the depth convention is
+        let point = inverse(view_projection) * vec4(x, y, 0.5, 1.0);
+        Self {
+            origin: *eye,
+            dir: normalize(&(point.xyz() / point.w - eye)),
+        }
+    }
+
+    pub fn intersect_plane(&self, point: &Vec3, normal: &Vec3) -> Option<Vec3> {
+        let denominator = dot(normal, &self.dir);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let t = dot(normal, &(point - self.origin)) / denominator;
+        (t >= 0.0).then_some(self.origin + self.dir * t)
+    }
+
+    // How far along the ray it enters `aabb`, 0 when it starts inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut near = 0.0_f32;
+        let mut far = f32::INFINITY;
+        for i in 0..3 {
+            let t0 = (aabb.min[i] - self.origin[i]) / self.dir[i];
+            let t1 = (aabb.max[i] - self.origin[i]) / self.dir[i];
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+// The candidate whose box `ray` goes through first
+pub fn pick<T>(ray: &Ray, candidates: impl IntoIterator<Item = (T, Aabb)>) -> Option<T> {
+    candidates
+        .into_iter()
+        .filter_map(|(candidate, aabb)| Some((ray.intersect_aabb(&aabb)?, candidate)))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| candidate)
+}
+
+pub fn snap_to_grid(point: &Vec3, step: f32) -> Vec3 {
+    round(&(point / step)) * step
+}
+
+// Layout helper: clicks are picked against the horizontal plane at `plane_height`. Left clicks
+// measure the distance between the last two points, right clicks ask for an object to be placed.
+// Middle clicks select the object under the cursor, or clear the selection when there's none.
+pub struct Editor {
+    pub active: bool,
+    pub snapping: bool,
+    pub grid_step: f32,
+    pub plane_height: f32,
+    clicks: Vec<(MouseButton, i32, i32)>,
+    measure: Vec<Vec3>,
+    selection: Option<ObjectHandle>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            snapping: true,
+            grid_step: GRID_STEPS[DEFAULT_GRID_STEP],
+            plane_height: 0.0,
+            clicks: vec![],
+            measure: vec![],
+            selection: None,
+        }
+    }
+
+    pub fn get_selection(&self) -> Option<ObjectHandle> {
+        self.selection
+    }
+
+    pub fn select(&mut self, selection: Option<ObjectHandle>, events: &EventBus) {
+        if selection != self.selection {
+            self.selection = selection;
+            events.publish(EngineEvent::SelectionChanged(selection));
+        }
+    }
+
+    pub fn snap(&self, point: &Vec3) -> Vec3 {
+        if self.snapping {
+            snap_to_grid(point, self.grid_step)
+        } else {
+            *point
+        }
+    }
+
+    pub fn get_measurement(&self) -> Option<f32> {
+        match self.measure.as_slice() {
+            [a, b] => Some(distance(a, b)),
+            _ => None,
+        }
+    }
+
+    // Resolves the clicks gathered since the last call and returns where objects should be placed.
+    // The viewport is in pixels, while clicks come in window points and are scaled by
+    // `display_scale` to match.
+    pub fn update(
+        &mut self,
+        view_projection: &Mat4,
+        eye: &Vec3,
+        viewport: (u32, u32),
+        display_scale: f32,
+        objects: &[SceneObject],
+        events: &EventBus,
+    ) -> Vec<Vec3> {
+        let mut placements = vec![];
+        let plane_point = vec3(0.0, self.plane_height, 0.0);
+        for (button, x, y) in std::mem::take(&mut self.clicks) {
+            let pixel = (
+                (x as f32 * display_scale) as i32,
+                (y as f32 * display_scale) as i32,
+            );
+            let ray = Ray::from_cursor(view_projection, eye, pixel, viewport);
+            if button == MouseButton::Middle {
+                let instances = objects.iter().flat_map(|object| {
+                    let handle = object.get_handle();
+                    object
+                        .get_instance_bounds()
+                        .into_iter()
+                        .map(move |aabb| (handle, aabb))
+                });
+                self.select(pick(&ray, instances), events);
+                continue;
+            }
+            let point = match ray.intersect_plane(&plane_point, &Vec3::y()) {
+                Some(point) => self.snap(&point),
+                None => continue,
+            };
+            match button {
+                MouseButton::Left => {
+                    if self.measure.len() == 2 {
+                        self.measure.clear();
+                    }
+                    self.measure.push(point);
+                    if let Some(d) = self.get_measurement() {
+                        println!("Distance: {:.3}", d);
+                    }
+                }
+                MouseButton::Right => placements.push(point),
+                _ => (),
+            }
+        }
+        placements
+    }
+
+    pub fn draw(&self, lines: &mut DebugLines, eye: &Vec3) {
+        lines.clear();
+        if !self.active {
+            return;
+        }
+        // the grid follows the camera around, in whole cells so it doesn't swim
+        let center = snap_to_grid(&vec3(eye.x, 0.0, eye.z), self.grid_step);
+        lines.grid(
+            &vec3(center.x, self.plane_height, center.z),
+            self.grid_step,
+            GRID_CELLS,
+            &GRID_COLOR,
+        );
+        for point in &self.measure {
+            lines.cross(point, self.grid_step / 2.0, &MEASURE_COLOR);
+        }
+        if let [a, b] = self.measure.as_slice() {
+            lines.line(a, b, &MEASURE_COLOR);
+        }
+    }
+}
+
+pub struct EditorController {
+    active: bool,
+    snapping: bool,
+    grid_step: usize,
+    clicks: Vec<(MouseButton, i32, i32)>,
+}
+
+impl EditorController {
+    pub fn new() -> Rc<RefCell<EditorController>> {
+        Rc::new(RefCell::new(Self {
+            active: false,
+            snapping: true,
+            grid_step: DEFAULT_GRID_STEP,
+            clicks: vec![],
+        }))
+    }
+    pub fn on_key_pressed(&mut self, keycode: Keycode) {
+        match keycode {
+            Keycode::TAB => self.active = !self.active,
+            Keycode::G => self.snapping = !self.snapping,
+            Keycode::LEFTBRACKET => self.grid_step = self.grid_step.saturating_sub(1),
+            Keycode::RIGHTBRACKET => {
+                self.grid_step = (self.grid_step + 1).min(GRID_STEPS.len() - 1)
+            }
+            _ => (),
+        }
+    }
+    pub fn on_mouse_clicked(&mut self, button: MouseButton, x: i32, y: i32) {
+        if self.active {
+            self.clicks.push((button, x, y));
+        }
+    }
+}
+
+impl Slot for EditorController {
+    fn on_signal(&mut self, signal: SignalType) {
+        match signal {
+            SignalType::KeyPressed(key) => self.on_key_pressed(key),
+            SignalType::MouseClicked(button, x, y) => self.on_mouse_clicked(button, x, y),
+            _ => (),
+        }
+    }
+}
+
+impl<'a> Controller<'a, Editor, EditorController> for Rc<RefCell<EditorController>> {
+    fn update_control_parameters(&self, update: &'a mut (dyn FnMut(&mut EditorController))) {
+        update(&mut (**self).borrow_mut());
+    }
+    fn process_signals(&'a self, obj: &mut Editor) {
+        let mut self_obj = (**self).borrow_mut();
+        obj.active = self_obj.active;
+        obj.snapping = self_obj.snapping;
+        obj.grid_step = GRID_STEPS[self_obj.grid_step];
+        obj.clicks.append(&mut self_obj.clicks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    #[test]
+    fn snapping_rounds_to_nearest_step() {
+        let snapped = snap_to_grid(&vec3(0.74, -1.3, 2.1), 0.5);
+        assert!(
+            distance(&snapped, &vec3(0.5, -1.5, 2.0)) < EPSILON,
+            "{snapped}"
+        );
+    }
+
+    #[test]
+    fn cursor_ray_hits_the_ground_under_the_center() {
+        let eye = vec3(0.0, 2.0, 0.0);
+        let view = look_at(&eye, &vec3(0.0, 0.0, 0.0), &vec3(0.0, 0.0, -1.0));
+        let projection = perspective(1.0, 45.0_f32.to_radians(), 0.1, 100.0);
+        let ray = Ray::from_cursor(&(projection * view), &eye, (299, 299), (600, 600));
+        let hit = ray
+            .intersect_plane(&vec3(0.0, 0.0, 0.0), &Vec3::y())
+            .unwrap();
+        assert!(distance(&hit, &vec3(0.0, 0.0, 0.0)) < 1e-2, "{hit}");
+    }
+
+    #[test]
+    fn clicks_are_scaled_to_pixels() {
+        let eye = vec3(0.0, 2.0, 0.0);
+        let view = look_at(&eye, &vec3(0.0, 0.0, 0.0), &vec3(0.0, 0.0, -1.0));
+        let view_projection = perspective(1.0, 45.0_f32.to_radians(), 0.1, 100.0) * view;
+        let mut editor = Editor::new();
+        editor.snapping = false;
+
+        let events = EventBus::new();
+
+        editor.clicks.push((MouseButton::Right, 100, 450));
+        let regular = editor.update(&view_projection, &eye, (600, 600), 1.0, &[], &events);
+        // the same spot on a display with twice the pixels per point
+        editor.clicks.push((MouseButton::Right, 100, 450));
+        let high_dpi = editor.update(&view_projection, &eye, (1200, 1200), 2.0, &[], &events);
+        assert!(
+            distance(&regular[0], &high_dpi[0]) < 1e-2,
+            "{} {}",
+            regular[0],
+            high_dpi[0]
+        );
+    }
+
+    #[test]
+    fn rays_pointing_away_miss() {
+        let ray = Ray {
+            origin: vec3(0.0, 1.0, 0.0),
+            dir: vec3(0.0, 1.0, 0.0),
+        };
+        assert!(ray
+            .intersect_plane(&vec3(0.0, 0.0, 0.0), &Vec3::y())
+            .is_none());
+    }
+
+    #[test]
+    fn picks_the_nearest_box_along_the_ray() {
+        let ray = Ray {
+            origin: vec3(0.0, 1.0, -5.0),
+            dir: vec3(0.0, 0.0, 1.0),
+        };
+        let near = Aabb::new(vec3(-1.0, 0.0, -1.0), vec3(1.0, 2.0, 1.0));
+        let far = Aabb::new(vec3(-1.0, 0.0, 4.0), vec3(1.0, 2.0, 6.0));
+        let aside = Aabb::new(vec3(3.0, 0.0, -4.0), vec3(4.0, 2.0, -3.0));
+        assert_eq!(pick(&ray, [(1, far), (2, near), (3, aside)]), Some(2));
+        assert_eq!(pick(&ray, [(3, aside)]), None);
+        // starting inside a box hits it right away
+        assert_eq!(
+            ray.intersect_aabb(&Aabb::new(vec3(-1.0, 0.0, -6.0), vec3(1.0, 2.0, -4.0))),
+            Some(0.0)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    rc::{Rc, Weak},
+};
+
+use crate::scene::ObjectHandle;
+
+// Things that happened in the engine, as opposed to the raw input carried by `SignalType`
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineEvent {
+    ObjectSpawned(ObjectHandle),
+    ObjectDespawned(ObjectHandle),
+    WindowResized(u32, u32), // drawable size, in pixels
+    AssetReloaded(PathBuf),  // read again, like the scene file and config on a scene reload
+    SelectionChanged(Option<ObjectHandle>),
+}
+
+pub trait Subscriber {
+    fn on_event(&mut self, event: &EngineEvent);
+}
+
+// Events are queued when published and delivered together by `dispatch`, once per frame, so
+// publishers never run subscriber code in the middle of their own work
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RefCell<Vec<Weak<RefCell<dyn Subscriber>>>>,
+    queue: RefCell<Vec<EngineEvent>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, subscriber: Weak<RefCell<dyn Subscriber>>) {
+        self.subscribers.borrow_mut().push(subscriber);
+    }
+
+    pub fn publish(&self, event: EngineEvent) {
+        self.queue.borrow_mut().push(event);
+    }
+
+    pub fn dispatch(&self) {
+        // events published by subscribers go out in the same dispatch
+        loop {
+            let events = std::mem::take(&mut *self.queue.borrow_mut());
+            if events.is_empty() {
+                break;
+            }
+            let subscribers: Vec<Rc<RefCell<dyn Subscriber>>> = {
+                let mut subscribers = self.subscribers.borrow_mut();
+                subscribers.retain(|s| s.strong_count() > 0);
+                subscribers.iter().filter_map(Weak::upgrade).collect()
+            };
+            for event in &events {
+                for subscriber in &subscribers {
+                    (**subscriber).borrow_mut().on_event(event);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<EngineEvent>,
+    }
+
+    impl Subscriber for Recorder {
+        fn on_event(&mut self, event: &EngineEvent) {
+            self.events.push(event.clone());
+        }
+    }
+
+    #[test]
+    fn events_wait_for_dispatch() {
+        let bus = EventBus::new();
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let weak: Weak<RefCell<dyn Subscriber>> = Rc::downgrade(&recorder) as _;
+        bus.subscribe(weak);
+
+        bus.publish(EngineEvent::WindowResized(800, 600));
+        assert!(recorder.borrow().events.is_empty());
+        bus.dispatch();
+        assert_eq!(
+            recorder.borrow().events,
+            vec![EngineEvent::WindowResized(800, 600)]
+        );
+    }
+
+    #[test]
+    fn dropped_subscribers_are_skipped() {
+        let bus = EventBus::new();
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let weak: Weak<RefCell<dyn Subscriber>> = Rc::downgrade(&recorder) as _;
+        bus.subscribe(weak);
+        drop(recorder);
+
+        bus.publish(EngineEvent::SelectionChanged(None));
+        bus.dispatch();
+        assert!(bus.subscribers.borrow().is_empty());
+    }
+}
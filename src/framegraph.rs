@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::Path;
+
+// A target passes draw into or sample from
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameResource {
+    pub name: String,
+    pub size: (u32, u32),
+    pub format: String,
+    pub samples: u32, // 1 when it isn't multisampled
+}
+
+impl FrameResource {
+    fn describe(&self) -> String {
+        let samples = if self.samples > 1 {
+            format!(" x{}", self.samples)
+        } else {
+            String::new()
+        };
+        format!(
+            "{}\\n{}x{} {}{}",
+            self.name, self.size.0, self.size.1, self.format, samples
+        )
+    }
+}
+
+// Resources by index and the version of them a pass sees
+struct FramePass {
+    name: String,
+    reads: Vec<(usize, u32)>,
+    writes: Vec<(usize, u32)>,
+}
+
+// The passes of a frame and the targets they go through, in the order they run. Every write makes
+// a new version of its target, so a pass only depends on the last one that wrote what it reads
+// and the graph has no cycles even when a target is reused.
+pub struct FrameGraph {
+    resources: Vec<FrameResource>,
+    versions: Vec<u32>, // 0 until something writes the resource
+    passes: Vec<FramePass>,
+}
+
+impl FrameGraph {
+    pub fn new() -> Self {
+        Self {
+            resources: vec![],
+            versions: vec![],
+            passes: vec![],
+        }
+    }
+
+    pub fn add_resource(&mut self, name: &str, size: (u32, u32), format: &str, samples: u32) {
+        if self.find(name).is_ok() {
+            return;
+        }
+        self.resources.push(FrameResource {
+            name: name.to_string(),
+            size,
+            format: format.to_string(),
+            samples,
+        });
+        self.versions.push(0);
+    }
+
+    fn find(&self, name: &str) -> Result<usize, String> {
+        self.resources
+            .iter()
+            .position(|resource| resource.name == name)
+            .ok_or(format!("No resource called {} in the frame graph", name))
+    }
+
+    pub fn add_pass(&mut self, name: &str, reads: &[&str], writes: &[&str]) -> Result<(), String> {
+        let mut pass = FramePass {
+            name: name.to_string(),
+            reads: vec![],
+            writes: vec![],
+        };
+        for read in reads {
+            let index = self.find(read)?;
+            pass.reads.push((index, self.versions[index]));
+        }
+        for write in writes {
+            let index = self.find(write)?;
+            self.versions[index] += 1;
+            pass.writes.push((index, self.versions[index]));
+        }
+        self.passes.push(pass);
+        Ok(())
+    }
+
+    pub fn get_resources(&self) -> &[FrameResource] {
+        &self.resources
+    }
+
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name.as_str()).collect()
+    }
+
+    // GraphViz source, passes as boxes and resource versions as ellipses
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph frame {\n    rankdir=LR;\n");
+        out += "    node [fontname=\"monospace\"];\n";
+        let mut used: Vec<(usize, u32)> = vec![];
+        for pass in &self.passes {
+            for version in pass.reads.iter().chain(&pass.writes) {
+                if !used.contains(version) {
+                    used.push(*version);
+                }
+            }
+        }
+        for (index, version) in &used {
+            let resource = &self.resources[*index];
+            let label = if *version > 1 {
+                format!("{} (v{})", resource.describe(), version)
+            } else {
+                resource.describe()
+            };
+            out += &format!(
+                "    r{}_{} [shape=ellipse, label=\"{}\"];\n",
+                index, version, label
+            );
+        }
+        for (i, pass) in self.passes.iter().enumerate() {
+            out += &format!(
+                "    p{} [shape=box, style=filled, fillcolor=lightgrey, label=\"{}. {}\"];\n",
+                i,
+                i + 1,
+                pass.name
+            );
+            for (index, version) in &pass.reads {
+                out += &format!("    r{}_{} -> p{};\n", index, version, i);
+            }
+            for (index, version) in &pass.writes {
+                out += &format!("    p{} -> r{}_{};\n", i, index, version);
+            }
+        }
+        out += "}\n";
+        out
+    }
+
+    pub fn export(&self, path: &Path) -> Result<(), String> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)
+                .map_err(|e| format!("Couldn't create {}: {}", directory.display(), e))?;
+        }
+        fs::write(path, self.to_dot())
+            .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_targets_get_a_version_per_write() {
+        let mut graph = FrameGraph::new();
+        graph.add_resource("Color", (600, 600), "RGB8", 16);
+        graph.add_resource("Resolved", (600, 600), "RGBA8", 1);
+        graph.add_pass("Outgoing", &[], &["Color"]).unwrap();
+        graph.add_pass("Scene", &[], &["Color"]).unwrap();
+        graph
+            .add_pass("Resolve", &["Color"], &["Resolved"])
+            .unwrap();
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("r0_1 [shape=ellipse, label=\"Color\\n600x600 RGB8 x16\"]"));
+        assert!(dot.contains("(v2)"));
+        // the resolve waits on the scene, not on the outgoing scene
+        assert!(dot.contains("r0_2 -> p2;"));
+        assert!(!dot.contains("r0_1 -> p2;"));
+        assert!(dot.contains("p2 -> r1_1;"));
+        assert_eq!(graph.pass_names(), vec!["Outgoing", "Scene", "Resolve"]);
+    }
+
+    #[test]
+    fn unknown_resources_are_rejected() {
+        let mut graph = FrameGraph::new();
+        graph.add_resource("Window", (600, 600), "default", 1);
+        graph.add_resource("Window", (1, 1), "RGBA8", 1);
+        assert_eq!(graph.get_resources().len(), 1);
+        assert_eq!(graph.get_resources()[0].size, (600, 600));
+        assert!(graph.add_pass("Post", &["Resolved"], &["Window"]).is_err());
+    }
+}
@@ -1,6 +1,7 @@
-use std::fs;
 use std::path::Path;
 
+use crate::vfs;
+
 pub fn read_from_file(path: &Path) -> String {
-    fs::read_to_string(path).expect(&format!("Unable to read file {}", path.display())[..])
+    vfs::read_to_string(path).unwrap_or_else(|e| panic!("{}", e))
 }
@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+
+use gl33::gl_enumerations::*;
+use gl33::global_loader::*;
+
+use crate::camera::Camera;
+use crate::data::UniformBuffer;
+use crate::shaders::ShaderProgram;
+
+// Points in a frame where custom drawing can be injected. Scene hooks only run for the main view,
+// not for the mirror, cubemap captures or thumbnails drawn from the same scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassHook {
+    BeforeOpaque, // matrices are uploaded, but neither the skybox nor the objects are drawn yet
+    AfterOpaque,  // scene objects are drawn, debug lines aren't yet
+    BeforePost,   // the scene is resolved and about to be post-processed onto the window
+}
+
+// What's bound when a hook runs. Hooks may change GL state, but should leave the shader, the
+// framebuffer and the UBO block binding as they found them.
+pub struct PassContext<'c> {
+    pub hook: PassHook,
+    pub shader: ShaderProgram,
+    pub ubo: &'c UniformBuffer,
+    pub camera: Option<&'c Camera>, // only while drawing a scene
+    pub framebuffer: u32,
+}
+
+impl<'c> PassContext<'c> {
+    // Picks up whichever framebuffer is currently bound for drawing
+    pub fn new(
+        hook: PassHook,
+        shader: ShaderProgram,
+        ubo: &'c UniformBuffer,
+        camera: Option<&'c Camera>,
+    ) -> Self {
+        let mut framebuffer = 0;
+        unsafe {
+            glGetIntegerv(GL_DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+        }
+        Self {
+            hook,
+            shader,
+            ubo,
+            camera,
+            framebuffer: framebuffer as u32,
+        }
+    }
+}
+
+type HookCallback = Box<dyn FnMut(&PassContext)>;
+
+// Callbacks registered per hook, shared by the scenes and screens that should run them
+#[derive(Default)]
+pub struct PassHooks {
+    callbacks: RefCell<Vec<(PassHook, HookCallback)>>,
+}
+
+impl PassHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Callbacks of the same hook run in registration order
+    pub fn register<F: FnMut(&PassContext) + 'static>(&self, hook: PassHook, callback: F) {
+        self.callbacks.borrow_mut().push((hook, Box::new(callback)));
+    }
+
+    pub fn has(&self, hook: PassHook) -> bool {
+        self.callbacks.borrow().iter().any(|(h, _)| *h == hook)
+    }
+
+    // The callbacks are taken out while they run, so they can register others. Those only run
+    // from the next time on.
+    pub fn run(&self, context: &PassContext) {
+        let mut callbacks = std::mem::take(&mut *self.callbacks.borrow_mut());
+        for (hook, callback) in callbacks.iter_mut() {
+            if *hook == context.hook {
+                callback(context);
+            }
+        }
+        let mut registered = self.callbacks.borrow_mut();
+        callbacks.append(&mut registered);
+        *registered = callbacks;
+    }
+}
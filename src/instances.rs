@@ -0,0 +1,777 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytemuck::{Pod, Zeroable};
+use nalgebra::{Quaternion, Unit, UnitQuaternion};
+use nalgebra_glm::*;
+use rayon::prelude::*;
+
+use crate::spatial::{compose, decompose, Aabb, Frustum, Spatial};
+use crate::stats::{CullingStats, MemoryUsage};
+
+// Layout actually uploaded to the instance buffer, generated from the TRS form of an `Instance`.
+// Only rotation, position and a uniform scale go through, along with the ones from the previous
+// upload for motion vectors, and the vertex shader builds the matrices from them. With the draw
+// distance that's 72 bytes an instance instead of two full matrices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct InstanceData {
+    pub rotation: Vec4,       // quaternion as (x, y, z, w)
+    pub position_scale: Vec4, // position, then the scale
+    pub previous_rotation: Vec4,
+    pub previous_position_scale: Vec4,
+    pub draw_distance: Vec2,
+}
+
+unsafe impl Zeroable for InstanceData {}
+unsafe impl Pod for InstanceData {}
+
+impl InstanceData {
+    // What the vertex shader reconstructs
+    pub fn model(&self) -> Mat4 {
+        let rotation = UnitQuaternion::new_normalize(Quaternion::from(self.rotation));
+        let scale = self.position_scale.w;
+        compose(
+            &self.position_scale.xyz(),
+            &rotation,
+            &vec3(scale, scale, scale),
+        )
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.rotation != self.previous_rotation
+            || self.position_scale != self.previous_position_scale
+    }
+}
+
+// Instances only scale uniformly, since that's all the instance data has room for. Non-uniform
+// factors are collapsed to their largest component here, so what's culled and picked on the CPU is
+// what the GPU draws.
+#[inline(always)]
+fn uniform_scale(factors: &Vec3) -> f32 {
+    factors.abs().max()
+}
+
+#[derive(Clone, Copy)]
+pub struct Instance {
+    pub position: Vec3,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: f32,
+    // Farther than x from the camera it isn't drawn, and it dithers out over the last y units
+    // before that. Drawn at any distance while x is 0.
+    pub draw_distance: Vec2,
+    normal: Mat3,
+}
+
+impl Instance {
+    pub fn new() -> Self {
+        Instance {
+            position: Vec3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: 1.0,
+            draw_distance: Vec2::zeros(),
+            normal: Mat3::identity(),
+        }
+    }
+
+    // For a TRS transform the inverse transpose of the upper 3x3 is just R * S^-1
+    #[inline(always)]
+    fn normal_matrix(&self) -> Mat3 {
+        self.rotation.to_rotation_matrix().into_inner() / self.scale
+    }
+
+    #[inline(always)]
+    fn packed(&self) -> (Vec4, Vec4) {
+        (self.rotation.coords, self.position.push(self.scale))
+    }
+
+    // Still instance, i.e. no motion since the last upload
+    #[inline(always)]
+    pub fn to_data(&self) -> InstanceData {
+        self.to_moving_data(self)
+    }
+
+    #[inline(always)]
+    pub fn to_moving_data(&self, previous: &Instance) -> InstanceData {
+        let (rotation, position_scale) = self.packed();
+        let (previous_rotation, previous_position_scale) = previous.packed();
+        InstanceData {
+            rotation,
+            position_scale,
+            previous_rotation,
+            previous_position_scale,
+            draw_distance: self.draw_distance,
+        }
+    }
+}
+
+impl Spatial for Instance {
+    fn get_model(&self) -> Mat4 {
+        compose(
+            &self.position,
+            &self.rotation,
+            &vec3(self.scale, self.scale, self.scale),
+        )
+    }
+    fn get_normal(&mut self) -> &Mat3 {
+        self.normal = self.normal_matrix();
+        &self.normal
+    }
+    fn set_model(&mut self, model: &Mat4) {
+        let scale;
+        (self.position, self.rotation, scale) = decompose(model);
+        self.scale = uniform_scale(&scale);
+    }
+    #[inline(always)]
+    fn rotate(&mut self, angle: f32, axis: &Vec3) {
+        self.rotate_quat(&UnitQuaternion::from_axis_angle(&Unit::new_normalize(*axis), angle));
+    }
+    #[inline(always)]
+    fn apply_rotation(&mut self, rotation: &Mat4) {
+        self.rotate_quat(&UnitQuaternion::from_matrix(&mat4_to_mat3(rotation)));
+    }
+    #[inline(always)]
+    fn rotate_quat(&mut self, rotation: &UnitQuaternion<f32>) {
+        self.rotation = rotation * self.rotation;
+        self.rotation.renormalize_fast();
+    }
+    fn get_rotation(&self) -> UnitQuaternion<f32> {
+        self.rotation
+    }
+    fn set_rotation(&mut self, rotation: &UnitQuaternion<f32>) {
+        self.rotation = *rotation;
+    }
+    fn get_position(&self) -> Vec3 {
+        self.position
+    }
+    fn set_position(&mut self, position: &Vec3) {
+        self.position = *position;
+    }
+    fn get_scale(&self) -> Vec3 {
+        vec3(self.scale, self.scale, self.scale)
+    }
+    fn set_scale(&mut self, scale: &Vec3) {
+        self.scale = uniform_scale(scale);
+    }
+    #[inline(always)]
+    fn scale(&mut self, factors: &Vec3) {
+        self.scale *= uniform_scale(factors);
+    }
+    #[inline(always)]
+    fn apply_scaling(&mut self, scaling: &Mat4) {
+        self.scale *= uniform_scale(&vec3(scaling.m11, scaling.m22, scaling.m33));
+    }
+    #[inline(always)]
+    fn translate(&mut self, offset: &Vec3) {
+        self.position += offset;
+    }
+}
+
+// Where an `InstanceSet` sends its instances, a GL buffer outside of tests
+pub trait InstanceBuffer {
+    // Replaces the contents. Streamed data is only drawn for a single frame.
+    fn upload(&self, data: &[InstanceData], streaming: bool);
+    // Lets `fill` write `len` instances straight into fresh storage, if the buffer can be written
+    // that way. False when the contents didn't make it, whether `fill` ran or not.
+    fn write(&self, len: usize, fill: &mut dyn FnMut(&mut [InstanceData])) -> bool;
+}
+
+// Never repeats, so two clones that changed their instances apart never look the same
+fn next_version() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+// What the instance buffer shared by an object's clones holds
+#[derive(Default)]
+struct Uploaded {
+    version: Cell<u64>,            // 0 for none
+    poses: RefCell<Vec<Instance>>, // as the last frame left them, where motion starts if known
+    moving: Cell<bool>,            // uploaded with motion, which needs a still upload next frame
+}
+
+// The instances of an object, along with what it takes to bring its instance buffer up to date.
+// Nothing in here touches GL, the buffer itself belongs to the `SceneObject`.
+//
+// Clones share the buffer but each has its own instances, so every change gets a new version and
+// the buffer remembers which version it holds. A clone needs an upload whenever that isn't its own.
+#[derive(Clone)]
+pub struct InstanceSet {
+    instances: Vec<Instance>,
+    previous: Vec<Instance>, // before the last update, while culled instances are in motion
+    version: u64,
+    uploaded: Rc<Uploaded>,
+    culled: bool,
+}
+
+impl InstanceSet {
+    // A single instance at the origin
+    pub fn new() -> Self {
+        Self {
+            instances: vec![Instance::new()],
+            previous: vec![],
+            version: next_version(),
+            uploaded: Rc::new(Uploaded::default()),
+            culled: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn add(&mut self, instances: usize) {
+        for _ in 0..instances {
+            self.instances.push(Instance::new());
+        }
+        self.mark_dirty();
+    }
+
+    // Negative indices count from the back
+    fn index(&self, instance: isize) -> usize {
+        if instance < 0 {
+            self.instances.len() - (-instance as usize)
+        } else {
+            instance as usize
+        }
+    }
+
+    pub fn get(&self, instance: isize) -> &Instance {
+        &self.instances[self.index(instance)]
+    }
+
+    pub fn get_mut(&mut self, instance: isize) -> &mut Instance {
+        self.mark_dirty();
+        let index = self.index(instance);
+        &mut self.instances[index]
+    }
+
+    pub fn as_slice(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Instance] {
+        self.mark_dirty();
+        &mut self.instances
+    }
+
+    // Whether the buffer holds anything but these instances, as they moved this frame
+    pub fn is_dirty(&self) -> bool {
+        self.uploaded.version.get() != self.version
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.version = next_version();
+    }
+
+    // After these instances went to the buffer some other way than `upload`, which leaves no poses
+    // to take the motion of the next upload from
+    pub fn mark_uploaded(&self) {
+        self.uploaded.version.set(self.version);
+        self.uploaded.moving.set(false);
+        self.uploaded.poses.borrow_mut().clear();
+    }
+
+    pub fn is_culled(&self) -> bool {
+        self.culled
+    }
+
+    pub fn set_culled(&mut self, culled: bool) {
+        self.culled = culled;
+        self.previous.clear();
+        self.uploaded.poses.borrow_mut().clear();
+        self.mark_dirty();
+    }
+
+    // Still, as they are now
+    pub fn data(&self) -> Vec<InstanceData> {
+        self.instances.iter().map(Instance::to_data).collect()
+    }
+
+    // Runs `update` over the instances in parallel and remembers where they were before, for the
+    // motion of the culled upload
+    pub fn update<F>(&mut self, update: F)
+    where
+        F: Fn(usize, &mut Instance) + Sync,
+    {
+        self.previous = self.instances.clone();
+        self.instances
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, instance)| update(i, instance));
+    }
+
+    // Same, writing each instance along with its motion into `data`, one per instance
+    pub fn update_into<F>(&mut self, data: &mut [InstanceData], update: F)
+    where
+        F: Fn(usize, &mut Instance) + Sync,
+    {
+        self.instances
+            .par_iter_mut()
+            .zip(data.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, (instance, data))| {
+                let previous = *instance;
+                update(i, instance);
+                *data = instance.to_moving_data(&previous);
+            });
+    }
+
+    // Runs `update` while writing the results through `buffer`, or leaves them for the next upload
+    // if it can't be written to. Culled instances are never written, only the visible ones go up.
+    pub fn update_through<F>(&mut self, buffer: &dyn InstanceBuffer, update: F)
+    where
+        F: Fn(usize, &mut Instance) + Sync,
+    {
+        if self.culled {
+            self.update(update);
+            return;
+        }
+        self.mark_dirty();
+        let mut updated = false;
+        let written = buffer.write(self.instances.len(), &mut |data| {
+            self.update_into(data, &update);
+            updated = true;
+        });
+        if !updated {
+            self.update(update);
+        }
+        if written {
+            self.mark_uploaded();
+        }
+    }
+
+    // Uploads every instance, unless the buffer holds them already. Instances changed since the last
+    // frame carry the motion from where it left them, however many views upload them in between.
+    pub fn upload(&self, buffer: &dyn InstanceBuffer) -> CullingStats {
+        if self.is_dirty() {
+            let poses = self.uploaded.poses.borrow();
+            let data: Vec<InstanceData> = self
+                .instances
+                .iter()
+                .enumerate()
+                .map(|(i, instance)| match poses.get(i) {
+                    Some(previous) => instance.to_moving_data(previous),
+                    None => instance.to_data(),
+                })
+                .collect();
+            buffer.upload(&data, false);
+            self.uploaded.version.set(self.version);
+            self.uploaded
+                .moving
+                .set(data.iter().any(InstanceData::is_moving));
+        }
+        CullingStats {
+            drawn: self.len(),
+            culled: 0,
+        }
+    }
+
+    // Uploads the instances in view, see `visible_data`. They change with the view, so they're
+    // streamed every time.
+    pub fn upload_visible(
+        &self,
+        buffer: &dyn InstanceBuffer,
+        bounds: &Aabb,
+        model: &Mat4,
+        frustum: &Frustum,
+    ) -> CullingStats {
+        let visible = self.visible_data(bounds, model, frustum);
+        buffer.upload(&visible, true);
+        self.mark_uploaded();
+        CullingStats {
+            drawn: visible.len(),
+            culled: self.len() - visible.len(),
+        }
+    }
+
+    // The motion of the next frame starts from the instances as they are now, and whatever moved
+    // this frame gets uploaded still then
+    pub fn end_frame(&self) {
+        if self.uploaded.moving.get() {
+            self.uploaded.version.set(0);
+            self.uploaded.moving.set(false);
+        }
+        self.uploaded.poses.borrow_mut().clone_from(&self.instances);
+    }
+
+    pub fn stop_motion(&mut self) {
+        self.previous.clear();
+        self.uploaded.poses.borrow_mut().clear();
+        self.mark_dirty();
+    }
+
+    // The instances whose `bounds`, in instance space, reach into `frustum` once placed by
+    // `model`. They keep their order.
+    pub fn visible_data(
+        &self,
+        bounds: &Aabb,
+        model: &Mat4,
+        frustum: &Frustum,
+    ) -> Vec<InstanceData> {
+        self.instances
+            .par_iter()
+            .enumerate()
+            .filter_map(|(i, instance)| {
+                let world_bounds = bounds.transformed(&(model * instance.get_model()));
+                frustum
+                    .intersects_aabb(&world_bounds)
+                    .then(|| match self.previous.get(i) {
+                        Some(previous) => instance.to_moving_data(previous),
+                        None => instance.to_data(),
+                    })
+            })
+            .collect()
+    }
+
+    // Bytes held on the host
+    pub fn host_memory(&self) -> usize {
+        MemoryUsage::of_vec(&self.instances) + MemoryUsage::of_vec(&self.previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::Transform;
+    use std::cell::RefCell;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_mat4_eq(a: &Mat4, b: &Mat4) {
+        assert!((a - b).abs().max() < EPSILON, "{a} != {b}");
+    }
+
+    #[test]
+    fn instance_matches_matrix_path() {
+        let axis = normalize(&vec3(1.0, 2.0, -1.0));
+        let mut instance = Instance::new();
+        let mut transform = Transform::new();
+        for spatial in [&mut instance as &mut dyn Spatial, &mut transform] {
+            spatial.scale(&vec3(0.1, 0.1, 0.1));
+            spatial.rotate(0.8, &axis);
+            spatial.translate(&vec3(-4.0, 2.0, 7.0));
+            spatial.apply_rotation(&rotation(-0.3, &vec3(0.0, 1.0, 0.0)));
+            spatial.translate(&vec3(1.0, 1.0, 1.0));
+        }
+        assert_mat4_eq(&instance.get_model(), &transform.get_model());
+    }
+
+    #[test]
+    fn packed_instance_rebuilds_its_model() {
+        let mut instance = Instance::new();
+        instance.scale(&vec3(0.1, 0.1, 0.1));
+        instance.rotate(0.8, &normalize(&vec3(1.0, 2.0, -1.0)));
+        instance.translate(&vec3(-4.0, 2.0, 7.0));
+        let previous = instance;
+        instance.translate(&vec3(1.0, 0.0, 0.0));
+        let data = instance.to_moving_data(&previous);
+        assert_eq!(std::mem::size_of::<InstanceData>(), 72);
+        assert_mat4_eq(&data.model(), &instance.get_model());
+        let previous_data = InstanceData {
+            rotation: data.previous_rotation,
+            position_scale: data.previous_position_scale,
+            ..data
+        };
+        assert_mat4_eq(&previous_data.model(), &previous.get_model());
+    }
+
+    #[test]
+    fn draw_distance_goes_along_with_motion() {
+        let mut instance = Instance::new();
+        instance.draw_distance = vec2(50.0, 10.0);
+        let previous = instance;
+        instance.translate(&vec3(1.0, 0.0, 0.0));
+        assert_eq!(instance.to_data().draw_distance, vec2(50.0, 10.0));
+        assert_eq!(
+            instance.to_moving_data(&previous).draw_distance,
+            vec2(50.0, 10.0)
+        );
+    }
+
+    #[test]
+    fn instance_set_model_round_trips() {
+        let model = translation(&vec3(1.0, -2.0, 3.0))
+            * rotation(1.2, &normalize(&vec3(0.0, 1.0, 1.0)))
+            * scaling(&vec3(2.0, 2.0, 2.0));
+        let mut instance = Instance::new();
+        instance.set_model(&model);
+        assert_mat4_eq(&instance.get_model(), &model);
+    }
+
+    #[test]
+    fn non_uniform_scales_are_drawn_as_they_are_culled() {
+        let mut instance = Instance::new();
+        instance.set_scale(&vec3(1.0, 3.0, 2.0));
+        assert_eq!(instance.get_scale(), vec3(3.0, 3.0, 3.0));
+        instance.scale(&vec3(0.5, 0.5, 0.25));
+        assert_eq!(instance.scale, 1.5);
+        assert_mat4_eq(&instance.to_data().model(), &instance.get_model());
+    }
+
+    #[test]
+    fn instance_normal_is_inverse_transpose() {
+        let mut instance = Instance::new();
+        instance.set_model(&(rotation(0.5, &vec3(1.0, 0.0, 0.0)) * scaling(&vec3(3.0, 3.0, 3.0))));
+        let expected = mat4_to_mat3(&instance.get_model().try_inverse().unwrap().transpose());
+        assert!((instance.get_normal() - expected).abs().max() < EPSILON);
+    }
+
+    #[test]
+    fn instance_data_carries_previous_model() {
+        let mut instance = Instance::new();
+        let still = instance.to_data();
+        assert_eq!(still.rotation, still.previous_rotation);
+        assert_eq!(still.position_scale, still.previous_position_scale);
+
+        let previous = instance;
+        instance.translate(&vec3(0.0, 1.0, 0.0));
+        let moving = instance.to_moving_data(&previous);
+        assert_eq!(moving.previous_position_scale, vec4(0.0, 0.0, 0.0, 1.0));
+        assert_mat4_eq(&moving.model(), &translation(&vec3(0.0, 1.0, 0.0)));
+    }
+
+    fn set_of(positions: &[Vec3]) -> InstanceSet {
+        let mut set = InstanceSet::new();
+        set.add(positions.len() - 1);
+        for (i, position) in positions.iter().enumerate() {
+            set.get_mut(i as isize).set_position(position);
+        }
+        set.mark_uploaded();
+        set
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_back() {
+        let mut set = set_of(&[
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(2.0, 0.0, 0.0),
+        ]);
+        assert_eq!(set.get(-1).get_position(), vec3(2.0, 0.0, 0.0));
+        assert_eq!(set.get(-3).get_position(), vec3(0.0, 0.0, 0.0));
+        assert!(!set.is_dirty());
+        set.get_mut(-2).translate(&vec3(0.0, 1.0, 0.0));
+        assert!(set.is_dirty());
+        assert_eq!(set.get(1).get_position(), vec3(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn clones_upload_their_own_instances() {
+        let mut set = set_of(&[Vec3::zeros()]);
+        let copy = set.clone();
+        set.add(2);
+        assert_eq!(set.len(), 3);
+        assert_eq!(copy.len(), 1);
+        // the buffer still holds what the copy has
+        let buffer = RecordingBuffer::new(true);
+        copy.upload(&buffer);
+        assert_eq!(buffer.uploads(), 0);
+
+        set.upload(&buffer);
+        assert_eq!(buffer.last().0.len(), 3);
+        assert!(!set.is_dirty());
+        // and now it doesn't anymore
+        assert!(copy.is_dirty());
+        copy.upload(&buffer);
+        assert_eq!(buffer.last().0.len(), 1);
+    }
+
+    // Keeps whatever would have gone to the GPU, and whether it was streamed
+    struct RecordingBuffer {
+        writable: bool,
+        contents: RefCell<Vec<(Vec<InstanceData>, bool)>>,
+    }
+
+    impl RecordingBuffer {
+        fn new(writable: bool) -> Self {
+            Self {
+                writable,
+                contents: RefCell::new(vec![]),
+            }
+        }
+
+        fn uploads(&self) -> usize {
+            self.contents.borrow().len()
+        }
+
+        fn last(&self) -> (Vec<InstanceData>, bool) {
+            self.contents.borrow().last().unwrap().clone()
+        }
+    }
+
+    impl InstanceBuffer for RecordingBuffer {
+        fn upload(&self, data: &[InstanceData], streaming: bool) {
+            self.contents.borrow_mut().push((data.to_vec(), streaming));
+        }
+
+        fn write(&self, len: usize, fill: &mut dyn FnMut(&mut [InstanceData])) -> bool {
+            if !self.writable {
+                return false;
+            }
+            let mut data = vec![InstanceData::zeroed(); len];
+            fill(&mut data);
+            self.contents.borrow_mut().push((data, false));
+            true
+        }
+    }
+
+    #[test]
+    fn instances_are_uploaded_once_per_change() {
+        let mut set = InstanceSet::new();
+        set.add(1);
+        let buffer = RecordingBuffer::new(true);
+        assert_eq!(
+            set.upload(&buffer),
+            CullingStats {
+                drawn: 2,
+                culled: 0
+            }
+        );
+        set.upload(&buffer);
+        assert_eq!(buffer.uploads(), 1);
+
+        set.get_mut(1).set_position(&vec3(1.0, 0.0, 0.0));
+        set.upload(&buffer);
+        assert_eq!(buffer.uploads(), 2);
+        let (data, streaming) = buffer.last();
+        assert_eq!(data[1].position_scale, vec4(1.0, 0.0, 0.0, 1.0));
+        assert!(!streaming);
+    }
+
+    #[test]
+    fn edits_move_from_the_last_frame_for_a_frame() {
+        let mut set = InstanceSet::new();
+        let buffer = RecordingBuffer::new(true);
+        set.upload(&buffer);
+        set.end_frame();
+        set.get_mut(0).translate(&vec3(1.0, 0.0, 0.0));
+        set.upload(&buffer);
+        let (data, _) = buffer.last();
+        assert_eq!(data[0].position_scale, vec4(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(data[0].previous_position_scale, vec4(0.0, 0.0, 0.0, 1.0));
+        set.end_frame();
+
+        // the next frame nothing moved anymore
+        assert!(set.is_dirty());
+        set.upload(&buffer);
+        assert!(!buffer.last().0[0].is_moving());
+        set.upload(&buffer);
+        assert_eq!(buffer.uploads(), 3);
+    }
+
+    #[test]
+    fn every_view_of_a_frame_gets_the_motion() {
+        let mut set = InstanceSet::new();
+        let buffer = RecordingBuffer::new(true);
+        set.upload(&buffer);
+        set.end_frame();
+        set.get_mut(0).translate(&vec3(1.0, 0.0, 0.0));
+        // say a mirror, then the main view with its own copy of the objects
+        set.upload(&buffer);
+        let view = set.clone();
+        view.upload(&buffer);
+        assert_eq!(buffer.uploads(), 2);
+        assert!(buffer.last().0[0].is_moving());
+
+        // moved again within the same frame, still from where the last frame left it
+        set.get_mut(0).translate(&vec3(1.0, 0.0, 0.0));
+        set.upload(&buffer);
+        assert_eq!(buffer.uploads(), 3);
+        let (data, _) = buffer.last();
+        assert_eq!(data[0].position_scale, vec4(2.0, 0.0, 0.0, 1.0));
+        assert_eq!(data[0].previous_position_scale, vec4(0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn updates_are_written_through_with_their_motion() {
+        let mut set = set_of(&[Vec3::zeros()]);
+        let buffer = RecordingBuffer::new(true);
+        set.update_through(&buffer, |_, instance| {
+            instance.translate(&vec3(0.0, 1.0, 0.0))
+        });
+        assert_eq!(buffer.uploads(), 1);
+        let (data, _) = buffer.last();
+        assert_eq!(data[0].position_scale, vec4(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(data[0].previous_position_scale, vec4(0.0, 0.0, 0.0, 1.0));
+        // nothing left to upload at draw time
+        set.upload(&buffer);
+        assert_eq!(buffer.uploads(), 1);
+    }
+
+    #[test]
+    fn failed_writes_are_uploaded_later() {
+        let mut set = set_of(&[Vec3::zeros()]);
+        let buffer = RecordingBuffer::new(false);
+        set.update_through(&buffer, |_, instance| {
+            instance.translate(&vec3(0.0, 1.0, 0.0))
+        });
+        assert_eq!(buffer.uploads(), 0);
+        assert_eq!(set.get(0).get_position(), vec3(0.0, 1.0, 0.0));
+        set.upload(&buffer);
+        assert_eq!(buffer.last().0[0].position_scale, vec4(0.0, 1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn culled_instances_stream_the_visible_ones() {
+        let mut set = set_of(&[vec3(0.0, 0.0, -10.0), vec3(0.0, 0.0, 10.0)]);
+        set.set_culled(true);
+        let buffer = RecordingBuffer::new(true);
+        set.update_through(&buffer, |_, instance| {
+            instance.translate(&vec3(0.0, 1.0, 0.0))
+        });
+        assert_eq!(buffer.uploads(), 0);
+
+        let view = look_at(&Vec3::zeros(), &vec3(0.0, 0.0, -1.0), &Vec3::y());
+        let frustum = Frustum::from_view_projection(&(perspective(1.0, 1.5, 0.1, 100.0) * view));
+        let bounds = Aabb::new(vec3(-0.5, -0.5, -0.5), vec3(0.5, 0.5, 0.5));
+        let stats = set.upload_visible(&buffer, &bounds, &Mat4::identity(), &frustum);
+        assert_eq!(
+            stats,
+            CullingStats {
+                drawn: 1,
+                culled: 1
+            }
+        );
+        let (data, streaming) = buffer.last();
+        assert_eq!(data.len(), 1);
+        assert!(streaming);
+    }
+
+    #[test]
+    fn culling_keeps_visible_instances_in_order_with_their_motion() {
+        let mut set = set_of(&[
+            vec3(0.0, 0.0, -10.0),
+            vec3(0.0, 0.0, 10.0),
+            vec3(2.0, 0.0, -20.0),
+        ]);
+        set.set_culled(true);
+        set.update(|_, instance| instance.translate(&vec3(0.0, 1.0, 0.0)));
+
+        let view = look_at(&Vec3::zeros(), &vec3(0.0, 0.0, -1.0), &Vec3::y());
+        let frustum = Frustum::from_view_projection(&(perspective(1.0, 1.5, 0.1, 100.0) * view));
+        let bounds = Aabb::new(vec3(-0.5, -0.5, -0.5), vec3(0.5, 0.5, 0.5));
+        let visible = set.visible_data(&bounds, &Mat4::identity(), &frustum);
+        let positions: Vec<Vec3> = visible
+            .iter()
+            .map(|data| data.position_scale.xyz())
+            .collect();
+        assert_eq!(
+            positions,
+            vec![vec3(0.0, 1.0, -10.0), vec3(2.0, 1.0, -20.0)]
+        );
+        assert_eq!(
+            visible[0].previous_position_scale.xyz(),
+            vec3(0.0, 0.0, -10.0)
+        );
+
+        set.stop_motion();
+        let still = set.visible_data(&bounds, &Mat4::identity(), &frustum);
+        assert_eq!(still[0].previous_position_scale, still[0].position_scale);
+    }
+}
@@ -3,7 +3,70 @@ use std::{cell::RefCell, rc::Rc};
 use beryllium::Keycode;
 use nalgebra_glm::*;
 
+use crate::camera::Camera;
 use crate::controls::{Controller, SignalHandler, SignalType, Slot};
+use crate::spatial::{BoundingSphere, Frustum};
+
+const AMBIENT_RATIO: f32 = 0.2;
+const REFERENCE_LUX: f32 = 1000.0; // illuminance that maps to a diffuse of 1.0
+const REFERENCE_LUMENS: f32 = 1250.0; // same for point-like lights, ~100 cd in every direction
+const SHADOW_RADIUS_STEP: f32 = 1.0 / 16.0; // stabilized shadow bounds grow and shrink in steps
+const INFLUENCE_CUTOFF: f32 = 1.0 / 256.0; // below one 8 bit step a light is as good as off
+const NEUTRAL_KELVIN: f32 = 6500.0; // for lights made from plain colors
+const INTENSITY_STEP: f32 = 1.25;
+const TEMPERATURE_STEP: f32 = 500.0;
+
+// Held to edit the selected light with the movement keys, which the camera ignores meanwhile
+pub const LIGHT_EDIT_MODIFIER: Keycode = Keycode::LALT;
+
+// Approximate sRGB color of a black body at `kelvin`, normalized so its brightest channel is 1.0.
+// Fit by Tanner Helland, good enough between 1000K and 40000K.
+pub fn kelvin_to_rgb(kelvin: f32) -> Vec3 {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_17 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    vec3(r, g, b).map(|c| c.clamp(0.0, 255.0) / 255.0)
+}
+
+// Distance at which a light of the given colors and attenuation drops below the cutoff
+fn influence_radius(colors: [Vec3; 3], att: &Vec3) -> f32 {
+    let intensity = colors.iter().map(|color| color.max()).fold(0.0, f32::max);
+    let (constant, linear, quadratic) = (att.x, att.y, att.z);
+    // solve constant + linear * d + quadratic * d^2 = intensity / cutoff
+    let target = intensity / INFLUENCE_CUTOFF;
+    if intensity <= 0.0 || target <= constant {
+        0.0
+    } else if quadratic > 0.0 {
+        let discriminant = linear * linear - 4.0 * quadratic * (constant - target);
+        (-linear + discriminant.sqrt()) / (2.0 * quadratic)
+    } else if linear > 0.0 {
+        (target - constant) / linear
+    } else {
+        f32::INFINITY
+    }
+}
+
+// Ambient, diffuse and specular colors for a light of the given temperature and relative
+// intensity, where 1.0 is the reference brightness
+fn light_colors(kelvin: f32, intensity: f32) -> (Vec3, Vec3, Vec3) {
+    let diffuse = kelvin_to_rgb(kelvin) * intensity;
+    (diffuse * AMBIENT_RATIO, diffuse, diffuse)
+}
 
 pub struct DirectionalLight {
     pub dir: Vec3,
@@ -23,6 +86,103 @@ impl DirectionalLight {
             on: true,
         }
     }
+
+    pub fn from_temperature(dir: Vec3, kelvin: f32, lux: f32) -> Self {
+        let (amb, diff, spec) = light_colors(kelvin, lux / REFERENCE_LUX);
+        Self::new(dir, amb, diff, spec)
+    }
+
+    pub fn get_amb(&self) -> Vec3 {
+        self.amb * (self.on as i32 as f32)
+    }
+    pub fn get_diff(&self) -> Vec3 {
+        self.diff * (self.on as i32 as f32)
+    }
+    pub fn get_spec(&self) -> Vec3 {
+        self.spec * (self.on as i32 as f32)
+    }
+}
+
+// Shadow map of the directional light, covering the view up to `distance` from the camera
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    pub resolution: u32,
+    pub distance: f32,
+    // Trades some resolution for shadow edges that stay put while the camera moves or turns
+    pub stabilize: bool,
+}
+
+impl ShadowSettings {
+    pub fn new() -> Self {
+        Self {
+            resolution: 2048,
+            distance: 30.0,
+            stabilize: true,
+        }
+    }
+}
+
+impl DirectionalLight {
+    // Rotation from world to light space, anchored at the world origin so it only changes when
+    // the light turns
+    fn shadow_view(&self) -> Mat4 {
+        let dir = normalize(&self.dir);
+        let up = if dir.y.abs() > 0.99 {
+            vec3(0.0, 0.0, 1.0)
+        } else {
+            vec3(0.0, 1.0, 0.0)
+        };
+        look_at(&Vec3::zeros(), &dir, &up)
+    }
+
+    // Orthographic view-projection of the shadow map for the part of `camera`'s view within the
+    // shadow distance. Casters up to that same distance in front of it, towards the light, are
+    // kept too.
+    //
+    // A plain fit hugs the frustum, so the shadow map changes size as the camera turns and slides
+    // by fractions of a texel as it moves, and shadow edges crawl. A stabilized one fits a bounding
+    // sphere instead, whose size doesn't depend on the orientation, and only moves in whole
+    // texels, so every texel keeps covering the same patch of the world.
+    pub fn shadow_view_projection(
+        &self,
+        camera: &Camera,
+        aspect: f32,
+        settings: &ShadowSettings,
+    ) -> Mat4 {
+        let view = self.shadow_view();
+        let (near, _) = camera.get_clip_planes();
+        let corners = camera
+            .frustum_corners(aspect, near, settings.distance.max(near))
+            .map(|corner| (view * corner.push(1.0)).xyz());
+
+        let (min, max) = if settings.stabilize {
+            let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .map(|corner| distance(corner, &center))
+                .fold(0.0, f32::max);
+            let radius = (radius / SHADOW_RADIUS_STEP).ceil() * SHADOW_RADIUS_STEP;
+            let texel = 2.0 * radius / settings.resolution.max(1) as f32;
+            let snapped = vec3(
+                (center.x / texel).floor() * texel,
+                (center.y / texel).floor() * texel,
+                center.z,
+            );
+            (
+                snapped - vec3(radius, radius, radius),
+                snapped + vec3(radius, radius, radius),
+            )
+        } else {
+            corners
+                .iter()
+                .fold((corners[0], corners[0]), |(min, max), corner| {
+                    (min.inf(corner), max.sup(corner))
+                })
+        };
+        // light space looks down -z, so the farthest depth is the smallest z
+        let depth_range = max.z - min.z;
+        ortho(min.x, max.x, min.y, max.y, -max.z - depth_range, -min.z) * view
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -33,6 +193,7 @@ pub struct PointLight {
     pub spec: Vec3,
     pub att: Vec3,
     pub on: bool,
+    kelvin: Option<f32>, // None for lights made from plain colors
 }
 
 impl PointLight {
@@ -44,8 +205,65 @@ impl PointLight {
             spec,
             att,
             on: true,
+            kelvin: None,
+        }
+    }
+
+    pub fn from_temperature(pos: Vec3, kelvin: f32, lumens: f32, att: Vec3) -> Self {
+        let mut light = Self::new(pos, Vec3::zeros(), Vec3::zeros(), Vec3::zeros(), att);
+        light.set_temperature(kelvin);
+        light.set_intensity(lumens / REFERENCE_LUMENS);
+        light
+    }
+
+    // Relative to the reference brightness, whether the light is on or not
+    pub fn get_intensity(&self) -> f32 {
+        self.diff.max()
+    }
+
+    // Lights made from plain colors keep them, only scaled
+    pub fn set_intensity(&mut self, intensity: f32) {
+        let intensity = intensity.max(0.0);
+        match self.kelvin {
+            Some(kelvin) => (self.amb, self.diff, self.spec) = light_colors(kelvin, intensity),
+            None => {
+                let current = self.get_intensity();
+                if current > 0.0 {
+                    let factor = intensity / current;
+                    self.amb *= factor;
+                    self.diff *= factor;
+                    self.spec *= factor;
+                }
+            }
         }
     }
+
+    pub fn get_temperature(&self) -> Option<f32> {
+        self.kelvin
+    }
+
+    // Keeps the intensity
+    pub fn set_temperature(&mut self, kelvin: f32) {
+        let kelvin = kelvin.clamp(1000.0, 40000.0);
+        self.kelvin = Some(kelvin);
+        (self.amb, self.diff, self.spec) = light_colors(kelvin, self.get_intensity());
+    }
+
+    pub fn get_amb(&self) -> Vec3 {
+        self.amb * (self.on as i32 as f32)
+    }
+    pub fn get_diff(&self) -> Vec3 {
+        self.diff * (self.on as i32 as f32)
+    }
+    pub fn get_spec(&self) -> Vec3 {
+        self.spec * (self.on as i32 as f32)
+    }
+
+    // Everything the light noticeably reaches, empty while it's off
+    pub fn influence(&self) -> BoundingSphere {
+        let colors = [self.get_amb(), self.get_diff(), self.get_spec()];
+        BoundingSphere::new(self.pos, influence_radius(colors, &self.att))
+    }
 }
 
 // phi: angle of the inner cone
@@ -86,6 +304,21 @@ impl Spotlight {
         }
     }
 
+    // `lumens` is what the light would emit over the whole sphere, so narrowing the cone doesn't
+    // dim it
+    pub fn from_temperature(
+        pos: Vec3,
+        dir: Vec3,
+        kelvin: f32,
+        lumens: f32,
+        att: Vec3,
+        phi: f32,
+        gamma: f32,
+    ) -> Self {
+        let (amb, diff, spec) = light_colors(kelvin, lumens / REFERENCE_LUMENS);
+        Self::new(pos, dir, amb, diff, spec, att, phi, gamma)
+    }
+
     pub fn get_amb(&self) -> Vec3 {
         self.amb * (self.on as i32 as f32)
     }
@@ -95,6 +328,12 @@ impl Spotlight {
     pub fn get_spec(&self) -> Vec3 {
         self.spec * (self.on as i32 as f32)
     }
+
+    // Bounds the whole sphere rather than the cone, which is loose but cheap
+    pub fn influence(&self) -> BoundingSphere {
+        let colors = [self.get_amb(), self.get_diff(), self.get_spec()];
+        BoundingSphere::new(self.pos, influence_radius(colors, &self.att))
+    }
 }
 
 pub struct FlashlightController {
@@ -107,7 +346,7 @@ impl FlashlightController {
     }
     pub fn on_key_pressed(&mut self, keycode: Keycode) {
         match keycode {
-            Keycode::F => self.on = !self.on,
+            Keycode::T => self.on = !self.on, // F frames the selection
             _ => (),
         }
     }
@@ -132,8 +371,407 @@ impl<'a> Controller<'a, Spotlight, FlashlightController> for Rc<RefCell<Flashlig
     }
 }
 
+// Debug switches: F1 toggles the directional light, F2 to F5 the point lights
+pub struct LightSwitchController {
+    toggle_dir: bool,
+    toggled_points: Vec<usize>,
+}
+
+impl LightSwitchController {
+    pub fn new() -> Rc<RefCell<LightSwitchController>> {
+        Rc::new(RefCell::new(Self {
+            toggle_dir: false,
+            toggled_points: vec![],
+        }))
+    }
+    pub fn on_key_pressed(&mut self, keycode: Keycode) {
+        match keycode {
+            Keycode::F1 => self.toggle_dir = !self.toggle_dir,
+            Keycode::F2 => self.toggled_points.push(0),
+            Keycode::F3 => self.toggled_points.push(1),
+            Keycode::F4 => self.toggled_points.push(2),
+            Keycode::F5 => self.toggled_points.push(3),
+            _ => (),
+        }
+    }
+}
+
+impl Slot for LightSwitchController {
+    fn on_signal(&mut self, signal: SignalType) {
+        match signal {
+            SignalType::KeyPressed(key) => self.on_key_pressed(key),
+            _ => (),
+        }
+    }
+}
+
+impl<'a> Controller<'a, Lighting, LightSwitchController> for Rc<RefCell<LightSwitchController>> {
+    fn update_control_parameters(&self, update: &'a mut (dyn FnMut(&mut LightSwitchController))) {
+        update(&mut (**self).borrow_mut());
+    }
+    // Only flips the lights, scripts switch them too
+    fn process_signals(&'a self, obj: &mut Lighting) {
+        let mut self_obj = (**self).borrow_mut();
+        if std::mem::take(&mut self_obj.toggle_dir) {
+            obj.dir.on = !obj.dir.on;
+        }
+        for index in std::mem::take(&mut self_obj.toggled_points) {
+            if let Some(light) = obj.point.get_mut(index) {
+                light.on = !light.on;
+            }
+        }
+    }
+}
+
+// Edits the point lights at runtime. Comma and period select the previous or next one, and while
+// `LIGHT_EDIT_MODIFIER` is held the movement keys move it along the world axes, up and down change
+// its intensity and left and right its color temperature.
+pub struct LightEditController {
+    selected: Option<usize>,
+    select_steps: i32,
+    modifier: bool,
+    positive_delta_mov: Vec3,
+    negative_delta_mov: Vec3,
+    intensity_steps: i32,
+    temperature_steps: i32,
+    trans_speed: f32,
+}
+
+impl LightEditController {
+    pub fn new() -> Rc<RefCell<LightEditController>> {
+        Rc::new(RefCell::new(Self {
+            selected: None,
+            select_steps: 0,
+            modifier: false,
+            positive_delta_mov: Vec3::zeros(),
+            negative_delta_mov: Vec3::zeros(),
+            intensity_steps: 0,
+            temperature_steps: 0,
+            trans_speed: 0.0,
+        }))
+    }
+    // Same pace as the camera
+    pub fn set_speed(&mut self, cycle_time: f32) {
+        self.trans_speed = cycle_time * 0.002;
+    }
+    pub fn get_selected(&self) -> Option<usize> {
+        self.selected
+    }
+    // The movement keys move the selected light meanwhile
+    pub fn is_editing(&self) -> bool {
+        self.modifier
+    }
+    fn set_movement(&mut self, keycode: Keycode, amount: f32) {
+        match keycode {
+            Keycode::D => self.positive_delta_mov.x = amount,
+            Keycode::A => self.negative_delta_mov.x = amount,
+            Keycode::SPACE => self.positive_delta_mov.y = amount,
+            Keycode::LCTRL => self.negative_delta_mov.y = amount,
+            Keycode::S => self.positive_delta_mov.z = amount,
+            Keycode::W => self.negative_delta_mov.z = amount,
+            _ => (),
+        }
+    }
+    pub fn on_key_pressed(&mut self, keycode: Keycode) {
+        match keycode {
+            Keycode::COMMA => self.select_steps -= 1,
+            Keycode::PERIOD => self.select_steps += 1,
+            LIGHT_EDIT_MODIFIER => self.modifier = true,
+            Keycode::UP if self.modifier => self.intensity_steps += 1,
+            Keycode::DOWN if self.modifier => self.intensity_steps -= 1,
+            Keycode::RIGHT if self.modifier => self.temperature_steps += 1,
+            Keycode::LEFT if self.modifier => self.temperature_steps -= 1,
+            _ if self.modifier => self.set_movement(keycode, 1.0),
+            _ => (),
+        }
+    }
+    pub fn on_key_released(&mut self, keycode: Keycode) {
+        match keycode {
+            LIGHT_EDIT_MODIFIER => {
+                self.modifier = false;
+                self.positive_delta_mov = Vec3::zeros();
+                self.negative_delta_mov = Vec3::zeros();
+            }
+            _ => self.set_movement(keycode, 0.0),
+        }
+    }
+}
+
+impl Slot for LightEditController {
+    fn on_signal(&mut self, signal: SignalType) {
+        match signal {
+            SignalType::KeyPressed(key) => self.on_key_pressed(key),
+            SignalType::KeyReleased(key) => self.on_key_released(key),
+            _ => (),
+        }
+    }
+}
+
+impl<'a> Controller<'a, Lighting, LightEditController> for Rc<RefCell<LightEditController>> {
+    fn update_control_parameters(&self, update: &'a mut (dyn FnMut(&mut LightEditController))) {
+        update(&mut (**self).borrow_mut());
+    }
+    fn process_signals(&'a self, obj: &mut Lighting) {
+        let mut self_obj = (**self).borrow_mut();
+        let count = obj.point.len() as i32;
+        let steps = std::mem::take(&mut self_obj.select_steps);
+        if count == 0 {
+            self_obj.selected = None;
+        } else if steps != 0 {
+            // the first step forward lands on the first light, the first one back on the last
+            let start = match self_obj.selected {
+                Some(selected) => selected as i32,
+                None if steps > 0 => -1,
+                None => count,
+            };
+            self_obj.selected = Some((start + steps).rem_euclid(count) as usize);
+        }
+        let intensity_steps = std::mem::take(&mut self_obj.intensity_steps);
+        let temperature_steps = std::mem::take(&mut self_obj.temperature_steps);
+        let Some(light) = self_obj.selected.and_then(|i| obj.point.get_mut(i)) else {
+            return;
+        };
+        light.pos +=
+            (self_obj.positive_delta_mov - self_obj.negative_delta_mov) * self_obj.trans_speed;
+        if intensity_steps != 0 {
+            light.set_intensity(light.get_intensity() * INTENSITY_STEP.powi(intensity_steps));
+        }
+        if temperature_steps != 0 {
+            let kelvin = light.get_temperature().unwrap_or(NEUTRAL_KELVIN);
+            light.set_temperature(kelvin + TEMPERATURE_STEP * temperature_steps as f32);
+        }
+    }
+}
+
 pub struct Lighting {
     pub dir: DirectionalLight,
     pub point: Vec<PointLight>,
     pub spot: Spotlight,
+    pub shadows: ShadowSettings,
+    // Lights farther than this from the camera are skipped even if they'd reach into the view
+    pub cull_distance: f32,
+}
+
+impl Lighting {
+    fn is_visible(&self, influence: &BoundingSphere, frustum: &Frustum, eye: &Vec3) -> bool {
+        influence.radius > 0.0
+            && distance(&influence.center, eye) <= self.cull_distance
+            && frustum.intersects_sphere(influence)
+    }
+
+    // Point lights that light anything in the view, in their original order
+    pub fn visible_point_lights(&self, frustum: &Frustum, eye: &Vec3) -> Vec<&PointLight> {
+        self.point
+            .iter()
+            .filter(|light| self.is_visible(&light.influence(), frustum, eye))
+            .collect()
+    }
+
+    pub fn is_spotlight_visible(&self, frustum: &Frustum, eye: &Vec3) -> bool {
+        self.is_visible(&self.spot.influence(), frustum, eye)
+    }
+}
+
+#[cfg(test)]
+impl Lighting {
+    // Just `point`, with the sun and flashlight dark
+    pub fn test_rig(point: Vec<PointLight>) -> Self {
+        Self {
+            dir: DirectionalLight::new(Vec3::y(), Vec3::zeros(), Vec3::zeros(), Vec3::zeros()),
+            point,
+            spot: Spotlight::new(
+                Vec3::zeros(),
+                -Vec3::z(),
+                Vec3::zeros(),
+                Vec3::zeros(),
+                Vec3::zeros(),
+                vec3(1.0, 0.5, 0.25),
+                0.2,
+                0.3,
+            ),
+            shadows: ShadowSettings::new(),
+            cull_distance: 50.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daylight_is_close_to_white() {
+        let color = kelvin_to_rgb(6600.0);
+        assert!(color.iter().all(|c| *c > 0.95), "{:?}", color);
+    }
+
+    #[test]
+    fn warm_and_cold_lights_tint_opposite_ways() {
+        let warm = kelvin_to_rgb(2700.0);
+        let cold = kelvin_to_rgb(10000.0);
+        assert!(warm.x > warm.z);
+        assert!(cold.z > cold.x);
+        assert_eq!(warm.x, 1.0);
+        assert_eq!(cold.z, 1.0);
+    }
+
+    #[test]
+    fn intensity_scales_every_component() {
+        let (amb, diff, spec) = light_colors(5000.0, 2.0);
+        let (amb1, diff1, spec1) = light_colors(5000.0, 1.0);
+        assert_eq!(diff, diff1 * 2.0);
+        assert_eq!(spec, spec1 * 2.0);
+        assert_eq!(amb, amb1 * 2.0);
+        assert_eq!(amb1, diff1 * AMBIENT_RATIO);
+    }
+
+    #[test]
+    fn influence_ends_where_the_light_fades_out() {
+        let att = vec3(1.0, 0.5, 0.25);
+        let mut lamp = PointLight::new(
+            Vec3::zeros(),
+            vec3(0.1, 0.1, 0.1),
+            vec3(1.0, 0.8, 0.6),
+            vec3(0.5, 0.5, 0.5),
+            att,
+        );
+        let radius = lamp.influence().radius;
+        let falloff = 1.0 / (att.x + att.y * radius + att.z * radius * radius);
+        assert!((falloff - INFLUENCE_CUTOFF).abs() < 1e-5, "{}", falloff);
+        lamp.on = false;
+        assert_eq!(lamp.influence().radius, 0.0);
+    }
+
+    #[test]
+    fn culls_lights_outside_the_view_or_too_far() {
+        let att = vec3(1.0, 0.7, 1.8); // reaches about 12 units
+        let lamp =
+            |pos| PointLight::new(pos, Vec3::zeros(), vec3(1.0, 1.0, 1.0), Vec3::zeros(), att);
+        let lighting = Lighting::test_rig(vec![
+            lamp(vec3(0.0, 0.0, -5.0)),
+            lamp(vec3(0.0, 0.0, 30.0)),
+            lamp(vec3(0.0, 0.0, -60.0)),
+        ]);
+        let view = look_at(&Vec3::zeros(), &vec3(0.0, 0.0, -1.0), &Vec3::y());
+        let frustum = Frustum::from_view_projection(&(perspective(1.0, 1.5, 0.1, 100.0) * view));
+        let visible = lighting.visible_point_lights(&frustum, &Vec3::zeros());
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].pos, vec3(0.0, 0.0, -5.0));
+        // black, so it doesn't reach anything
+        assert!(!lighting.is_spotlight_visible(&frustum, &Vec3::zeros()));
+    }
+
+    #[test]
+    fn light_switches_keep_what_others_switched() {
+        let att = vec3(1.0, 0.5, 0.25);
+        let mut lighting = Lighting::test_rig(vec![
+            PointLight::from_temperature(Vec3::zeros(), 3000.0, 1250.0, att),
+            PointLight::from_temperature(Vec3::zeros(), 3000.0, 1250.0, att),
+        ]);
+        let switches = LightSwitchController::new();
+        lighting.point[1].on = false;
+        switches.process_signals(&mut lighting);
+        assert!(!lighting.point[1].on);
+
+        for key in [Keycode::F1, Keycode::F3, Keycode::F4] {
+            switches.borrow_mut().on_signal(SignalType::KeyPressed(key));
+        }
+        switches.process_signals(&mut lighting);
+        assert!(!lighting.dir.on);
+        assert!(lighting.point[0].on);
+        assert!(lighting.point[1].on);
+        // once per press
+        switches.process_signals(&mut lighting);
+        assert!(!lighting.dir.on);
+    }
+
+    #[test]
+    fn light_editor_selects_moves_and_retints() {
+        let att = vec3(1.0, 0.5, 0.25);
+        let mut lighting = Lighting::test_rig(vec![
+            PointLight::from_temperature(Vec3::zeros(), 3000.0, 1250.0, att),
+            PointLight::from_temperature(Vec3::zeros(), 3000.0, 1250.0, att),
+        ]);
+        let editor = LightEditController::new();
+        editor.borrow_mut().set_speed(500.0);
+        for key in [
+            Keycode::COMMA,
+            LIGHT_EDIT_MODIFIER,
+            Keycode::W,
+            Keycode::UP,
+            Keycode::RIGHT,
+        ] {
+            editor.borrow_mut().on_signal(SignalType::KeyPressed(key));
+        }
+        editor.process_signals(&mut lighting);
+
+        assert_eq!(editor.borrow().get_selected(), Some(1));
+        let (edited, untouched) = (&lighting.point[1], &lighting.point[0]);
+        assert_eq!(edited.pos, vec3(0.0, 0.0, -1.0));
+        assert_eq!(untouched.pos, Vec3::zeros());
+        assert!((edited.get_intensity() - INTENSITY_STEP).abs() < 1e-5);
+        assert_eq!(edited.get_temperature(), Some(3500.0));
+
+        // letting go of the modifier stops the light, and the keys go back to the camera
+        editor
+            .borrow_mut()
+            .on_signal(SignalType::KeyReleased(LIGHT_EDIT_MODIFIER));
+        editor
+            .borrow_mut()
+            .on_signal(SignalType::KeyPressed(Keycode::W));
+        editor.process_signals(&mut lighting);
+        assert_eq!(lighting.point[1].pos, vec3(0.0, 0.0, -1.0));
+    }
+
+    // Shadow map texel coordinates of a world point
+    fn shadow_texel(view_projection: &Mat4, point: &Vec3, resolution: u32) -> Vec2 {
+        let ndc = (view_projection * point.push(1.0)).xy();
+        (ndc + vec2(1.0, 1.0)) * 0.5 * resolution as f32
+    }
+
+    #[test]
+    fn stabilized_shadows_move_in_whole_texels() {
+        let sun = DirectionalLight::new(
+            vec3(0.5, -1.0, 0.3),
+            Vec3::zeros(),
+            Vec3::zeros(),
+            Vec3::zeros(),
+        );
+        let settings = ShadowSettings::new();
+        let mut camera = Camera::new(vec3(0.0, 1.0, -2.0));
+        let before = sun.shadow_view_projection(&camera, 1.0, &settings);
+        camera.translate(vec3(0.013, 0.0, 0.021));
+        let after = sun.shadow_view_projection(&camera, 1.0, &settings);
+
+        let point = vec3(1.0, 0.0, 1.0);
+        let shift = shadow_texel(&after, &point, settings.resolution)
+            - shadow_texel(&before, &point, settings.resolution);
+        for offset in shift.iter() {
+            assert!((offset - offset.round()).abs() < 1e-2, "{:?}", shift);
+        }
+    }
+
+    #[test]
+    fn stabilized_shadows_keep_their_size_when_turning() {
+        let sun = DirectionalLight::new(
+            vec3(0.5, -1.0, 0.3),
+            Vec3::zeros(),
+            Vec3::zeros(),
+            Vec3::zeros(),
+        );
+        let mut camera = Camera::new(vec3(0.0, 1.0, -2.0));
+        let scale = |camera: &Camera, stabilize| {
+            let settings = ShadowSettings {
+                stabilize,
+                ..ShadowSettings::new()
+            };
+            sun.shadow_view_projection(camera, 1.0, &settings)
+                .column(0)
+                .norm()
+        };
+        let (stable, loose) = (scale(&camera, true), scale(&camera, false));
+        camera.rotate_yaw(35.0);
+        assert_eq!(scale(&camera, true), stable);
+        assert!((scale(&camera, false) - loose).abs() > 1e-4);
+    }
 }
@@ -0,0 +1,142 @@
+use std::cell::Cell;
+
+use bytemuck::{Pod, Zeroable};
+use gl33::gl_enumerations::*;
+use gl33::global_loader::*;
+use nalgebra_glm::*;
+
+use crate::data::{buffer_data, Buffer, BufferType, VertexArray};
+use crate::meshes::AttributeSlot;
+use crate::shaders::ShaderProgram;
+
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct LineVertex {
+    pub pos: Vec3,
+    pub color: Vec3,
+}
+
+unsafe impl Zeroable for LineVertex {}
+unsafe impl Pod for LineVertex {}
+
+// World space line segments rebuilt every frame, for editor gizmos and the like. Lines are only
+// uploaded when they changed since the last draw.
+pub struct DebugLines {
+    vertices: Vec<LineVertex>,
+    dirty: Cell<bool>,
+    vao: VertexArray,
+    vbo: Buffer,
+}
+
+impl DebugLines {
+    pub fn new() -> Self {
+        let lines = Self {
+            vertices: vec![],
+            dirty: Cell::new(false),
+            vao: VertexArray::new().expect("Couldn't make a VAO"),
+            vbo: Buffer::new().expect("Couldn't make the vertex buffer"),
+        };
+        lines.setup_attributes();
+        lines
+    }
+
+    fn setup_attributes(&self) {
+        self.vao.bind();
+        self.vbo.bind(BufferType::Array);
+        unsafe {
+            glEnableVertexAttribArray(0);
+            glVertexAttribPointer(
+                0,
+                3,
+                GL_FLOAT,
+                GL_FALSE.0 as u8,
+                core::mem::size_of::<LineVertex>().try_into().unwrap(),
+                core::mem::offset_of!(LineVertex, pos) as *const _,
+            );
+            glEnableVertexAttribArray(1);
+            glVertexAttribPointer(
+                1,
+                3,
+                GL_FLOAT,
+                GL_FALSE.0 as u8,
+                core::mem::size_of::<LineVertex>().try_into().unwrap(),
+                core::mem::offset_of!(LineVertex, color) as *const _,
+            );
+        }
+        VertexArray::clear_binding();
+    }
+
+    pub fn attribute_layout(&self) -> Vec<AttributeSlot> {
+        vec![AttributeSlot::new(0, 3), AttributeSlot::new(1, 3)]
+    }
+
+    pub fn clear(&mut self) {
+        if !self.vertices.is_empty() {
+            self.dirty.set(true);
+        }
+        self.vertices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn line(&mut self, from: &Vec3, to: &Vec3, color: &Vec3) {
+        self.vertices.push(LineVertex {
+            pos: *from,
+            color: *color,
+        });
+        self.vertices.push(LineVertex {
+            pos: *to,
+            color: *color,
+        });
+        self.dirty.set(true);
+    }
+
+    // Three axis aligned segments crossing at `center`
+    pub fn cross(&mut self, center: &Vec3, size: f32, color: &Vec3) {
+        let half = size / 2.0;
+        for axis in [Vec3::x(), Vec3::y(), Vec3::z()] {
+            self.line(&(center - axis * half), &(center + axis * half), color);
+        }
+    }
+
+    // Square grid on the horizontal plane through `center`, `cells` cells of `step` to each side
+    pub fn grid(&mut self, center: &Vec3, step: f32, cells: u32, color: &Vec3) {
+        let extent = step * cells as f32;
+        for i in 0..=(2 * cells) {
+            let offset = i as f32 * step - extent;
+            self.line(
+                &(center + vec3(offset, 0.0, -extent)),
+                &(center + vec3(offset, 0.0, extent)),
+                color,
+            );
+            self.line(
+                &(center + vec3(-extent, 0.0, offset)),
+                &(center + vec3(extent, 0.0, offset)),
+                color,
+            );
+        }
+    }
+
+    pub fn draw(&self, shader: &ShaderProgram) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        self.vao.bind();
+        if self.dirty.get() {
+            self.vbo.bind(BufferType::Array);
+            buffer_data(
+                BufferType::Array,
+                bytemuck::cast_slice(&self.vertices),
+                GL_DYNAMIC_DRAW,
+            );
+            self.dirty.set(false);
+        }
+        shader.use_program();
+        unsafe {
+            glDrawArrays(GL_LINES, 0, self.vertices.len() as i32);
+        }
+        VertexArray::clear_binding();
+    }
+}
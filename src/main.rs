@@ -18,90 +18,196 @@ use gl33::global_loader::*;
 use nalgebra_glm::*;
 use rand::Rng;
 use russimp::light::Light;
-use spatial::Spatial;
+use spatial::{Spatial, SphereGrid};
 use std::{
     borrow::BorrowMut,
     cell::RefCell,
     collections::HashMap,
     ffi::c_void,
-    path::Path,
+    path::{Path, PathBuf},
     rc::{Rc, Weak},
     time::{Duration, Instant},
 };
 use utils::{RTController, RandomTransform};
 
-use camera::{Camera, CameraController};
-use controls::{Controller, SignalHandler};
-use data::{Buffer, BufferType, Framebuffer, PolygonMode, UniformBuffer, VertexArray};
-use lighting::{DirectionalLight, FlashlightController, Lighting, PointLight, Spotlight};
+use animation::MaterialAnimation;
+use camera::{Camera, CameraController, ProjectionWatch};
+use clock::{Clock, ClockController};
+use config::Config;
+use controls::{Controller, SignalHandler, SignalType, Slot};
+use data::{
+    Buffer, BufferType, Framebuffer, PolygonMode, StencilState, UniformBuffer, VertexArray,
+};
+use debug::FrameCapture;
+use editor::{Editor, EditorController};
+use events::{EngineEvent, EventBus, Subscriber};
+use framegraph::FrameGraph;
+use hooks::PassHooks;
+use lighting::{
+    DirectionalLight, FlashlightController, LightEditController, LightSwitchController, Lighting,
+    PointLight, ShadowSettings, Spotlight,
+};
+use lines::DebugLines;
 use meshes::{BasicMesh, Canvas, Draw, Skybox, Vertex};
+use mirror::{MirrorSurface, MIRROR_TEXTURE};
 use models::Model;
-use scene::{Scene, SceneController, SceneObject, SceneParameters};
-use screen::{Screen, ScreenController};
+use scene::{
+    ObjectHandle, Prefab, Scene, SceneController, SceneObject, SceneParameters, SpawnQueue,
+    OBJECT_STENCIL,
+};
+use screen::{Screen, ScreenController, ScreenParameters, TransitionKind};
+use scripting::ScriptRuntime;
 use shaders::{Shader, ShaderProgram, ShaderType};
+use stats::{CullingStats, FrameStats};
 use systems::{Program, ProgramController};
-use textures::{CubeMap, Material, Texture2D, TextureType};
+use textures::{
+    AlphaMode, BlendOp, CubeMap, MapBlend, Material, SplatLayer, SplatMap, Texture2D, TextureType,
+    Transmission,
+};
+use thumbnails::MaterialPreview;
 
+pub mod animation;
 pub mod camera;
+pub mod capture;
+pub mod clock;
+pub mod colorspace;
+pub mod config;
 pub mod controls;
 pub mod data;
+pub mod debug;
+pub mod editor;
+pub mod events;
+pub mod framegraph;
 pub mod helpers;
+pub mod hooks;
+pub mod instances;
 pub mod lighting;
+pub mod lines;
 pub mod meshes;
+pub mod mirror;
 pub mod models;
+pub mod profiler;
 pub mod scene;
 pub mod screen;
+pub mod scripting;
 pub mod shaders;
 pub mod spatial;
+pub mod stats;
 pub mod systems;
 pub mod textures;
+pub mod thumbnails;
 pub mod utils;
+pub mod vfs;
+
+const SHADERS_DIR: &str = "./src/shaders";
+const CONFIG_FILE: &str = "./tungus.cfg";
+const TEXTURES_DIR: &str = "./src/resources/textures";
+const MODELS_DIR: &str = "./src/resources/models";
+const SCRIPTS_DIR: &str = "./src/resources/scripts";
+const SCENES_DIR: &str = "./src/resources/scenes";
+const ASSET_BUNDLE: &str = "./assets.pak";
 
-// const SHADERS: &str = "./src/shaders/"
-const REGULAR_VERT_SHADER: &str = "./src/shaders/regular_vert_shader.vs";
-const OBJECT_FRAG_SHADER: &str = "./src/shaders/object_frag_shader.fs";
-const DEBUG_GEO_SHADER: &str = "./src/shaders/debug_geo_shader.gs";
-const DEBUG_FRAG_SHADER: &str = "./src/shaders/debug_frag_shader.fs";
-const BUFFER_FRAG_SHADER: &str = "./src/shaders/buffer_frag_shader.fs";
-const SCREEN_VERT_SHADER: &str = "./src/shaders/screen_vert_shader.vs";
-const SCREEN_FRAG_SHADER: &str = "./src/shaders/screen_frag_shader.fs";
-const SKYBOX_VERT_SHADER: &str = "./src/shaders/skybox_vert_shader.vs";
-const SKYBOX_FRAG_SHADER: &str = "./src/shaders/skybox_frag_shader.fs";
-
-const WALL_TEXTURE: &str = "./src/resources/textures/wall.jpg";
-const CONTAINER_TEXTURE: &str = "./src/resources/textures/container2.png";
-const CONTAINER_SPECULAR: &str = "./src/resources/textures/container2_specular.png";
-const FACE_TEXTURE: &str = "./src/resources/textures/awesomeface.png";
-const GRASS_TEXTURE: &str = "./src/resources/textures/grass.png";
-const LAMP_TEXTURE: &str = "./src/resources/textures/glowstone.png";
-const WINDOW_TEXTURE: &str = "./src/resources/textures/window_diff.png";
-const WINDOW_SPECULAR: &str = "./src/resources/textures/window_spec.png";
-
-const ABSTRACT_CUBE: &str = "./src/resources/models/cube/untitled.obj";
-const ROCK_1: &str = "./src/resources/models/rocks/rock.obj";
+const REGULAR_VERT_SHADER: &str = "shaders/regular_vert_shader.vs";
+const OBJECT_FRAG_SHADER: &str = "shaders/object_frag_shader.fs";
+const DEBUG_GEO_SHADER: &str = "shaders/debug_geo_shader.gs";
+const DEBUG_FRAG_SHADER: &str = "shaders/debug_frag_shader.fs";
+const BUFFER_FRAG_SHADER: &str = "shaders/buffer_frag_shader.fs";
+const SCREEN_VERT_SHADER: &str = "shaders/screen_vert_shader.vs";
+const SCREEN_FRAG_SHADER: &str = "shaders/screen_frag_shader.fs";
+const SKYBOX_VERT_SHADER: &str = "shaders/skybox_vert_shader.vs";
+const SKYBOX_FRAG_SHADER: &str = "shaders/skybox_frag_shader.fs";
+const LINE_VERT_SHADER: &str = "shaders/line_vert_shader.vs";
+const LINE_FRAG_SHADER: &str = "shaders/line_frag_shader.fs";
+
+const WALL_TEXTURE: &str = "textures/wall.jpg";
+const PLANKS_TEXTURE: &str = "textures/container.jpg";
+const CONTAINER_TEXTURE: &str = "textures/container2.png";
+const CONTAINER_SPECULAR: &str = "textures/container2_specular.png";
+const FACE_TEXTURE: &str = "textures/awesomeface.png";
+const GRASS_TEXTURE: &str = "textures/grass.png";
+const LAMP_TEXTURE: &str = "textures/glowstone.png";
+const WINDOW_TEXTURE: &str = "textures/window_diff.png";
+const WINDOW_SPECULAR: &str = "textures/window_spec.png";
+
+const ABSTRACT_CUBE: &str = "models/cube/untitled.obj";
+const ROCK_1: &str = "models/rocks/rock.obj";
+
+const SCENE_FILE: &str = "scenes/demo.scene";
+const SCENE_SCRIPTS: [&str; 1] = ["scripts/orbiting_light.rhai"]; // unless the scene file says
 
 const SKYBOX_FACES: [&str; 6] = [
-    "./src/resources/textures/skybox/right.jpg",
-    "./src/resources/textures/skybox/left.jpg",
-    "./src/resources/textures/skybox/top.jpg",
-    "./src/resources/textures/skybox/bottom.jpg",
-    "./src/resources/textures/skybox/front.jpg",
-    "./src/resources/textures/skybox/back.jpg",
+    "textures/skybox/right.jpg",
+    "textures/skybox/left.jpg",
+    "textures/skybox/top.jpg",
+    "textures/skybox/bottom.jpg",
+    "textures/skybox/front.jpg",
+    "textures/skybox/back.jpg",
 ];
 
 const WINDOW_TITLE: &str = "Tungus";
 const WINDOW_SIZE: (u32, u32) = (600, 600);
 
+const CAPTURE_DIR: &str = "./captures/skybox";
+const CAPTURE_SIZE: u32 = 512;
+const PROFILE_TRACE: &str = "./captures/profile.json";
+const THUMBNAIL_DIR: &str = "./captures/thumbnails";
+const COLOR_SPACE_SHEET: &str = "./captures/color_spaces.png";
+const FRAME_GRAPH: &str = "./captures/frame_graph.dot";
+const THUMBNAIL_SIZE: u32 = 128;
+
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 100.0;
+const REVERSED_Z: bool = true;
+
 const INSTANCES: usize = 1000;
+const SPAWN_DISTANCE: f32 = 3.0;
+const SPAWN_FADE_TIME: f32 = 0.5;
+const HIGHLIGHT_COLOR: Vec3 = Vec3::new(1.0, 0.85, 0.6);
+const HIGHLIGHT_PERIOD: f32 = 2.0;
+const SCATTER_ATTEMPTS: u32 = 20;
+const FRAMING_MARGIN: f32 = 0.1;
+const GROUND_SIZE: f32 = 40.0;
+const GROUND_HEIGHT: f32 = -3.0;
+const GROUND_TILING: f32 = 10.0;
+const SCENE_TRANSITION: TransitionKind = TransitionKind::Fade;
+const SCENE_TRANSITION_TIME: f32 = 0.75;
+const ROCK_DRAW_DISTANCE: f32 = 60.0;
+const ROCK_FADE_DISTANCE: f32 = 10.0;
+const LIGHT_CULL_DISTANCE: f32 = 50.0;
+const UBO_BLOCKS: usize = 64; // draws per frame before the matrices buffer has to grow
 
 const INPUT_POLL_INTERVAL: Duration = Duration::from_micros(2000);
 
+fn init_vfs() {
+    vfs::mount_dir("shaders", SHADERS_DIR);
+    vfs::mount_dir("textures", TEXTURES_DIR);
+    vfs::mount_dir("models", MODELS_DIR);
+    vfs::mount_dir("scripts", SCRIPTS_DIR);
+    vfs::mount_dir("scenes", SCENES_DIR);
+    // a bundle shadows the loose files, so it can ship everything or just overrides
+    if Path::new(ASSET_BUNDLE).is_file() {
+        if let Err(e) = vfs::mount_archive("", ASSET_BUNDLE) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
 fn init_shaders() -> HashMap<&'static str, ShaderProgram> {
     let mut shader_map = HashMap::new();
     shader_map.insert(
         "model",
         ShaderProgram::from_vert_frag(REGULAR_VERT_SHADER, OBJECT_FRAG_SHADER).unwrap(),
     );
+    // for cameras with logarithmic depth, which has to be written per fragment
+    shader_map.insert(
+        "model_log_depth",
+        ShaderProgram::from_vert_frag_with_defines(
+            REGULAR_VERT_SHADER,
+            OBJECT_FRAG_SHADER,
+            &["LOG_DEPTH"],
+        )
+        .unwrap(),
+    );
     shader_map.insert(
         "debug",
         ShaderProgram::from_vert_geo_frag(REGULAR_VERT_SHADER, DEBUG_GEO_SHADER, DEBUG_FRAG_SHADER)
@@ -119,9 +225,47 @@ fn init_shaders() -> HashMap<&'static str, ShaderProgram> {
         "skybox",
         ShaderProgram::from_vert_frag(SKYBOX_VERT_SHADER, SKYBOX_FRAG_SHADER).unwrap(),
     );
+    shader_map.insert(
+        "lines",
+        ShaderProgram::from_vert_frag(LINE_VERT_SHADER, LINE_FRAG_SHADER).unwrap(),
+    );
     shader_map
 }
 
+// Shaders and the meshes drawn with them have to agree on vertex layout, otherwise they render
+// garbage without any GL error
+fn validate_layouts(
+    shaders: &HashMap<&'static str, ShaderProgram>,
+    state: &SceneState,
+    canvas: &SceneObject,
+    lines: &DebugLines,
+) -> Result<(), String> {
+    let mut pairs = vec![];
+    for object in &state.objects {
+        for name in ["model", "model_log_depth", "debug", "outline"] {
+            pairs.push((name, object.attribute_layout()));
+        }
+    }
+    pairs.push(("skybox", state.skybox.attribute_layout()));
+    pairs.push(("screen", canvas.attribute_layout()));
+    pairs.push(("lines", lines.attribute_layout()));
+    for (name, layout) in pairs {
+        shaders[name]
+            .validate_attributes(&layout)
+            .map_err(|e| format!("Vertex layout mismatch in the {} shader:\n{}", name, e))?;
+    }
+    // GL ignores uniforms a program doesn't have, so a dithered material would quietly blend
+    for name in ["model", "model_log_depth"] {
+        if !shaders[name].has_uniform("material.dithered") {
+            return Err(format!(
+                "The {} shader doesn't read material.dithered",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn init_sdl() -> SDL {
     let sdl = SDL::init(InitFlags::Everything).expect("couldn't start SDL");
     sdl.gl_set_attribute(SdlGlAttr::MajorVersion, 3).unwrap();
@@ -139,7 +283,7 @@ fn init_glwindow(sdl: &SDL) -> GlWindow {
             WindowPosition::XY(500, 50),
             WINDOW_SIZE.0,
             WINDOW_SIZE.1,
-            WindowFlags::Shown,
+            WindowFlags::Shown | WindowFlags::AllowHighDPI,
         )
         .expect("couldn't make a window and context");
     win.set_swap_interval(SwapInterval::Vsync);
@@ -152,26 +296,26 @@ fn init_glwindow(sdl: &SDL) -> GlWindow {
 }
 
 fn init_lighting(camera: &Camera) -> Lighting {
-    let ambient = vec3(0.2, 0.2, 0.2);
-    let diffuse = vec3(1.0, 1.0, 1.0);
-    let specular = vec3(1.0, 1.0, 1.0);
     let attenuation = vec3(1.0, 0.5, 0.25);
 
-    let sun = DirectionalLight::new(vec3(0.5, -1.0, 0.5), ambient, diffuse, specular);
+    let sun = DirectionalLight::from_temperature(vec3(0.5, -1.0, 0.5), 5800.0, 1000.0);
 
-    let mut lamps: [PointLight; 4] =
-        [PointLight::new(vec3(0.0, 0.0, 0.0), ambient, diffuse, specular, attenuation); 4];
-    lamps[0].pos = vec3(0.0, 2.0, 0.0);
-    lamps[1].pos = vec3(-1.0, -2.0, -1.0);
-    lamps[2].pos = vec3(1.0, 0.0, 1.0);
-    lamps[3].pos = vec3(0.0, -10.0, 0.0);
+    let lamp_positions = [
+        vec3(0.0, 2.0, 0.0),
+        vec3(-1.0, -2.0, -1.0),
+        vec3(1.0, 0.0, 1.0),
+        vec3(0.0, -10.0, 0.0),
+    ];
+    let lamps: Vec<PointLight> = lamp_positions
+        .iter()
+        .map(|pos| PointLight::from_temperature(*pos, 3000.0, 1250.0, attenuation))
+        .collect();
 
-    let flashlight = Spotlight::new(
+    let flashlight = Spotlight::from_temperature(
         camera.get_pos(),
         camera.get_dir(),
-        ambient / 2.0,
-        diffuse / 2.0,
-        specular / 2.0,
+        4500.0,
+        625.0,
         attenuation,
         15.0_f32.to_radians(),
         20.0_f32.to_radians(),
@@ -179,26 +323,49 @@ fn init_lighting(camera: &Camera) -> Lighting {
 
     Lighting {
         dir: sun,
-        point: Vec::from(lamps),
+        point: lamps,
         spot: flashlight,
+        shadows: ShadowSettings::new(),
+        cull_distance: LIGHT_CULL_DISTANCE,
     }
 }
 
-fn init_obj_list(lamps: &Vec<PointLight>) -> Vec<SceneObject> {
+// The objects from `init_obj_list` the main loop keeps working on
+struct SceneHandles {
+    boxes: ObjectHandle,      // where the editor places boxes
+    lamps: Vec<ObjectHandle>, // a cube per point light, in the same order
+}
+
+fn init_obj_list(lamps: &Vec<PointLight>, rocks: usize) -> (Vec<SceneObject>, SceneHandles) {
     let mut objects_list: Vec<SceneObject> = vec![];
 
     let rock_model = Model::new(Path::new(ROCK_1));
     let mut rock_object = SceneObject::from(rock_model);
     rock_object.scale(&vec3(0.1, 0.1, 0.1));
-    rock_object.add_instances(INSTANCES);
-    for i in 0..INSTANCES {
-        RandomTransform::position(
+    rock_object.add_instances(rocks);
+    let rock_bounds = rock_object.get_bounding_sphere();
+    let mut placed = SphereGrid::new(rock_bounds.radius * 2.0);
+    let mut overlapping = 0;
+    for i in 0..rocks {
+        let clear = RandomTransform::position_clear(
             rock_object.get_instance_mut(i as isize),
+            &rock_bounds,
+            &mut placed,
+            SCATTER_ATTEMPTS,
             (-100.0, 100.0),
             (-100.0, 100.0),
             (-100.0, 100.0),
         );
+        overlapping += !clear as usize;
+    }
+    if overlapping > 0 {
+        println!(
+            "{} rocks couldn't be placed clear of the others",
+            overlapping
+        );
     }
+    rock_object.set_draw_distance(ROCK_DRAW_DISTANCE, ROCK_FADE_DISTANCE);
+    rock_object.set_instance_culling(true);
     objects_list.push(rock_object);
 
     let mut box_mesh = BasicMesh::cube(1.0);
@@ -213,8 +380,19 @@ fn init_obj_list(lamps: &Vec<PointLight>) -> Vec<SceneObject> {
         GL_CLAMP_TO_EDGE,
     );
     box_mesh.material = Material::new(vec![cont_tex], vec![cont_spec], 32.0);
+    box_mesh.material.set_reflections(0.3, 0.4);
+    // a decal layered over the wood where the face is opaque
+    let face_tex = Texture2D::setup_new(
+        TextureType::Diffuse,
+        &Path::new(FACE_TEXTURE),
+        GL_CLAMP_TO_EDGE,
+    );
+    box_mesh
+        .material
+        .add_diffuse_map(face_tex, MapBlend::new(BlendOp::Mask, 0.6));
     let mut box_object = SceneObject::from(box_mesh);
     box_object.set_outline(vec4(0.5, 0.2, 0.3, 1.0));
+    let boxes = box_object.get_handle();
     objects_list.push(box_object);
 
     let mut wind_mesh = BasicMesh::square(1.0);
@@ -229,6 +407,15 @@ fn init_obj_list(lamps: &Vec<PointLight>) -> Vec<SceneObject> {
         GL_CLAMP_TO_EDGE,
     );
     wind_mesh.material = Material::new(vec![wind_tex], vec![wind_spec], 32.0);
+    // dithered rather than blended, since nothing sorts it against the objects behind it
+    wind_mesh.material.set_alpha_mode(AlphaMode::Dither);
+    // the stained glass lets the light behind it through, tinted red
+    wind_mesh.material.set_transmission(Transmission {
+        strength: 0.8,
+        color: vec3(1.0, 0.6, 0.6),
+        thickness: 0.1,
+        map: None,
+    });
     let mut wind_object = SceneObject::from(wind_mesh);
     wind_object
         .get_instance_mut(0)
@@ -242,49 +429,198 @@ fn init_obj_list(lamps: &Vec<PointLight>) -> Vec<SceneObject> {
         GL_CLAMP_TO_EDGE,
     );
     lamp_mesh.material = Material::new(vec![lamp_texture], vec![], 32.0);
-    let mut lamp_object = SceneObject::from(lamp_mesh.clone());
-    lamp_object.get_instance_mut(0).translate(&lamps[0].pos);
-    lamp_object.get_instance_mut(0).scale(&vec3(0.1, 0.1, 0.1));
-    lamp_object.add_instances(lamps.len() - 1);
-    for i in 1..lamps.len() {
-        lamp_object
-            .get_instance_mut(i as isize)
-            .translate(&lamps[i].pos);
-        lamp_object
-            .get_instance_mut(i as isize)
-            .scale(&vec3(0.1, 0.1, 0.1));
+    // one repeat per lamp, whatever the cube's own UVs look like
+    lamp_mesh.material.set_triplanar(Some(10.0));
+    // an object per lamp rather than an instance, so each one can take its light's color
+    let mut lamp_handles = vec![];
+    for lamp in lamps {
+        let mut lamp_object = SceneObject::from(lamp_mesh.clone());
+        let instance = lamp_object.get_instance_mut(0);
+        instance.translate(&lamp.pos);
+        instance.scale(&vec3(0.1, 0.1, 0.1));
+        lamp_handles.push(lamp_object.get_handle());
+        objects_list.push(lamp_object);
     }
-    objects_list.push(lamp_object);
 
-    objects_list
+    let mut ground_mesh = BasicMesh::square(GROUND_SIZE);
+    ground_mesh.material = init_ground_material();
+    let mut ground_object = SceneObject::from(ground_mesh);
+    let ground = ground_object.get_instance_mut(0);
+    ground.rotate(-90.0_f32.to_radians(), &vec3(1.0, 0.0, 0.0));
+    ground.translate(&vec3(0.0, GROUND_HEIGHT, 0.0));
+    objects_list.push(ground_object);
+
+    let handles = SceneHandles {
+        boxes,
+        lamps: lamp_handles,
+    };
+    (objects_list, handles)
+}
+
+// Stone and planks in wavy patches, from a mask made up on the spot
+fn init_ground_material() -> Material {
+    const MASK_SIZE: u32 = 64;
+    let mut mask = Vec::with_capacity((MASK_SIZE * MASK_SIZE * 4) as usize);
+    for y in 0..MASK_SIZE {
+        for x in 0..MASK_SIZE {
+            let (u, v) = (x as f32 / MASK_SIZE as f32, y as f32 / MASK_SIZE as f32);
+            let stone = 0.5 + 0.5 * (u * 12.0).sin() * (v * 9.0).cos();
+            mask.extend_from_slice(&[(stone * 255.0) as u8, ((1.0 - stone) * 255.0) as u8, 0, 0]);
+        }
+    }
+    let mask_texture = Texture2D::new(TextureType::Data);
+    mask_texture
+        .upload_rgba8(MASK_SIZE, MASK_SIZE, &mask)
+        .unwrap();
+    let layers = vec![
+        SplatLayer::load(Path::new(WALL_TEXTURE), None, GROUND_TILING),
+        SplatLayer::load(Path::new(PLANKS_TEXTURE), None, GROUND_TILING),
+    ];
+    let splat = SplatMap::new(mask_texture, layers, false).unwrap();
+    Material::from_splat_map(splat, vec![], 8.0)
+}
+
+fn init_crate_material() -> Material {
+    let cont_tex = Texture2D::setup_new(
+        TextureType::Diffuse,
+        &Path::new(CONTAINER_TEXTURE),
+        GL_CLAMP_TO_EDGE,
+    );
+    let cont_spec = Texture2D::setup_new(
+        TextureType::Specular,
+        &Path::new(CONTAINER_SPECULAR),
+        GL_CLAMP_TO_EDGE,
+    );
+    Material::new(vec![cont_tex], vec![cont_spec], 32.0)
+}
+
+// Previews of the materials things get spawned with
+fn export_thumbnails(state: &SceneState, shader: ShaderProgram, ubo: &UniformBuffer) {
+    let preview = match MaterialPreview::new(THUMBNAIL_SIZE) {
+        Ok(preview) => preview,
+        Err(e) => return eprintln!("{}", e),
+    };
+    let materials = match &state.spawn_prefab {
+        Prefab::Cube(_, material) | Prefab::Square(_, material) => vec![("spawn", material)],
+        Prefab::Model(_) => vec![],
+    };
+    for (name, material) in materials {
+        let path = Path::new(THUMBNAIL_DIR).join(format!("{}.png", name));
+        match preview.save(material, shader, ubo, &path) {
+            Ok(()) => println!("Material preview written to {}", path.display()),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+// Reports the color space of every texture the scene loaded and writes a sheet comparing the
+// suspicious ones with how they'd look in the other one, at the screen's gamma. The sheet comes
+// back as a texture to show over the frame.
+fn audit_textures(state: &SceneState, gamma: f32) -> Option<Texture2D> {
+    let spawn_material = match &state.spawn_prefab {
+        Prefab::Cube(_, material) | Prefab::Square(_, material) => Some(material),
+        Prefab::Model(_) => None,
+    };
+    let materials = state
+        .objects
+        .iter()
+        .flat_map(SceneObject::get_materials)
+        .chain(spawn_material);
+    let checks = colorspace::audit(materials);
+    print!("{}", colorspace::report(&checks));
+    let sheet = match colorspace::comparison_sheet(&checks, gamma) {
+        Ok(Some(sheet)) => sheet,
+        Ok(None) => return None,
+        Err(e) => {
+            eprintln!("{}", e);
+            return None;
+        }
+    };
+    let path = Path::new(COLOR_SPACE_SHEET);
+    let rows = checks.iter().filter(|check| check.is_suspicious()).count();
+    match colorspace::export_sheet(&sheet, path) {
+        Ok(()) => println!("{} comparisons written to {}", rows, path.display()),
+        Err(e) => eprintln!("{}", e),
+    }
+    colorspace::sheet_texture(&sheet)
+        .map_err(|e| eprintln!("{}", e))
+        .ok()
+}
+
+// The passes of the frame about to be drawn, in the order `main` runs them
+fn dump_frame_graph(screen: &Screen, mirror: &MirrorSurface) {
+    let mut graph = FrameGraph::new();
+    let described = mirror
+        .describe_passes(&mut graph)
+        .and_then(|_| screen.describe_passes(&mut graph, &[MIRROR_TEXTURE]));
+    let path = Path::new(FRAME_GRAPH);
+    match described.and_then(|_| graph.export(path)) {
+        Ok(()) => println!(
+            "Frame graph ({}, {} targets) written to {}",
+            graph.pass_names().join(" -> "),
+            graph.get_resources().len(),
+            path.display()
+        ),
+        Err(e) => eprintln!("{}", e),
+    }
 }
 
 fn init_skybox() -> Skybox {
     let mut cube_map = CubeMap::new(TextureType::Diffuse);
     cube_map.load(SKYBOX_FACES);
+    cube_map.bind();
     cube_map.set_wrapping(GL_CLAMP_TO_EDGE);
-    cube_map.set_filters(GL_LINEAR, GL_LINEAR);
+    // Mips are what blurry reflections sample from
+    cube_map.set_filters(GL_LINEAR_MIPMAP_LINEAR, GL_LINEAR);
     let skybox = Skybox::new(cube_map);
     skybox
 }
 
+fn init_mirror(size: (u32, u32)) -> MirrorSurface {
+    let mut mirror = MirrorSurface::new(2.0, size, vec4(0.1, 0.1, 0.1, 1.0));
+    mirror.get_object_mut().translate(&vec3(0.0, 0.0, 3.0));
+    mirror
+}
+
 fn init_random_transforms(quantity: usize) -> Vec<RandomTransform> {
     let mut rts = vec![];
     for _ in 0..quantity {
         let mut rng = rand::thread_rng();
         rts.push(RandomTransform::continuous(
-            0.1,
-            0.1,
-            rng.gen_range(0..=1000),
-            rng.gen_range(0..=1000),
+            6.0,
+            6.0,
+            rng.gen_range(0.0..=16.0),
+            rng.gen_range(0.0..=16.0),
         ));
     }
     rts
 }
 
+// Hands the camera its signals, except for key presses while the light editor has the movement keys
+struct CameraInput {
+    camera: Rc<RefCell<CameraController>>,
+    light_edit: Rc<RefCell<LightEditController>>,
+}
+
+impl Slot for CameraInput {
+    fn on_signal(&mut self, signal: SignalType) {
+        match signal {
+            SignalType::KeyPressed(_) if (*self.light_edit).borrow().is_editing() => {
+                (*self.camera).borrow_mut().stop()
+            }
+            _ => (*self.camera).borrow_mut().on_signal(signal),
+        }
+    }
+}
+
 struct ControllerHub<'a> {
     pub camera: Rc<RefCell<CameraController>>,
+    _camera_input: Rc<RefCell<CameraInput>>, // held for the handler, which only has a weak one
+    pub clock: Rc<RefCell<ClockController>>,
+    pub editor: Rc<RefCell<EditorController>>,
     pub flashlight: Rc<RefCell<FlashlightController>>,
+    pub lights: Rc<RefCell<LightSwitchController>>,
+    pub light_edit: Rc<RefCell<LightEditController>>,
     pub program: Rc<RefCell<ProgramController>>,
     pub screen: Rc<RefCell<ScreenController>>,
     pub scene: Rc<RefCell<SceneController>>,
@@ -295,16 +631,33 @@ struct ControllerHub<'a> {
 impl<'a> ControllerHub<'a> {
     pub fn init(sdl: &'a SDL) -> Self {
         let camera_controller = CameraController::new();
+        let clock_controller = ClockController::new();
+        let editor_controller = EditorController::new();
         let flashlight_controller = FlashlightController::new();
+        let light_switch_controller = LightSwitchController::new();
+        let light_edit_controller = LightEditController::new();
         let program_controller = ProgramController::new();
         let screen_controller = ScreenController::new();
         let scene_controller = SceneController::new();
         let rt_controller = RTController::new();
+        let camera_input = Rc::new(RefCell::new(CameraInput {
+            camera: camera_controller.clone(),
+            light_edit: light_edit_controller.clone(),
+        }));
         let mut signal_handler = SignalHandler::new(&sdl);
+        // ahead of the camera, which asks it whether it's editing
+        signal_handler
+            .connect(unsafe { Weak::from_raw(Rc::downgrade(&light_edit_controller).into_raw()) });
+        let camera_slot: Rc<RefCell<dyn Slot>> = camera_input.clone();
+        signal_handler.connect(Rc::downgrade(&camera_slot));
+        signal_handler
+            .connect(unsafe { Weak::from_raw(Rc::downgrade(&clock_controller).into_raw()) });
         signal_handler
-            .connect(unsafe { Weak::from_raw(Rc::downgrade(&camera_controller).into_raw()) });
+            .connect(unsafe { Weak::from_raw(Rc::downgrade(&editor_controller).into_raw()) });
         signal_handler
             .connect(unsafe { Weak::from_raw(Rc::downgrade(&flashlight_controller).into_raw()) });
+        signal_handler
+            .connect(unsafe { Weak::from_raw(Rc::downgrade(&light_switch_controller).into_raw()) });
         signal_handler
             .connect(unsafe { Weak::from_raw(Rc::downgrade(&program_controller).into_raw()) });
         signal_handler
@@ -314,7 +667,12 @@ impl<'a> ControllerHub<'a> {
         signal_handler.connect(unsafe { Weak::from_raw(Rc::downgrade(&rt_controller).into_raw()) });
         ControllerHub {
             camera: camera_controller,
+            _camera_input: camera_input,
+            clock: clock_controller,
+            editor: editor_controller,
             flashlight: flashlight_controller,
+            lights: light_switch_controller,
+            light_edit: light_edit_controller,
             program: program_controller,
             screen: screen_controller,
             scene: scene_controller,
@@ -327,7 +685,9 @@ impl<'a> ControllerHub<'a> {
         &'a self,
         cycle_time: f32,
         camera: &mut Camera,
-        flashlight: &mut Spotlight,
+        clock: &mut Clock,
+        editor: &mut Editor,
+        lighting: &mut Lighting,
         prog: &mut Program,
         screen: &mut Screen,
         params: &mut SceneParameters,
@@ -337,9 +697,23 @@ impl<'a> ControllerHub<'a> {
             .update_control_parameters(&mut |controller: &mut CameraController| {
                 controller.set_speeds(cycle_time);
             });
+        self.light_edit
+            .update_control_parameters(&mut |controller: &mut LightEditController| {
+                controller.set_speed(cycle_time);
+            });
         (*self.handler).borrow_mut().wait_event();
         self.camera.process_signals(camera);
-        self.flashlight.process_signals(flashlight);
+        self.clock.process_signals(clock);
+        self.editor.process_signals(editor);
+        // the editor needs a cursor to click with, the camera needs the mouse
+        let mut handler = (*self.handler).borrow_mut();
+        if handler.is_mouse_captured() == editor.active {
+            handler.set_mouse_captured(!editor.active);
+        }
+        drop(handler);
+        self.flashlight.process_signals(&mut lighting.spot);
+        self.lights.process_signals(lighting);
+        self.light_edit.process_signals(lighting);
         self.program.process_signals(prog);
         self.screen.process_signals(screen);
         self.scene.process_signals(params);
@@ -361,55 +735,155 @@ impl App {
         unsafe {
             glEnable(GL_MULTISAMPLE);
             glEnable(GL_DEPTH_TEST);
-            glEnable(GL_STENCIL_TEST);
             glEnable(GL_BLEND);
             glEnable(GL_CULL_FACE);
+            // so the blurry mip levels of environment maps don't show the face edges
+            glEnable(GL_TEXTURE_CUBE_MAP_SEAMLESS);
             glBlendFunc(GL_SRC_ALPHA, GL_ONE_MINUS_SRC_ALPHA);
-            glStencilOp(GL_KEEP, GL_KEEP, GL_REPLACE);
         }
+        // objects mark where they're drawn, for outlines
+        data::set_stencil(StencilState::write(OBJECT_STENCIL));
 
         let _ = sdl.set_relative_mouse_mode(true);
 
         App { sdl, win }
     }
+
+    // Pixels per window point. The drawable only outgrows the window on displays that scale, and
+    // it follows the window from one monitor to another.
+    pub fn display_scale(&self) -> f32 {
+        let (width, _) = self.win.get_drawable_size();
+        let (window_width, _) = self.win.get_window_size();
+        width as f32 / window_width.max(1) as f32
+    }
+}
+
+// Everything that makes up the scene, as opposed to the window, renderer and tools around it.
+// Rebuilt from scratch and the scene file when the scene is reloaded.
+struct SceneState {
+    pub objects: Vec<SceneObject>,
+    pub lighting: Lighting,
+    pub skybox: Skybox,
+    pub scripts: ScriptRuntime,
+    pub spawn_prefab: Prefab,
+    pub spawned: Vec<ObjectHandle>,
+    pub rts: Vec<RandomTransform>,
+    pub animations: Vec<(ObjectHandle, MaterialAnimation)>,
+    pub boxes: ObjectHandle,
+    pub lamps: Vec<ObjectHandle>, // one per point light
+}
+
+impl SceneState {
+    // Settings missing from `scene`, or the whole file, fall back to the built-in demo
+    pub fn load(camera: &Camera, scene: &Config) -> Self {
+        let rocks = scene.get("rocks").unwrap_or(INSTANCES);
+        let lighting = init_lighting(camera);
+        let (objects, handles) = init_obj_list(&lighting.point, rocks);
+        let crate_material = init_crate_material();
+        let spawn_size = scene.get("spawn.size").unwrap_or(0.5);
+        let spawn_prefab = Prefab::Cube(spawn_size, crate_material.clone());
+        let mut scripts = ScriptRuntime::new(crate_material);
+        let script_list = scene
+            .get::<String>("scripts")
+            .unwrap_or_else(|| SCENE_SCRIPTS.join(","));
+        let script_paths = script_list.split(',').map(str::trim);
+        for script in script_paths.filter(|path| !path.is_empty()) {
+            if let Err(e) = scripts.load(Path::new(script)) {
+                eprintln!("{}", e);
+            }
+        }
+        // the crate pulses to stand out
+        let period = scene.get("highlight.period").unwrap_or(HIGHLIGHT_PERIOD);
+        let animations = vec![(
+            handles.boxes,
+            MaterialAnimation::pulse(HIGHLIGHT_COLOR, 0.3, period),
+        )];
+        SceneState {
+            boxes: handles.boxes,
+            lamps: handles.lamps,
+            objects,
+            lighting,
+            skybox: init_skybox(),
+            scripts,
+            spawn_prefab,
+            spawned: vec![],
+            rts: init_random_transforms(rocks),
+            animations,
+        }
+    }
+
+    // Keeps the lamp cubes on their lights and in their colors, which can be changed at runtime
+    pub fn sync_lamp_gizmos(&mut self) {
+        for (light, handle) in self.lighting.point.iter().zip(&self.lamps) {
+            let Some(lamp) = self.objects.iter_mut().find(|o| o.get_handle() == *handle) else {
+                continue;
+            };
+            if lamp.get_instance(0).get_position() != light.pos {
+                lamp.get_instance_mut(0).set_position(&light.pos);
+            }
+            // tinted with the light's color and glowing as bright as it is, dark while it's off
+            let intensity = light.get_intensity();
+            let surface = lamp.get_surface_mut();
+            if intensity > 0.0 {
+                surface.tint = light.diff / intensity;
+            }
+            surface.emissive = light.get_diff().max();
+        }
+    }
 }
 
 fn main() {
     // System initialization
     let app = App::init();
+    init_vfs();
+    debug::load(&app.win);
+    let mut frame_capture = FrameCapture::new();
 
     let mut main_camera = Camera::new(vec3(0.0, 0.0, -2.0));
+    main_camera.set_clip_planes(NEAR_PLANE, FAR_PLANE);
+    // reversed-Z needs clip control to be worth it, logarithmic depth covers for it otherwise
+    let reversed_z = REVERSED_Z && data::load_clip_control(&app.win);
+    data::set_reversed_depth(reversed_z);
+    main_camera.set_reversed_z(reversed_z);
+    main_camera.set_logarithmic_depth(REVERSED_Z && !reversed_z);
 
-    let mut lighting = init_lighting(&main_camera);
+    let mut state = SceneState::load(&main_camera, &Config::load_asset(SCENE_FILE));
+    let mut outgoing: Option<SceneState> = None; // previous scene, while transitioning out of it
+    let mut editor = Editor::new();
+    let mut debug_lines = DebugLines::new();
 
     let matrices_ubo = UniformBuffer::new(0).unwrap();
-    matrices_ubo.allocate(240);
+    matrices_ubo.allocate(UBO_BLOCKS);
 
-    // Scene objects initialization
-    let skybox = init_skybox();
-    let mut objects_list: Vec<SceneObject> = init_obj_list(&lighting.point);
     let canvas = SceneObject::from(Canvas::new());
-    let mirror = SceneObject::from(Canvas::new());
 
     let shaders = init_shaders();
-
-    let mut rts = init_random_transforms(INSTANCES);
+    if let Err(e) = validate_layouts(&shaders, &state, &canvas, &debug_lines) {
+        panic!("{}", e);
+    }
+    let object_shader = if main_camera.is_logarithmic_depth() {
+        shaders["model_log_depth"]
+    } else {
+        shaders["model"]
+    };
 
     // Screen initialization
-    let mut screen = Screen::new(
+    let mut screen = Screen::with_parameters(
         canvas,
         vec4(0.1, 0.1, 0.1, 1.0),
         WINDOW_SIZE,
         shaders["screen"],
-        matrices_ubo,
-    );
-    let mut mirrored_screen = Screen::new(
-        mirror,
-        vec4(0.1, 0.1, 0.1, 1.0),
-        WINDOW_SIZE,
-        shaders["screen"],
-        matrices_ubo,
+        matrices_ubo.clone(),
+        ScreenParameters {
+            motion_vectors: true,
+            ..ScreenParameters::new()
+        },
     );
+    // for tools and effects drawn in between the built-in passes
+    let pass_hooks = Rc::new(PassHooks::new());
+    screen.set_hooks(pass_hooks.clone());
+    screen.set_display_scale(app.display_scale());
+    let mut mirror = init_mirror(screen.get_size());
 
     ///////////////////////////////////////////////////////////////////////////////////////////////
     // This has an error for some reason
@@ -423,7 +897,9 @@ fn main() {
 
     ///////////////////////////////////////////////////////////////////////////////////////////////
     let control_hub = ControllerHub::init(&app.sdl);
-    (*control_hub.rt).borrow_mut().add_rts(&rts);
+    (*control_hub.rt).borrow_mut().add_rts(&state.rts);
+    let mut config = Config::load(CONFIG_FILE);
+    (*control_hub.screen).borrow_mut().load_settings(&config);
 
     // Program loop
     let mut program_loop = Program {
@@ -434,84 +910,294 @@ fn main() {
 
     elapsed_time = 0;
     let mut cycle_time;
+    let mut clock = Clock::new();
 
     let mut scene_params = SceneParameters::init();
 
-    let mut total_update: Duration = Duration::new(0, 0);
-    let mut total_instances: Duration = Duration::new(0, 0);
-    let mut total_draw: Duration = Duration::new(0, 0);
-    let mut total_cycles: u32 = 0;
+    let frame_stats = Rc::new(RefCell::new(FrameStats::new()));
+    (*frame_stats).borrow_mut().dump_on_spike = true;
+    let event_bus = EventBus::new();
+    let stats_subscriber: Rc<RefCell<dyn Subscriber>> = frame_stats.clone();
+    event_bus.subscribe(Rc::downgrade(&stats_subscriber));
 
     let mut last_update = Instant::now();
+    let mut previous_view_projection = None;
+    let mut projection_watch = ProjectionWatch::new();
 
     while program_loop.loop_active {
-        let start_of_frame = Instant::now();
-        total_cycles += 1;
+        profiler::begin_frame();
 
         previous_time = elapsed_time;
         elapsed_time = app.sdl.get_ticks();
         cycle_time = (elapsed_time - previous_time) as f32;
 
-        let start_update = Instant::now();
         if last_update.elapsed() >= INPUT_POLL_INTERVAL {
+            profile_scope!("Control update");
             control_hub.update(
                 cycle_time,
                 &mut main_camera,
-                &mut lighting.spot,
+                &mut clock,
+                &mut editor,
+                &mut state.lighting,
                 &mut program_loop,
                 &mut screen,
                 &mut scene_params,
-                &mut rts,
+                &mut state.rts,
             );
             last_update = Instant::now();
         }
-        total_update += start_update.elapsed();
+        // the window may have moved to a monitor with another scale
+        let display_scale = app.display_scale();
+        if display_scale != screen.get_display_scale() {
+            screen.set_display_scale(display_scale);
+            let (width, height) = screen.get_drawable_size();
+            event_bus.publish(EngineEvent::WindowResized(width, height));
+        }
+        // the mirror samples its target in screen space, so it has to keep up with the screen
+        if mirror.get_size() != screen.get_size() {
+            mirror = init_mirror(screen.get_size());
+        }
+        // motion vectors against another projection would smear the whole frame. Scenes are drawn
+        // with a square aspect ratio, see `Scene::projection`.
+        if projection_watch.changed(&main_camera, 1.0) {
+            previous_view_projection = None;
+        }
+
+        // the camera and screen transitions run on real time, the scene on the clock's
+        clock.advance(cycle_time / 1000.0);
+        let dt = clock.get_delta();
+        {
+            profile_scope!("Scripts");
+            state.scripts.update(&state.lighting, clock.get_time(), dt);
+            state.scripts.apply(&mut state.objects, &mut state.lighting);
+        }
+        state.sync_lamp_gizmos();
+
+        state.lighting.spot.pos = main_camera.get_pos();
+        state.lighting.spot.dir = main_camera.get_dir();
+
+        let rts = &state.rts;
+        {
+            profile_scope!("Instance move");
+            state.objects[0].update_instances(|i, inst| {
+                rts[i].rotate(inst, dt);
+                rts[i].translate(inst, dt);
+            });
+        }
+
+        // objects that aren't spawned yet start animating once they are
+        for (handle, animation) in state.animations.iter_mut() {
+            if let Some(object) = state.objects.iter_mut().find(|o| o.get_handle() == *handle) {
+                animation.update(dt, object);
+            }
+        }
+        state
+            .animations
+            .retain(|(_, animation)| !animation.is_finished());
 
-        lighting.spot.pos = main_camera.get_pos();
-        lighting.spot.dir = main_camera.get_dir();
+        if scene_params.frame_selection {
+            let framed = state
+                .objects
+                .iter()
+                .filter(|o| editor.get_selection().map_or(true, |s| o.get_handle() == s))
+                .filter_map(SceneObject::get_world_bounds)
+                .reduce(|a, b| a.union(&b));
+            if let Some(aabb) = framed {
+                main_camera.frame_aabb(&aabb, FRAMING_MARGIN);
+            }
+            scene_params.frame_selection = false;
+        }
 
-        let start_instances = Instant::now();
-        for i in 0..INSTANCES {
-            let inst = objects_list[0].get_instance_mut(i.try_into().unwrap());
-            rts[i].rotate(inst);
-            rts[i].translate(inst);
+        let view_projection = main_camera.projection(1.0) * main_camera.look_at();
+        for position in editor.update(
+            &view_projection,
+            &main_camera.get_pos(),
+            screen.get_drawable_size(),
+            screen.get_display_scale(),
+            &state.objects,
+            &event_bus,
+        ) {
+            // placed boxes rest on the editor plane
+            let Some(boxes) = state
+                .objects
+                .iter_mut()
+                .find(|o| o.get_handle() == state.boxes)
+            else {
+                continue;
+            };
+            boxes.add_instance();
+            boxes
+                .get_instance_mut(-1)
+                .translate(&(position + vec3(0.0, 0.5, 0.0)));
         }
-        total_instances += start_instances.elapsed();
+        editor.draw(&mut debug_lines, &main_camera.get_pos());
 
         let mut scene = Scene {
-            objects: objects_list.clone(),
-            skyboxes: &vec![&skybox],
-            object_shader: shaders["model"],
+            objects: state.objects.clone(),
+            skyboxes: &vec![&state.skybox],
+            object_shader,
             skybox_shader: shaders["skybox"],
             outline_shader: shaders["outline"],
             debug_shader: shaders["debug"],
+            line_shader: shaders["lines"],
             camera: main_camera,
-            lighting: &lighting,
+            lighting: &state.lighting,
             params: scene_params,
+            previous_view_projection,
+            lines: (!debug_lines.is_empty()).then_some(&debug_lines),
+            spawns: SpawnQueue::new(),
+            hooks: Some(&pass_hooks),
+            culling: CullingStats::default(),
         };
 
-        shaders["model"].use_program();
-        shaders["model"].set_1f("time", app.sdl.get_ticks() as f32 / 500.0);
+        (*frame_stats).borrow_mut().record_memory(
+            scene::host_memory(&state.objects),
+            scene.host_memory().instances,
+        );
+
+        if scene.params.spawn_object {
+            let position = main_camera.get_pos() + main_camera.get_dir() * SPAWN_DISTANCE;
+            let handle = scene.spawn(state.spawn_prefab.clone(), &translation(&position));
+            state.spawned.push(handle);
+            state
+                .animations
+                .push((handle, MaterialAnimation::fade_in(SPAWN_FADE_TIME)));
+            scene_params.spawn_object = false;
+        }
+        if scene.params.despawn_object {
+            if let Some(handle) = state.spawned.pop() {
+                scene.despawn(handle);
+                state.animations.retain(|(h, _)| *h != handle);
+            }
+            scene_params.despawn_object = false;
+        }
 
-        let start_draw = Instant::now();
+        let draw_scope = profiler::ProfileScope::new("Draw");
+        frame_capture.begin_frame(scene.params.capture_frame);
+        scene_params.capture_frame = false;
+        matrices_ubo.begin_frame();
+        matrices_ubo.set_frame_time(clock.get_time(), dt, clock.get_real_time());
+        if scene.params.capture_environment {
+            let position = scene.camera.get_pos();
+            match capture::capture_cubemap(
+                scene.borrow_mut(),
+                &matrices_ubo,
+                position,
+                CAPTURE_SIZE,
+                Path::new(CAPTURE_DIR),
+            ) {
+                Ok(()) => println!("Environment captured to {}", CAPTURE_DIR),
+                Err(e) => eprintln!("{}", e),
+            }
+            scene_params.capture_environment = false;
+        }
+        if let Some(previous) = &outgoing {
+            let mut outgoing_scene = Scene {
+                objects: previous.objects.clone(),
+                skyboxes: &vec![&previous.skybox],
+                object_shader,
+                skybox_shader: shaders["skybox"],
+                outline_shader: shaders["outline"],
+                debug_shader: shaders["debug"],
+                line_shader: shaders["lines"],
+                camera: main_camera,
+                lighting: &previous.lighting,
+                params: scene_params,
+                previous_view_projection,
+                lines: None,
+                spawns: SpawnQueue::new(),
+                hooks: None,
+                culling: CullingStats::default(),
+            };
+            screen.draw_outgoing(outgoing_scene.borrow_mut());
+        }
+        if scene.params.export_thumbnails {
+            export_thumbnails(&state, shaders["model"], &matrices_ubo);
+            scene_params.export_thumbnails = false;
+        }
+        if scene.params.audit_textures {
+            // a second press hides the sheet
+            let sheet = if screen.has_overlay() {
+                None
+            } else {
+                audit_textures(&state, screen.get_parameters().gamma)
+            };
+            screen.set_overlay(sheet);
+            scene_params.audit_textures = false;
+        }
+        if scene.params.dump_frame_graph {
+            dump_frame_graph(&screen, &mirror);
+            scene_params.dump_frame_graph = false;
+        }
+        mirror.render(scene.borrow_mut(), &matrices_ubo);
+        scene.objects.push(mirror.get_object().clone());
         screen.draw_on_framebuffer(scene.borrow_mut());
-        let mut mirrored_scene = scene.mirrored();
-        mirrored_screen.draw_on_framebuffer(mirrored_scene.borrow_mut());
-        mirrored_screen.draw_on_another(&screen, 0.3, vec2(0.5, 0.5));
         screen.draw_on_screen();
-        total_draw += start_draw.elapsed();
-
-        app.win.swap_window();
-        let fps = Duration::from_secs(1).div_duration_f32(start_of_frame.elapsed());
-        let average_update = total_update / total_cycles;
-        let average_instances = total_instances / total_cycles;
-        let average_draw = total_draw / total_cycles;
-        let mut info: String =
-            String::from(std::format!("Control update time: {average_update:?}\n"));
-        info += &std::format!("Instance move time: {average_instances:?}\n");
-        info += &std::format!("Draw time: {average_draw:?}\n");
-        info += &std::format!("FPS: {fps}\n");
-        info += "----------------------------------------";
-        std::println!("{info}");
+        drop(draw_scope);
+        (*frame_stats).borrow_mut().record_culling(scene.culling);
+
+        previous_view_projection = Some(scene.view_projection());
+        matrices_ubo.end_frame();
+        let outgoing_objects = outgoing.iter().flat_map(|previous| &previous.objects);
+        for object in scene.objects.iter().chain(outgoing_objects) {
+            object.end_frame();
+        }
+        scene
+            .spawns
+            .apply(&mut state.objects, &event_bus, clock.get_time());
+        state
+            .scripts
+            .spawns
+            .apply(&mut state.objects, &event_bus, clock.get_time());
+        if screen.advance_transition(cycle_time / 1000.0) {
+            outgoing = None;
+        }
+        if scene_params.reload_scene {
+            // the old scene stays around to fade out of. Nothing from the one before it, if any,
+            // is in flight anymore by now.
+            let scene_file = Config::load_asset(SCENE_FILE);
+            let previous =
+                std::mem::replace(&mut state, SceneState::load(&main_camera, &scene_file));
+            outgoing = Some(previous);
+            config = Config::load(CONFIG_FILE);
+            (*control_hub.screen).borrow_mut().load_settings(&config);
+            for asset in [SCENE_FILE, CONFIG_FILE] {
+                event_bus.publish(EngineEvent::AssetReloaded(PathBuf::from(asset)));
+            }
+            screen.start_transition(SCENE_TRANSITION, SCENE_TRANSITION_TIME);
+            (*control_hub.rt).borrow_mut().set_rts(&state.rts);
+            scene_params.reload_scene = false;
+        }
+        event_bus.dispatch();
+        {
+            profile_scope!("Swap");
+            app.win.swap_window();
+        }
+        frame_capture.end_frame();
+        profiler::end_frame();
+        if scene_params.export_profile {
+            if let Some(report) = profiler::last_frame_report() {
+                println!("{}", report);
+            }
+            match profiler::export_chrome_trace(Path::new(PROFILE_TRACE)) {
+                Ok(()) => println!("Profile written to {}", PROFILE_TRACE),
+                Err(e) => eprintln!("{}", e),
+            }
+            scene_params.export_profile = false;
+        }
+        // the frame stats take their timings from the profiler
+        if let Some(frame) = profiler::last_frame() {
+            (*frame_stats).borrow_mut().end_profiled_frame(&frame);
+            let fps = Duration::from_secs(1).div_duration_f32(frame.duration);
+            let mut info: String = (*frame_stats).borrow().report();
+            info += &std::format!("FPS: {fps}\n");
+            info += "----------------------------------------";
+            std::println!("{info}");
+        }
+    }
+
+    (*control_hub.screen).borrow().save_settings(&mut config);
+    if let Err(e) = config.save() {
+        eprintln!("{}", e);
     }
 }
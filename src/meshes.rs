@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use bytemuck::{NoUninit, Pod, Zeroable};
 use gl33::gl_core_types::*;
 use gl33::gl_enumerations::*;
@@ -6,13 +8,15 @@ use gl33::global_loader::*;
 use nalgebra_glm::*;
 
 use crate::data::buffer_data;
-use crate::scene::Instance;
+use crate::instances::InstanceData;
 use crate::shaders::Shader;
 use crate::shaders::ShaderProgram;
+use crate::spatial::{Aabb, BoundingSphere};
+use crate::stats::MemoryUsage;
 use crate::textures::Material;
 use crate::textures::TextureType;
 use crate::{
-    data::{Buffer, BufferType, VertexArray},
+    data::{Buffer, BufferType, CullMode, VertexArray, Winding},
     textures::{CubeMap, Texture2D},
 };
 
@@ -20,12 +24,123 @@ pub trait Draw {
     fn draw(&self, shader: &ShaderProgram);
     fn clone_box(&self) -> Box<dyn Draw>;
     fn instanced_draw(&self, shader: &ShaderProgram, instances: usize);
-    fn setup_inst_attr(&self);
-    fn cull_faces(&self) -> bool {
-        false
+    // Hooks `instances` up to this drawable and its clones, but not to whatever it was cloned from
+    fn setup_inst_attr(&mut self, instances: &Buffer);
+    // Every attribute location the drawable's VAO feeds, instance attributes included
+    fn attribute_layout(&self) -> Vec<AttributeSlot>;
+    fn cull_mode(&self) -> CullMode {
+        CullMode::None
+    }
+    fn winding(&self) -> Winding {
+        Winding::CounterClockwise
+    }
+    // In model space
+    fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::new(Vec3::zeros(), 0.0)
+    }
+    // In model space as well
+    fn bounding_box(&self) -> Aabb {
+        Aabb::around_sphere(&self.bounding_sphere())
+    }
+    // Geometry kept on the host. Clones of a mesh share theirs, so count it once per mesh.
+    fn host_memory(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+    fn materials(&self) -> Vec<&Material> {
+        vec![]
+    }
+}
+
+// A vertex attribute location fed by a buffer, and how many components it provides
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttributeSlot {
+    pub location: u32,
+    pub components: u32,
+}
+
+impl AttributeSlot {
+    pub const fn new(location: u32, components: u32) -> Self {
+        Self {
+            location,
+            components,
+        }
     }
 }
 
+// Position, normal and texture coordinates of a `Vertex`
+pub const VERTEX_ATTRIBUTES: [AttributeSlot; 3] = [
+    AttributeSlot::new(0, 3),
+    AttributeSlot::new(1, 3),
+    AttributeSlot::new(2, 3),
+];
+
+// A per-instance attribute sourced from `InstanceData`, by the name shaders declare it with.
+// Matrices take a location per column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstanceAttribute {
+    pub name: &'static str,
+    pub columns: u32,
+    pub components: u32, // floats per column
+    pub offset: usize,
+}
+
+pub const INSTANCE_ATTRIBUTES: [InstanceAttribute; 5] = [
+    InstanceAttribute {
+        name: "aInstRotation",
+        columns: 1,
+        components: 4,
+        offset: core::mem::offset_of!(InstanceData, rotation),
+    },
+    InstanceAttribute {
+        name: "aInstPosScale",
+        columns: 1,
+        components: 4,
+        offset: core::mem::offset_of!(InstanceData, position_scale),
+    },
+    InstanceAttribute {
+        name: "aPrevInstRotation",
+        columns: 1,
+        components: 4,
+        offset: core::mem::offset_of!(InstanceData, previous_rotation),
+    },
+    InstanceAttribute {
+        name: "aPrevInstPosScale",
+        columns: 1,
+        components: 4,
+        offset: core::mem::offset_of!(InstanceData, previous_position_scale),
+    },
+    InstanceAttribute {
+        name: "aInstDrawDistance",
+        columns: 1,
+        components: 2,
+        offset: core::mem::offset_of!(InstanceData, draw_distance),
+    },
+];
+
+// Instance attributes are placed right after the per-vertex ones, so they move along when `Vertex`
+// grows. Programs get the same locations bound before linking, see `ShaderProgram::link_program`.
+pub fn instance_attribute_locations() -> Vec<(InstanceAttribute, u32)> {
+    let mut location = VERTEX_ATTRIBUTES.len() as u32;
+    INSTANCE_ATTRIBUTES
+        .iter()
+        .map(|attribute| {
+            let first = location;
+            location += attribute.columns;
+            (*attribute, first)
+        })
+        .collect()
+}
+
+pub fn instance_attributes() -> Vec<AttributeSlot> {
+    instance_attribute_locations()
+        .into_iter()
+        .flat_map(|(attribute, first)| {
+            (first..first + attribute.columns)
+                .map(move |location| AttributeSlot::new(location, attribute.components))
+        })
+        .collect()
+}
+
 impl Clone for Box<dyn Draw> {
     fn clone(&self) -> Self {
         self.clone_box()
@@ -68,41 +183,126 @@ impl Vertex {
 unsafe impl Zeroable for Vertex {}
 unsafe impl Pod for Vertex {}
 
-#[derive(Clone)]
-pub struct BasicMesh {
-    pub vertices: Vec<Vertex>,
-    pub indices: Vec<u32>,
-    pub material: Material,
-    cull_faces: bool,
+// Geometry of a mesh, both the GL objects and the host copy they were filled from. Shared by the
+// mesh's clones and freed along with the last one.
+pub struct MeshGpu {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
     vao: VertexArray,
     vbo: Buffer,
     ebo: Buffer,
 }
 
-impl BasicMesh {
-    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, material: Material) -> Self {
-        let vao = VertexArray::new().expect("Couldn't make a VAO");
-        let vbo = Buffer::new().expect("Couldn't make the vertex buffer");
-        let ebo = Buffer::new().expect("Couldn't make the indices buffer");
-
-        let mesh = BasicMesh {
+impl MeshGpu {
+    fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        let mesh = MeshGpu {
             vertices,
             indices,
-            material,
-            cull_faces: true,
-            vao,
-            vbo,
-            ebo,
+            vao: VertexArray::new().expect("Couldn't make a VAO"),
+            vbo: Buffer::new().expect("Couldn't make the vertex buffer"),
+            ebo: Buffer::new().expect("Couldn't make the indices buffer"),
         };
         mesh.setup_mesh();
         mesh
     }
 
-    pub fn cube(side: f32) -> Self {
-        let vao = VertexArray::new().expect("Couldn't make a VAO");
-        let vbo = Buffer::new().expect("Couldn't make the vertex buffer");
-        let ebo = Buffer::new().expect("Couldn't make the indices buffer");
+    fn setup_mesh(&self) {
+        self.vao.bind();
+
+        self.vbo.bind(BufferType::Array);
+        buffer_data(
+            BufferType::Array,
+            bytemuck::cast_slice(&self.vertices),
+            GL_STATIC_DRAW,
+        );
+
+        self.ebo.bind(BufferType::ElementArray);
+        buffer_data(
+            BufferType::ElementArray,
+            bytemuck::cast_slice(&self.indices),
+            GL_STATIC_DRAW,
+        );
+
+        self.attach();
+        VertexArray::clear_binding();
+    }
+
+    // Feeds the geometry to the bound VAO
+    fn attach(&self) {
+        self.vbo.bind(BufferType::Array);
+        self.ebo.bind(BufferType::ElementArray);
+        unsafe {
+            glEnableVertexAttribArray(0);
+            glVertexAttribPointer(
+                0,
+                3,
+                GL_FLOAT,
+                GL_FALSE.0 as u8,
+                core::mem::size_of::<Vertex>().try_into().unwrap(),
+                core::mem::offset_of!(Vertex, pos) as *const _, // might seem redundant, but it's just in case the order changes
+            );
+            glEnableVertexAttribArray(1);
+            glVertexAttribPointer(
+                1,
+                3,
+                GL_FLOAT,
+                GL_FALSE.0 as u8,
+                core::mem::size_of::<Vertex>().try_into().unwrap(),
+                core::mem::offset_of!(Vertex, normal) as *const _,
+            );
+            glEnableVertexAttribArray(2);
+            glVertexAttribPointer(
+                2,
+                3,
+                GL_FLOAT,
+                GL_FALSE.0 as u8,
+                core::mem::size_of::<Vertex>().try_into().unwrap(),
+                core::mem::offset_of!(Vertex, tex_coords) as *const _,
+            );
+        }
+    }
+}
+
+impl Drop for MeshGpu {
+    fn drop(&mut self) {
+        self.vao.delete();
+        self.vbo.delete();
+        self.ebo.delete();
+    }
+}
+
+// A VAO feeding a mesh's geometry along with one object's instances
+struct InstancedVao(VertexArray);
+
+impl Drop for InstancedVao {
+    fn drop(&mut self) {
+        self.0.delete();
+    }
+}
+
+// Cheap to clone: clones share the geometry and only copy the material. The geometry can't be
+// changed after it's built, build another mesh instead.
+#[derive(Clone)]
+pub struct BasicMesh {
+    pub material: Material,
+    cull_mode: CullMode,
+    winding: Winding,
+    gpu: Rc<MeshGpu>,
+    instanced: Option<Rc<InstancedVao>>, // one per object, since each has its own instance buffer
+}
 
+impl BasicMesh {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, material: Material) -> Self {
+        BasicMesh {
+            material,
+            cull_mode: CullMode::Back,
+            winding: Winding::CounterClockwise,
+            gpu: Rc::new(MeshGpu::new(vertices, indices)),
+            instanced: None,
+        }
+    }
+
+    pub fn cube(side: f32) -> Self {
         let mut vertices = vec![
             Vertex::new(-side / 2.0, side / 2.0, -side / 2.0),
             Vertex::new(side / 2.0, side / 2.0, -side / 2.0),
@@ -159,24 +359,10 @@ impl BasicMesh {
             vertices[i].normal = normals[i] / 4.0;
             vertices[i].tex_coords = vec3((i % 2) as f32, ((i / 2) % 2) as f32, 0.0);
         }
-        let cube = BasicMesh {
-            vertices,
-            indices,
-            material: Material::new(vec![], vec![], 1.0),
-            cull_faces: true,
-            vao,
-            vbo,
-            ebo,
-        };
-        cube.setup_mesh();
-        cube
+        Self::new(vertices, indices, Material::new(vec![], vec![], 1.0))
     }
 
     pub fn square(side: f32) -> Self {
-        let vao = VertexArray::new().expect("Couldn't make a VAO");
-        let vbo = Buffer::new().expect("Couldn't make the vertex buffer");
-        let ebo = Buffer::new().expect("Couldn't make the indices buffer");
-
         let mut vertices = vec![
             Vertex::new(-side / 2.0, side / 2.0, 0.0),
             Vertex::new(side / 2.0, side / 2.0, 0.0),
@@ -196,76 +382,73 @@ impl BasicMesh {
             vertices[i].normal = normal;
             vertices[i].tex_coords = vec3((i % 2) as f32, (i as i32 / -2 + 1) as f32, 0.0);
         }
-        let square = BasicMesh {
-            vertices,
-            indices,
-            material: Material::new(vec![], vec![], 1.0),
-            cull_faces: false,
-            vao,
-            vbo,
-            ebo,
-        };
-        square.setup_mesh();
+        let mut square = Self::new(vertices, indices, Material::new(vec![], vec![], 1.0));
+        square.cull_mode = CullMode::None;
         square
     }
 
-    fn setup_mesh(&self) {
-        self.vao.bind();
+    // UV sphere with `segments` around and `rings` from pole to pole. The texture wraps around once,
+    // with the seam facing +x.
+    pub fn sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        let (segments, rings) = (segments.max(3), rings.max(2));
+        let mut vertices = Vec::with_capacity(((segments + 1) * (rings + 1)) as usize);
+        for ring in 0..=rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for segment in 0..=segments {
+                let phi = std::f32::consts::TAU * segment as f32 / segments as f32;
+                let normal = vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let mut vertex = Vertex::from_vector(normal * radius);
+                vertex.normal = normal;
+                vertex.tex_coords = vec3(
+                    segment as f32 / segments as f32,
+                    1.0 - ring as f32 / rings as f32,
+                    0.0,
+                );
+                vertices.push(vertex);
+            }
+        }
+        let mut indices = Vec::with_capacity((segments * rings * 6) as usize);
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let top = ring * (segments + 1) + segment;
+                let bottom = top + segments + 1;
+                indices.extend_from_slice(&[top, top + 1, bottom, top + 1, bottom + 1, bottom]);
+            }
+        }
+        Self::new(vertices, indices, Material::new(vec![], vec![], 1.0))
+    }
 
-        self.vbo.bind(BufferType::Array);
-        buffer_data(
-            BufferType::Array,
-            bytemuck::cast_slice(&self.vertices),
-            GL_STATIC_DRAW,
-        );
+    pub fn set_culling(&mut self, mode: CullMode, winding: Winding) {
+        self.cull_mode = mode;
+        self.winding = winding;
+    }
 
-        self.ebo.bind(BufferType::ElementArray);
-        buffer_data(
-            BufferType::ElementArray,
-            bytemuck::cast_slice(&self.indices),
-            GL_STATIC_DRAW,
-        );
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.gpu.vertices
+    }
 
-        unsafe {
-            glEnableVertexAttribArray(0);
-            glVertexAttribPointer(
-                0,
-                3,
-                GL_FLOAT,
-                GL_FALSE.0 as u8,
-                core::mem::size_of::<Vertex>().try_into().unwrap(),
-                core::mem::offset_of!(Vertex, pos) as *const _, // might seem redundant, but it's just in case the order changes
-            );
-            glEnableVertexAttribArray(1);
-            glVertexAttribPointer(
-                1,
-                3,
-                GL_FLOAT,
-                GL_FALSE.0 as u8,
-                core::mem::size_of::<Vertex>().try_into().unwrap(),
-                core::mem::offset_of!(Vertex, normal) as *const _,
-            );
-            glEnableVertexAttribArray(2);
-            glVertexAttribPointer(
-                2,
-                3,
-                GL_FLOAT,
-                GL_FALSE.0 as u8,
-                core::mem::size_of::<Vertex>().try_into().unwrap(),
-                core::mem::offset_of!(Vertex, tex_coords) as *const _,
-            );
-        }
+    pub fn indices(&self) -> &[u32] {
+        &self.gpu.indices
+    }
+
+    // Whether both are clones of the same mesh
+    pub fn shares_geometry(&self, other: &BasicMesh) -> bool {
+        Rc::ptr_eq(&self.gpu, &other.gpu)
     }
 }
 
 impl Draw for BasicMesh {
     fn draw(&self, shader: &ShaderProgram) {
         shader.set_material("material", &self.material);
-        self.vao.bind();
+        self.gpu.vao.bind();
         unsafe {
             glDrawElements(
                 GL_TRIANGLES,
-                self.indices.len() as i32,
+                self.gpu.indices.len() as i32,
                 GL_UNSIGNED_INT,
                 std::ptr::null(),
             );
@@ -275,13 +458,26 @@ impl Draw for BasicMesh {
     fn clone_box(&self) -> Box<dyn Draw> {
         Box::new(self.clone())
     }
+    fn host_memory(&self) -> MemoryUsage {
+        MemoryUsage {
+            vertices: MemoryUsage::of_vec(&self.gpu.vertices),
+            indices: MemoryUsage::of_vec(&self.gpu.indices),
+            instances: 0,
+        }
+    }
+    fn materials(&self) -> Vec<&Material> {
+        vec![&self.material]
+    }
     fn instanced_draw(&self, shader: &ShaderProgram, instances: usize) {
         shader.set_material("material", &self.material);
-        self.vao.bind();
+        match &self.instanced {
+            Some(vao) => vao.0.bind(),
+            None => self.gpu.vao.bind(),
+        }
         unsafe {
             glDrawElementsInstanced(
                 GL_TRIANGLES,
-                self.indices.len() as i32,
+                self.gpu.indices.len() as i32,
                 GL_UNSIGNED_INT,
                 std::ptr::null(),
                 instances as i32,
@@ -289,58 +485,133 @@ impl Draw for BasicMesh {
         }
         VertexArray::clear_binding();
     }
-    fn setup_inst_attr(&self) {
-        self.vao.bind();
-        unsafe {
-            for i in 0..4 {
-                glEnableVertexAttribArray(3 + i);
-                glVertexAttribPointer(
-                    3 + i,
-                    4,
-                    GL_FLOAT,
-                    GL_FALSE.0 as u8,
-                    core::mem::size_of::<Instance>().try_into().unwrap(),
-                    (i as usize * core::mem::size_of::<Vec4>()) as *const _,
-                );
-                glVertexAttribDivisor(3 + i, 1);
-            }
-            for i in 0..3 {
-                glEnableVertexAttribArray(7 + i);
-                glVertexAttribPointer(
-                    7 + i,
-                    3,
-                    GL_FLOAT,
-                    GL_FALSE.0 as u8,
-                    core::mem::size_of::<Instance>().try_into().unwrap(),
-                    (core::mem::offset_of!(Instance, normal)
-                        + i as usize * core::mem::size_of::<Vec3>())
-                        as *const _,
-                );
-                glVertexAttribDivisor(7 + i, 1);
+    // A VAO holds the layout of a single instance buffer, so every object gets its own over the
+    // shared geometry
+    fn setup_inst_attr(&mut self, instances: &Buffer) {
+        let vao = InstancedVao(VertexArray::new().expect("Couldn't make a VAO"));
+        vao.0.bind();
+        self.gpu.attach();
+        instances.bind(BufferType::Array);
+        for (attribute, first) in instance_attribute_locations() {
+            for column in 0..attribute.columns {
+                let location = first + column;
+                let column_size = attribute.components as usize * core::mem::size_of::<f32>();
+                let offset = attribute.offset + column as usize * column_size;
+                unsafe {
+                    glEnableVertexAttribArray(location);
+                    glVertexAttribPointer(
+                        location,
+                        attribute.components as i32,
+                        GL_FLOAT,
+                        GL_FALSE.0 as u8,
+                        core::mem::size_of::<InstanceData>().try_into().unwrap(),
+                        offset as *const _,
+                    );
+                    glVertexAttribDivisor(location, 1);
+                }
             }
         }
         VertexArray::clear_binding();
+        Buffer::clear_binding(BufferType::Array);
+        self.instanced = Some(Rc::new(vao));
+    }
+    fn attribute_layout(&self) -> Vec<AttributeSlot> {
+        let mut layout = VERTEX_ATTRIBUTES.to_vec();
+        layout.extend(instance_attributes());
+        layout
+    }
+    fn cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
+    fn winding(&self) -> Winding {
+        self.winding
     }
-    fn cull_faces(&self) -> bool {
-        self.cull_faces
+    fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(self.gpu.vertices.iter().map(|v| v.pos))
+    }
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(self.gpu.vertices.iter().map(|v| v.pos))
+            .unwrap_or(Aabb::new(Vec3::zeros(), Vec3::zeros()))
     }
 }
 
-pub struct Skybox {
-    pub texture: CubeMap,
-    vertices: [Vertex; 24],
-    indices: [u32; 36],
+// GL objects behind a skybox's cube, shared by its clones and freed along with the last one
+struct SkyboxMesh {
+    index_count: i32,
     vao: VertexArray,
     vbo: Buffer,
     ebo: Buffer,
 }
 
+impl SkyboxMesh {
+    fn new(vertices: &[Vertex], indices: &[u32]) -> Self {
+        let mesh = SkyboxMesh {
+            index_count: indices.len() as i32,
+            vao: VertexArray::new().expect("Couldn't make a VAO"),
+            vbo: Buffer::new().expect("Couldn't make the vertex buffer"),
+            ebo: Buffer::new().expect("Couldn't make the indices buffer"),
+        };
+        mesh.vao.bind();
+
+        mesh.vbo.bind(BufferType::Array);
+        buffer_data(
+            BufferType::Array,
+            bytemuck::cast_slice(vertices),
+            GL_STATIC_DRAW,
+        );
+
+        mesh.ebo.bind(BufferType::ElementArray);
+        buffer_data(
+            BufferType::ElementArray,
+            bytemuck::cast_slice(indices),
+            GL_STATIC_DRAW,
+        );
+
+        unsafe {
+            glEnableVertexAttribArray(0);
+            glVertexAttribPointer(
+                0,
+                3,
+                GL_FLOAT,
+                GL_FALSE.0 as u8,
+                core::mem::size_of::<Vertex>().try_into().unwrap(),
+                core::mem::offset_of!(Vertex, pos) as *const _, // might seem redundant, but it's just in case the order changes
+            );
+        }
+        VertexArray::clear_binding();
+        mesh
+    }
+}
+
+impl Drop for SkyboxMesh {
+    fn drop(&mut self) {
+        self.vao.delete();
+        self.vbo.delete();
+        self.ebo.delete();
+    }
+}
+
+// How a skybox covers the background
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkyboxGeometry {
+    // A cube around the camera, pushed to the far plane and depth tested against it
+    Cube,
+    // A single triangle over the whole screen, looking the cubemap up through the inverse
+    // view-projection. Drawn without depth testing, so it has to go before anything else, but it
+    // doesn't care about the depth convention or where the far plane is.
+    FullscreenTriangle,
+}
+
+// Cheap to clone, clones share both the cube and the texture
+#[derive(Clone)]
+pub struct Skybox {
+    pub texture: CubeMap,
+    mesh: Rc<SkyboxMesh>,
+    geometry: SkyboxGeometry,
+}
+
 impl Skybox {
     pub fn new(texture: CubeMap) -> Self {
-        let vao = VertexArray::new().expect("Couldn't make a VAO");
-        let vbo = Buffer::new().expect("Couldn't make the vertex buffer");
-        let ebo = Buffer::new().expect("Couldn't make the indices buffer");
-
         let vertices = [
             Vertex::new(-5.0, 5.0, -5.0),
             Vertex::new(5.0, 5.0, -5.0),
@@ -373,60 +644,40 @@ impl Skybox {
             18, 17, 17, 18, 19, 20, 22, 21, 21, 22, 23,
         ];
 
-        let skybox = Skybox {
+        Skybox {
             texture,
-            vertices,
-            indices,
-            vao,
-            vbo,
-            ebo,
-        };
-        skybox.setup_mesh();
-        skybox
+            mesh: Rc::new(SkyboxMesh::new(&vertices, &indices)),
+            geometry: SkyboxGeometry::Cube,
+        }
     }
 
-    fn setup_mesh(&self) {
-        self.vao.bind();
-
-        self.vbo.bind(BufferType::Array);
-        buffer_data(
-            BufferType::Array,
-            bytemuck::cast_slice(&self.vertices),
-            GL_STATIC_DRAW,
-        );
-
-        self.ebo.bind(BufferType::ElementArray);
-        buffer_data(
-            BufferType::ElementArray,
-            bytemuck::cast_slice(&self.indices),
-            GL_STATIC_DRAW,
-        );
+    pub fn set_geometry(&mut self, geometry: SkyboxGeometry) {
+        self.geometry = geometry;
+    }
 
-        unsafe {
-            glEnableVertexAttribArray(0);
-            glVertexAttribPointer(
-                0,
-                3,
-                GL_FLOAT,
-                GL_FALSE.0 as u8,
-                core::mem::size_of::<Vertex>().try_into().unwrap(),
-                core::mem::offset_of!(Vertex, pos) as *const _, // might seem redundant, but it's just in case the order changes
-            );
-        }
+    pub fn get_geometry(&self) -> SkyboxGeometry {
+        self.geometry
     }
 }
 
 impl Draw for Skybox {
     fn draw(&self, shader: &ShaderProgram) {
-        self.vao.bind();
+        // the triangle makes up its vertices, but core profiles won't draw without a VAO bound
+        self.mesh.vao.bind();
         shader.set_cubemap("skybox", &self.texture);
+        let fullscreen = self.geometry == SkyboxGeometry::FullscreenTriangle;
+        shader.set_1b("fullscreen", fullscreen);
         unsafe {
-            glDrawElements(
-                GL_TRIANGLES,
-                self.indices.len() as i32,
-                GL_UNSIGNED_INT,
-                std::ptr::null(),
-            );
+            if fullscreen {
+                glDrawArrays(GL_TRIANGLES, 0, 3);
+            } else {
+                glDrawElements(
+                    GL_TRIANGLES,
+                    self.mesh.index_count,
+                    GL_UNSIGNED_INT,
+                    std::ptr::null(),
+                );
+            }
         }
         VertexArray::clear_binding();
     }
@@ -436,12 +687,9 @@ impl Draw for Skybox {
     fn instanced_draw(&self, shader: &ShaderProgram, _: usize) {
         self.draw(shader);
     }
-    fn setup_inst_attr(&self) {}
-}
-
-impl Clone for Skybox {
-    fn clone(&self) -> Self {
-        Skybox::new(self.texture.clone())
+    fn setup_inst_attr(&mut self, _: &Buffer) {}
+    fn attribute_layout(&self) -> Vec<AttributeSlot> {
+        vec![VERTEX_ATTRIBUTES[0]]
     }
 }
 
@@ -541,5 +789,9 @@ impl Draw for Canvas {
     fn instanced_draw(&self, shader: &ShaderProgram, _: usize) {
         self.draw(shader);
     }
-    fn setup_inst_attr(&self) {}
+    fn setup_inst_attr(&mut self, _: &Buffer) {}
+    // texture coordinates go in the normal's place
+    fn attribute_layout(&self) -> Vec<AttributeSlot> {
+        vec![VERTEX_ATTRIBUTES[0], AttributeSlot::new(1, 3)]
+    }
 }
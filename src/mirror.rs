@@ -0,0 +1,105 @@
+use gl33::gl_enumerations::*;
+use gl33::global_loader::*;
+use nalgebra_glm::*;
+
+use crate::data::{self, ClearOptions, Framebuffer, RenderTarget, UniformBuffer};
+use crate::debug::DebugGroup;
+use crate::framegraph::FrameGraph;
+use crate::meshes::BasicMesh;
+use crate::scene::{Scene, SceneObject};
+use crate::spatial::Spatial;
+use crate::textures::Material;
+
+pub const MIRROR_TEXTURE: &str = "Mirror color"; // in frame graphs
+
+// Planar mirror: a square whose material shows the scene rendered from the main camera reflected
+// about the square's plane. The target is sampled in screen space, so it has to be as big as the
+// framebuffer the mirror itself is drawn on.
+pub struct MirrorSurface {
+    target: RenderTarget,
+    object: SceneObject,
+    clear: ClearOptions,
+    previous_view_projection: Option<Mat4>, // of the reflected camera, for its own motion vectors
+}
+
+impl MirrorSurface {
+    pub fn new(side: f32, target_size: (u32, u32), clear_color: Vec4) -> Self {
+        let target = RenderTarget::new(target_size).expect("Couldn't make the mirror target");
+        let mut square = BasicMesh::square(side);
+        square.material = Material::from_render_target(target.get_texture().clone());
+        Self {
+            target,
+            object: SceneObject::from(square),
+            clear: ClearOptions::all(clear_color),
+            previous_view_projection: None,
+        }
+    }
+
+    // Everything is cleared to the color the mirror was made with by default
+    pub fn set_clear(&mut self, clear: ClearOptions) {
+        self.clear = clear;
+    }
+
+    pub fn get_object(&self) -> &SceneObject {
+        &self.object
+    }
+
+    pub fn get_object_mut(&mut self) -> &mut SceneObject {
+        &mut self.object
+    }
+
+    // The pass drawing the reflection, which the scene samples as `MIRROR_TEXTURE`
+    pub fn describe_passes(&self, graph: &mut FrameGraph) -> Result<(), String> {
+        let size = self.target.get_size();
+        graph.add_resource(MIRROR_TEXTURE, size, "RGBA8", 1);
+        graph.add_resource("Mirror depth", size, "D24S8", 1);
+        graph.add_pass("Mirror", &[], &[MIRROR_TEXTURE, "Mirror depth"])
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        self.target.get_size()
+    }
+
+    // Point on the mirror and its world space normal
+    pub fn get_plane(&self) -> (Vec3, Vec3) {
+        let model = self.object.get_model();
+        let point = model.column(3).xyz();
+        let normal = normalize(&(mat4_to_mat3(&model) * vec3(0.0, 0.0, 1.0)));
+        (point, normal)
+    }
+
+    // Renders `scene` as seen in the mirror. Must run before the mirror object is added to the
+    // scene, otherwise it would cover its own reflection.
+    pub fn render(&mut self, scene: &mut Scene, ubo: &UniformBuffer) {
+        let _group = DebugGroup::new("Mirror");
+        let (point, normal) = self.get_plane();
+        let camera = scene.camera;
+        scene.camera = camera.reflected(&point, &normal);
+        let previous_view_projection = std::mem::replace(
+            &mut scene.previous_view_projection,
+            self.previous_view_projection,
+        );
+        // hooks are for the main view
+        let hooks = scene.hooks.take();
+
+        self.target.bind();
+        let size = self.target.get_size();
+        unsafe {
+            glViewport(0, 0, size.0 as i32, size.1 as i32);
+        }
+        self.clear.apply();
+        unsafe {
+            glEnable(GL_DEPTH_TEST);
+        }
+        // reflection turns every triangle around
+        data::set_mirrored_winding(true);
+        scene.compose(ubo);
+        data::set_mirrored_winding(false);
+        Framebuffer::clear_binding();
+
+        self.previous_view_projection = Some(scene.view_projection());
+        scene.previous_view_projection = previous_view_projection;
+        scene.hooks = hooks;
+        scene.camera = camera;
+    }
+}
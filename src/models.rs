@@ -11,43 +11,90 @@ use std::{
 };
 
 use crate::{
-    meshes::{BasicMesh, Draw, Vertex},
+    data::{Buffer, CullMode, Winding},
+    meshes::{AttributeSlot, BasicMesh, Draw, Vertex},
     shaders::ShaderProgram,
+    spatial::{Aabb, BoundingSphere},
+    stats::MemoryUsage,
     textures::{Material, Texture2D, TextureType},
+    vfs,
 };
 
+const EXTRACT_DIR: &str = "tungus-models"; // in the temporary directory
+
 #[derive(Clone)]
 pub struct Model {
     meshes: Vec<BasicMesh>,
     directory: String,
     loaded_textures: Vec<String>,
+    cull_mode: CullMode,
+    winding: Winding,
 }
 
 impl Model {
-    pub fn new(path: &'static Path) -> Self {
+    // Models are loaded from disk so assimp can follow references to sibling files (e.g. .mtl).
+    // Those inside mounted archives are extracted along with their siblings first, and imported
+    // from memory if that fails.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
         let directory = path
-            .to_path_buf()
             .parent()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
+            .map_or(String::new(), |dir| dir.display().to_string());
+        let file = vfs::resolve(path).or_else(|| {
+            vfs::extract(path, &std::env::temp_dir().join(EXTRACT_DIR))
+                .map_err(|e| eprintln!("{}", e))
+                .ok()
+        });
+        // assimp only takes UTF-8 paths, anything else is imported from memory as well
+        let file = file.and_then(|real_path| match real_path.to_str() {
+            Some(file) => Some(file.to_string()),
+            None => {
+                eprintln!(
+                    "{} isn't a UTF-8 path, importing it without the files it references",
+                    real_path.display()
+                );
+                None
+            }
+        });
+        let scene = match file {
+            Some(file) => Scene::from_file(&file, Self::post_process()),
+            None => {
+                let bytes = vfs::read(path)
+                    .unwrap_or_else(|e| panic!("Couldn't read model {}: {}", path.display(), e));
+                let hint = path.extension().map_or("", |ext| ext.to_str().unwrap_or(""));
+                Scene::from_buffer(&bytes, Self::post_process(), hint)
+            }
+        }
+        .unwrap_or_else(|e| panic!("Couldn't load model {}: {:?}", path.display(), e));
+        Self::from_scene(&scene, directory)
+    }
+    // `hint` is the file extension assimp should assume for the data (e.g. "obj"). Textures
+    // referenced by the model are looked up relative to the working directory.
+    pub fn from_bytes(bytes: &[u8], hint: &str) -> Self {
+        let scene = Scene::from_buffer(bytes, Self::post_process(), hint)
+            .expect("Couldn't load model from memory");
+        Self::from_scene(&scene, String::new())
+    }
+    // Models aren't culled unless asked to, since exporters don't agree on winding. An inside-out
+    // model gets fixed by culling it with the opposite winding.
+    pub fn set_culling(&mut self, mode: CullMode, winding: Winding) {
+        self.cull_mode = mode;
+        self.winding = winding;
+    }
+    fn post_process() -> Vec<PostProcess> {
+        vec![PostProcess::Triangulate, PostProcess::FlipUVs]
+    }
+    fn from_scene(scene: &Scene, directory: String) -> Self {
         let mut model = Model {
             meshes: vec![],
             directory,
             loaded_textures: vec![],
+            cull_mode: CullMode::None,
+            winding: Winding::CounterClockwise,
         };
-        model.load_model(path);
-        model
-    }
-    fn load_model(&mut self, path: &'static Path) {
-        let scene = Scene::from_file(
-            path.to_str().unwrap(),
-            vec![PostProcess::Triangulate, PostProcess::FlipUVs],
-        )
-        .unwrap();
         let root = scene.root.as_ref().unwrap();
-        self.process_node(&root, &scene);
+        model.process_node(&root, scene);
+        model
     }
     fn process_node(&mut self, node: &Node, scene: &Scene) {
         for mesh in &node.meshes {
@@ -128,7 +175,7 @@ impl Model {
     }
     fn load_material_color(&mut self, mat: &material::Material, typename: TextureType) -> Vec3 {
         let key_name = match typename {
-            TextureType::Attachment => "",
+            TextureType::Attachment | TextureType::Data => "",
             TextureType::Diffuse => "$clr.diffuse",
             TextureType::Specular => "$clr.specular",
         };
@@ -185,9 +232,45 @@ impl Draw for Model {
             mesh.instanced_draw(shader, instances);
         }
     }
-    fn setup_inst_attr(&self) {
+    fn setup_inst_attr(&mut self, instances: &Buffer) {
+        for mesh in &mut self.meshes {
+            mesh.setup_inst_attr(instances);
+        }
+    }
+    // All meshes are set up the same way
+    fn attribute_layout(&self) -> Vec<AttributeSlot> {
+        self.meshes
+            .first()
+            .map_or(vec![], |mesh| mesh.attribute_layout())
+    }
+    fn cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
+    fn winding(&self) -> Winding {
+        self.winding
+    }
+    fn host_memory(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
         for mesh in &self.meshes {
-            mesh.setup_inst_attr();
+            usage += mesh.host_memory();
         }
+        usage
+    }
+    fn materials(&self) -> Vec<&Material> {
+        self.meshes.iter().map(|mesh| &mesh.material).collect()
+    }
+    fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(
+            self.meshes
+                .iter()
+                .flat_map(|mesh| mesh.vertices().iter().map(|v| v.pos)),
+        )
+    }
+    fn bounding_box(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounding_box())
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(Aabb::new(Vec3::zeros(), Vec3::zeros()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const HISTORY_SIZE: usize = 120;
+
+// Times the rest of the enclosing block as a scope of the current frame. Scopes opened while
+// another one is alive are nested under it.
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiler::ProfileScope::new($name);
+    };
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeRecord {
+    pub name: &'static str,
+    pub depth: usize,
+    pub start: Duration, // since the profiler started
+    pub duration: Duration,
+}
+
+// The scopes of a frame, in the order they were opened
+#[derive(Clone, Debug, Default)]
+pub struct ProfileFrame {
+    pub start: Duration,
+    pub duration: Duration,
+    pub scopes: Vec<ScopeRecord>,
+}
+
+// Scopes with the same name under the same parent, added up
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeNode {
+    pub name: &'static str,
+    pub depth: usize,
+    pub total: Duration,
+    pub calls: u32,
+}
+
+impl ProfileFrame {
+    // Depth first, children in the order they first showed up
+    pub fn hierarchy(&self) -> Vec<ScopeNode> {
+        let mut nodes: Vec<(ScopeNode, Option<usize>)> = vec![];
+        let mut path: Vec<usize> = vec![];
+        for scope in &self.scopes {
+            path.truncate(scope.depth);
+            let parent = path.last().copied();
+            let existing = nodes
+                .iter()
+                .position(|(node, p)| *p == parent && node.name == scope.name);
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    let node = ScopeNode {
+                        name: scope.name,
+                        depth: scope.depth,
+                        total: Duration::ZERO,
+                        calls: 0,
+                    };
+                    nodes.push((node, parent));
+                    nodes.len() - 1
+                }
+            };
+            nodes[index].0.total += scope.duration;
+            nodes[index].0.calls += 1;
+            path.push(index);
+        }
+
+        let mut ordered = Vec::with_capacity(nodes.len());
+        let mut pending: Vec<usize> = (0..nodes.len())
+            .filter(|i| nodes[*i].1.is_none())
+            .rev()
+            .collect();
+        while let Some(index) = pending.pop() {
+            ordered.push(nodes[index].0.clone());
+            pending.extend(
+                (0..nodes.len())
+                    .filter(|i| nodes[*i].1 == Some(index))
+                    .rev(),
+            );
+        }
+        ordered
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = format!("Frame: {:?}\n", self.duration);
+        for node in self.hierarchy() {
+            out += &format!(
+                "{}{}: {:?} ({} calls)\n",
+                "  ".repeat(node.depth + 1),
+                node.name,
+                node.total,
+                node.calls
+            );
+        }
+        out
+    }
+}
+
+pub struct Profiler {
+    epoch: Instant,
+    frame_count: u64,
+    current: Option<ProfileFrame>,
+    open: Vec<usize>, // scopes of the current frame that haven't closed yet
+    history: VecDeque<ProfileFrame>,
+    capacity: usize,
+}
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::new(HISTORY_SIZE));
+}
+
+impl Profiler {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            epoch: Instant::now(),
+            frame_count: 0,
+            current: None,
+            open: vec![],
+            history: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        if self.current.is_some() {
+            self.end_frame();
+        }
+        self.current = Some(ProfileFrame {
+            start: self.epoch.elapsed(),
+            ..ProfileFrame::default()
+        });
+    }
+
+    // Scopes still open are cut short at the end of the frame
+    pub fn end_frame(&mut self) {
+        let Some(mut frame) = self.current.take() else {
+            return;
+        };
+        let now = self.epoch.elapsed();
+        for index in self.open.drain(..) {
+            frame.scopes[index].duration = now - frame.scopes[index].start;
+        }
+        frame.duration = now - frame.start;
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+        self.frame_count += 1;
+    }
+
+    // Scopes outside of a frame aren't recorded
+    fn open(&mut self, name: &'static str) -> Option<(u64, usize)> {
+        let start = self.epoch.elapsed();
+        let frame = self.current.as_mut()?;
+        frame.scopes.push(ScopeRecord {
+            name,
+            depth: self.open.len(),
+            start,
+            duration: Duration::ZERO,
+        });
+        self.open.push(frame.scopes.len() - 1);
+        Some((self.frame_count, frame.scopes.len() - 1))
+    }
+
+    fn close(&mut self, frame_number: u64, index: usize) {
+        let now = self.epoch.elapsed();
+        match self.current.as_mut() {
+            Some(frame) if frame_number == self.frame_count => {
+                frame.scopes[index].duration = now - frame.scopes[index].start;
+                self.open.retain(|open| *open != index);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn get_last_frame(&self) -> Option<&ProfileFrame> {
+        self.history.back()
+    }
+
+    // Chrome's trace event format, which chrome://tracing, Perfetto and speedscope can open as a
+    // flame graph. Each frame is a scope of its own, with the frame's scopes under it.
+    pub fn chrome_trace(&self) -> String {
+        let mut events = vec![];
+        for (number, frame) in self.history.iter().enumerate() {
+            events.push(trace_event(
+                &format!("Frame {}", number),
+                frame.start,
+                frame.duration,
+            ));
+            for scope in &frame.scopes {
+                events.push(trace_event(scope.name, scope.start, scope.duration));
+            }
+        }
+        format!(
+            "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n",
+            events.join(",\n")
+        )
+    }
+}
+
+fn trace_event(name: &str, start: Duration, duration: Duration) -> String {
+    let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+    format!(
+        "{{\"name\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":1}}",
+        name,
+        start.as_secs_f64() * 1e6,
+        duration.as_secs_f64() * 1e6
+    )
+}
+
+// Closes its scope when dropped, see `profile_scope!`
+pub struct ProfileScope {
+    scope: Option<(u64, usize)>,
+}
+
+impl ProfileScope {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            scope: PROFILER.with(|p| p.borrow_mut().open(name)),
+        }
+    }
+}
+
+impl Drop for ProfileScope {
+    fn drop(&mut self) {
+        if let Some((frame, index)) = self.scope {
+            PROFILER.with(|p| p.borrow_mut().close(frame, index));
+        }
+    }
+}
+
+// The profiler of the calling thread. Only the thread running the frame loop begins frames, so
+// scopes on worker threads are ignored.
+pub fn begin_frame() {
+    PROFILER.with(|p| p.borrow_mut().begin_frame());
+}
+
+pub fn end_frame() {
+    PROFILER.with(|p| p.borrow_mut().end_frame());
+}
+
+pub fn last_frame() -> Option<ProfileFrame> {
+    PROFILER.with(|p| p.borrow().get_last_frame().cloned())
+}
+
+pub fn last_frame_report() -> Option<String> {
+    PROFILER.with(|p| p.borrow().get_last_frame().map(ProfileFrame::report))
+}
+
+// Writes the kept frames as a Chrome trace
+pub fn export_chrome_trace(path: &Path) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("Couldn't create {}: {}", directory.display(), e))?;
+    }
+    let trace = PROFILER.with(|p| p.borrow().chrome_trace());
+    fs::write(path, trace).map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(name: &'static str, depth: usize, start: u64, duration: u64) -> ScopeRecord {
+        ScopeRecord {
+            name,
+            depth,
+            start: Duration::from_millis(start),
+            duration: Duration::from_millis(duration),
+        }
+    }
+
+    #[test]
+    fn hierarchy_merges_repeated_scopes() {
+        let frame = ProfileFrame {
+            start: Duration::ZERO,
+            duration: Duration::from_millis(10),
+            scopes: vec![
+                scope("Draw", 0, 0, 6),
+                scope("Upload", 1, 0, 1),
+                scope("Objects", 1, 1, 4),
+                scope("Update", 0, 6, 2),
+                scope("Draw", 0, 8, 2),
+                scope("Upload", 1, 8, 1),
+            ],
+        };
+        let names: Vec<_> = frame
+            .hierarchy()
+            .iter()
+            .map(|node| (node.name, node.depth, node.total.as_millis(), node.calls))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Draw", 0, 8, 2),
+                ("Upload", 1, 2, 2),
+                ("Objects", 1, 4, 1),
+                ("Update", 0, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn scopes_nest_within_frames() {
+        let mut profiler = Profiler::new(2);
+        assert_eq!(profiler.open("Outside"), None);
+        profiler.begin_frame();
+        let (frame, outer) = profiler.open("Outer").unwrap();
+        let (_, inner) = profiler.open("Inner").unwrap();
+        profiler.close(frame, inner);
+        profiler.end_frame();
+        // closing after the frame ended doesn't touch the next one
+        profiler.begin_frame();
+        profiler.close(frame, outer);
+        profiler.end_frame();
+
+        let recorded = &profiler.history[0];
+        assert_eq!(recorded.scopes.len(), 2);
+        assert_eq!(recorded.scopes[1].depth, 1);
+        assert!(profiler.history[1].scopes.is_empty());
+        assert!(profiler
+            .chrome_trace()
+            .contains("\"name\":\"Inner\",\"ph\":\"X\""));
+    }
+}
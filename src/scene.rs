@@ -2,146 +2,297 @@ use std::borrow::{Borrow, BorrowMut};
 use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::mem;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::time::SystemTime;
 
 use crate::camera::Camera;
 use crate::controls::{Controller, SignalType, Slot};
-use crate::data::{buffer_data, Buffer, BufferType, UniformBuffer, VertexArray};
+use crate::data::{
+    self, allocate_buffer, buffer_data, map_buffer_range, unmap_buffer, Buffer, BufferType,
+    CullMode, StencilState, UniformBuffer, VertexArray, Winding,
+};
+use crate::debug::DebugGroup;
+use crate::events::{EngineEvent, EventBus};
+use crate::hooks::{PassContext, PassHook, PassHooks};
+use crate::instances::{Instance, InstanceBuffer, InstanceData, InstanceSet};
 use crate::lighting::Lighting;
-use crate::meshes::{BasicMesh, Draw, Skybox, Vertex};
+use crate::lines::DebugLines;
+use crate::meshes::{AttributeSlot, BasicMesh, Draw, Skybox, SkyboxGeometry, Vertex};
 use crate::models::Model;
-use crate::shaders::ShaderProgram;
-use crate::spatial::Spatial;
+use crate::profile_scope;
+use crate::shaders::{environment_unit, ShaderProgram};
+use crate::spatial::{Aabb, BoundingSphere, Frustum, Spatial, Transform};
+use crate::stats::{CullingStats, MemoryUsage};
+use crate::textures::Material;
 use beryllium::Keycode;
-use bytemuck::{Pod, Zeroable};
 use gl33::gl_enumerations::*;
+use gl33::gl_groups::*;
 use gl33::global_loader::*;
 use nalgebra_glm::*;
 
-#[derive(Clone)]
-#[repr(C)]
-pub struct Instance {
-    pub model: Mat4,
-    pub normal: Mat3,
-    pub trans: Mat4,
-    pub rot: Mat4,
+const MAX_POINT_LIGHTS: usize = 4; // NR_POINT_LIGHTS in the object shader
+pub const OBJECT_STENCIL: i32 = 1; // what objects leave in the stencil buffer, outlines go around it
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ObjectHandle(u64);
+
+impl ObjectHandle {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, AtomicOrdering::Relaxed))
+    }
 }
 
-impl Copy for Instance {}
-unsafe impl Zeroable for Instance {}
-unsafe impl Pod for Instance {}
+// Adjustments on top of whatever materials the object's drawable has, for effects on a single
+// object such as fading it in or highlighting it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceParams {
+    pub tint: Vec3,
+    pub opacity: f32,
+    pub emissive: f32, // how much of the unlit color is added on top of the lighting
+}
 
-impl Instance {
+impl SurfaceParams {
     pub fn new() -> Self {
-        Instance {
-            model: Mat4::identity(),
-            normal: Mat3::identity(),
-            trans: Mat4::identity(),
-            rot: Mat4::identity(),
+        Self {
+            tint: vec3(1.0, 1.0, 1.0),
+            opacity: 1.0,
+            emissive: 0.0,
         }
     }
 }
 
-impl Spatial for Instance {
-    fn get_model(&self) -> &Mat4 {
-        &self.model
+// The instance buffer of an object, shared by its clones and freed along with the last one
+struct GlInstanceBuffer(Buffer);
+
+impl Drop for GlInstanceBuffer {
+    fn drop(&mut self) {
+        self.0.delete();
     }
-    fn get_normal(&mut self) -> &Mat3 {
-        self.normal = mat4_to_mat3(&self.get_model().try_inverse().unwrap().transpose());
-        &self.normal
+}
+
+impl InstanceBuffer for GlInstanceBuffer {
+    fn upload(&self, data: &[InstanceData], streaming: bool) {
+        profile_scope!("Instance upload");
+        // streamed data gets a new data store every time, so the driver orphans the old one instead
+        // of waiting for the draws still reading it
+        let usage = if streaming {
+            GL_STREAM_DRAW
+        } else {
+            GL_STATIC_DRAW
+        };
+        self.0.bind(BufferType::Array);
+        buffer_data(BufferType::Array, bytemuck::cast_slice(data), usage);
+        Buffer::clear_binding(BufferType::Array);
     }
-    fn set_model(&mut self, model: &Mat4) {
-        self.model = *model;
+
+    fn write(&self, len: usize, fill: &mut dyn FnMut(&mut [InstanceData])) -> bool {
+        let size = len * mem::size_of::<InstanceData>();
+        self.0.bind(BufferType::Array);
+        allocate_buffer(BufferType::Array, size, GL_STREAM_DRAW);
+        let mapped = map_buffer_range(
+            BufferType::Array,
+            0,
+            size,
+            GL_MAP_WRITE_BIT | GL_MAP_INVALIDATE_BUFFER_BIT,
+        ) as *mut InstanceData;
+
+        if mapped.is_null() {
+            Buffer::clear_binding(BufferType::Array);
+            return false;
+        }
+        fill(unsafe { std::slice::from_raw_parts_mut(mapped, len) });
+
+        // the buffer contents are undefined if unmapping fails, so they need a regular upload
+        let unmapped = unmap_buffer(BufferType::Array);
+        Buffer::clear_binding(BufferType::Array);
+        unmapped
     }
 }
 
 pub struct SceneObject {
+    handle: ObjectHandle, // kept by clones, they're the same object
     drawable: Box<dyn Draw>,
-    instances: Vec<Instance>,
-    ibo: Buffer,
-    model: Mat4,
-    normal: Mat3,
+    instances: InstanceSet,
+    ibo: Rc<GlInstanceBuffer>,
+    transform: Transform,
     outline: Vec4, // last element indicates whether the object should be outlined
-    dirty_instances: bool,
-    dirty_normal: bool,
+    draw_distance: Vec2, // what instances get, see `Instance::draw_distance`
+    surface: SurfaceParams,
+    spawn_time: f32, // engine time, 0 for objects the scene started with
 }
 
 impl Clone for SceneObject {
     fn clone(&self) -> Self {
         SceneObject {
+            handle: self.handle,
             drawable: self.drawable.clone(),
             instances: self.instances.clone(),
-            ibo: self.ibo,
-            model: self.model.clone(),
-            normal: self.normal.clone(),
+            ibo: self.ibo.clone(),
+            transform: self.transform,
             outline: self.outline.clone(),
-            dirty_instances: self.dirty_instances,
-            dirty_normal: self.dirty_normal,
+            draw_distance: self.draw_distance,
+            surface: self.surface,
+            spawn_time: self.spawn_time,
         }
     }
 }
 
 impl SceneObject {
     pub fn from<T: Draw + 'static>(object: T) -> Self {
-        let obj = SceneObject {
+        let mut obj = SceneObject {
+            handle: ObjectHandle::next(),
             drawable: Box::new(object),
-            instances: vec![Instance::new()],
-            ibo: Buffer::new().expect("Couldn't make the instance buffer!"),
-            model: Mat4::identity(),
-            normal: Mat3::identity(),
+            instances: InstanceSet::new(),
+            ibo: Rc::new(GlInstanceBuffer(
+                Buffer::new().expect("Couldn't make the instance buffer!"),
+            )),
+            transform: Transform::new(),
             outline: Vec4::zeros(),
-            dirty_instances: false,
-            dirty_normal: false,
+            draw_distance: Vec2::zeros(),
+            surface: SurfaceParams::new(),
+            spawn_time: 0.0,
         };
         obj.setup_object();
         obj
     }
 
-    fn setup_object(&self) {
-        self.ibo.bind(BufferType::Array);
+    fn setup_object(&mut self) {
+        self.ibo.0.bind(BufferType::Array);
 
         buffer_data(
             BufferType::Array,
-            bytemuck::cast_slice(&self.instances),
+            bytemuck::cast_slice(&self.instances.data()),
             GL_STATIC_DRAW,
         );
-
-        self.drawable.setup_inst_attr();
         Buffer::clear_binding(BufferType::Array);
+
+        self.drawable.setup_inst_attr(&self.ibo.0);
+    }
+
+    pub fn get_handle(&self) -> ObjectHandle {
+        self.handle
+    }
+
+    pub fn attribute_layout(&self) -> Vec<AttributeSlot> {
+        self.drawable.attribute_layout()
+    }
+
+    // Instances are counted by this object, the geometry by its drawable
+    pub fn host_memory(&self) -> MemoryUsage {
+        MemoryUsage {
+            instances: self.instances.host_memory(),
+            ..self.drawable.host_memory()
+        }
+    }
+
+    pub fn get_materials(&self) -> Vec<&Material> {
+        self.drawable.materials()
+    }
+
+    // Of the drawable, in instance space
+    pub fn get_bounding_sphere(&self) -> BoundingSphere {
+        self.drawable.bounding_sphere()
+    }
+
+    // Around each instance, in world space
+    pub fn get_instance_bounds(&self) -> Vec<Aabb> {
+        let local = self.drawable.bounding_box();
+        let model = self.get_model();
+        self.instances
+            .as_slice()
+            .iter()
+            .map(|instance| local.transformed(&(model * instance.get_model())))
+            .collect()
+    }
+
+    // Around every instance, in world space. None without instances.
+    pub fn get_world_bounds(&self) -> Option<Aabb> {
+        self.get_instance_bounds()
+            .into_iter()
+            .reduce(|a, b| a.union(&b))
     }
 
     pub fn add_instance(&mut self) {
-        self.instances.push(Instance::new());
+        self.instances.add(1);
     }
 
     pub fn add_instances(&mut self, instances: usize) {
-        for _ in 0..instances {
-            self.instances.push(Instance::new());
+        self.instances.add(instances);
+        let added = self.instances.len() - instances;
+        for instance in &mut self.instances.as_mut_slice()[added..] {
+            instance.draw_distance = self.draw_distance;
         }
     }
 
+    pub fn has_dirty_instances(&self) -> bool {
+        self.instances.is_dirty()
+    }
+
     pub fn get_instances(&self) -> usize {
         self.instances.len()
     }
 
     pub fn get_instance(&self, instance: isize) -> &Instance {
-        if instance < 0 {
-            let index = self.instances.len() - (-instance as usize);
-            &self.instances[index]
-        } else {
-            &self.instances[instance as usize]
-        }
+        self.instances.get(instance)
     }
 
     pub fn get_instance_mut(&mut self, instance: isize) -> &mut Instance {
-        self.dirty_instances = true;
-        if instance < 0 {
-            let index = self.instances.len() - (-instance as usize);
-            &mut self.instances[index]
-        } else {
-            &mut self.instances[instance as usize]
+        self.instances.get_mut(instance)
+    }
+
+    pub fn get_instances_mut(&mut self) -> &mut [Instance] {
+        self.instances.as_mut_slice()
+    }
+
+    // Runs `update` over disjoint chunks of the instances in parallel, writing each result straight
+    // into the (orphaned) instance buffer so no separate upload is needed at draw time. The motion
+    // each instance went through is uploaded too, so objects animated this way should be updated
+    // every frame, or stopped with `stop_motion`.
+    //
+    // Culled instances are only uploaded at draw time, once it's known which are visible, so the
+    // models before the update are kept for then instead.
+    pub fn update_instances<F>(&mut self, update: F)
+    where
+        F: Fn(usize, &mut Instance) + Sync,
+    {
+        self.instances.update_through(self.ibo.as_ref(), update);
+    }
+
+    // Once every view of the frame is drawn, see `InstanceSet::end_frame`
+    pub fn end_frame(&self) {
+        self.instances.end_frame();
+    }
+
+    // Uploads the instances again without motion, e.g. after they stopped being animated
+    pub fn stop_motion(&mut self) {
+        self.instances.stop_motion();
+    }
+
+    // Tests every instance against the view before drawing and only uploads the visible ones.
+    // Worth it for many instances spread out enough that most are off screen at any time.
+    pub fn set_instance_culling(&mut self, enable: bool) {
+        self.instances.set_culled(enable);
+    }
+
+    pub fn has_instance_culling(&self) -> bool {
+        self.instances.is_culled()
+    }
+
+    // Brings the instance buffer up to date for a view, returning how many instances it holds
+    pub fn upload_instances(&self, frustum: &Frustum) -> CullingStats {
+        if !self.instances.is_culled() {
+            return self.instances.upload(self.ibo.as_ref());
         }
+        profile_scope!("Instance culling");
+        self.instances.upload_visible(
+            self.ibo.as_ref(),
+            &self.drawable.bounding_box(),
+            &self.get_model(),
+            frustum,
+        )
     }
 
     pub fn get_outline(&self) -> Vec4 {
@@ -163,10 +314,43 @@ impl SceneObject {
         self.outline.w > 0.0
     }
 
+    // Instances farther than `max_distance` from the camera aren't drawn, and they dither out over
+    // the last `fade` units before it. A max distance of 0 turns it off. Applies to every instance,
+    // including those added later, but single instances can still be given their own.
+    pub fn set_draw_distance(&mut self, max_distance: f32, fade: f32) {
+        let max_distance = max_distance.max(0.0);
+        self.draw_distance = vec2(max_distance, fade.clamp(0.0, max_distance));
+        for instance in self.instances.as_mut_slice() {
+            instance.draw_distance = self.draw_distance;
+        }
+    }
+
+    pub fn get_draw_distance(&self) -> Vec2 {
+        self.draw_distance
+    }
+
+    pub fn get_surface(&self) -> &SurfaceParams {
+        &self.surface
+    }
+
+    pub fn get_surface_mut(&mut self) -> &mut SurfaceParams {
+        &mut self.surface
+    }
+
+    pub fn set_spawn_time(&mut self, time: f32) {
+        self.spawn_time = time;
+    }
+
+    pub fn get_spawn_time(&self) -> f32 {
+        self.spawn_time
+    }
+
+    // Drawn around what the object marked in the stencil buffer. Both sides of the enlarged copy
+    // are drawn, so the outline doesn't depend on the drawable's winding or a mirrored camera.
     pub fn draw_outline(&self, shader: &ShaderProgram, drawable: &dyn Draw) {
+        data::push_stencil(StencilState::outside(OBJECT_STENCIL));
+        data::set_face_culling(CullMode::None, drawable.winding());
         unsafe {
-            glStencilFunc(GL_NOTEQUAL, 1, 0xFF);
-            glStencilMask(0x00);
             glDisable(GL_DEPTH_TEST);
         }
 
@@ -174,44 +358,130 @@ impl SceneObject {
         drawable.draw(shader);
 
         unsafe {
-            glStencilMask(0xFF);
-            glStencilFunc(GL_ALWAYS, 1, 0xFF);
             glEnable(GL_DEPTH_TEST);
         }
+        data::pop_stencil();
     }
 
-    pub fn draw(&self, shader: &ShaderProgram) {
-        if self.dirty_instances == true {
-            self.ibo.bind(BufferType::Array);
-            buffer_data(
-                BufferType::Array,
-                bytemuck::cast_slice(&self.instances),
-                GL_STATIC_DRAW,
-            );
+    // Draws the first `instances` of the instance buffer, see `upload_instances`
+    pub fn draw(&self, shader: &ShaderProgram, instances: usize) {
+        if instances == 0 {
+            return;
         }
-        self.drawable.instanced_draw(shader, self.instances.len());
-        Buffer::clear_binding(BufferType::Array);
+        shader.set_3f("surface.tint", &self.surface.tint);
+        shader.set_1f("surface.opacity", self.surface.opacity);
+        shader.set_1f("surface.emissive", self.surface.emissive);
+        self.drawable.instanced_draw(shader, instances);
     }
 }
 
 impl Spatial for SceneObject {
-    fn get_model(&self) -> &Mat4 {
-        &self.model
+    fn get_model(&self) -> Mat4 {
+        self.transform.get_model()
     }
     fn get_normal(&mut self) -> &Mat3 {
-        if self.dirty_normal {
-            self.normal = mat4_to_mat3(&self.get_model().try_inverse().unwrap().transpose());
-        }
-        &self.normal
+        self.transform.get_normal()
     }
     fn set_model(&mut self, model: &Mat4) {
-        self.model = *model;
+        self.transform.set_model(model);
+    }
+}
+
+// What to build when spawning. Every spawn gets its own meshes, since the vertex arrays hold the
+// instance buffer layout of the object they belong to.
+#[derive(Clone)]
+pub enum Prefab {
+    Cube(f32, Material),
+    Square(f32, Material),
+    Model(PathBuf),
+}
+
+impl Prefab {
+    fn build(&self) -> SceneObject {
+        match self {
+            Prefab::Cube(side, material) => {
+                let mut mesh = BasicMesh::cube(*side);
+                mesh.material = material.clone();
+                SceneObject::from(mesh)
+            }
+            Prefab::Square(side, material) => {
+                let mut mesh = BasicMesh::square(*side);
+                mesh.material = material.clone();
+                SceneObject::from(mesh)
+            }
+            Prefab::Model(path) => SceneObject::from(Model::new(path)),
+        }
+    }
+}
+
+// Spawns and despawns requested during a frame. They're applied to the persistent object list
+// between frames, when no copy of the objects is being drawn anymore.
+#[derive(Default)]
+pub struct SpawnQueue {
+    spawns: Vec<(ObjectHandle, Prefab, Mat4)>,
+    despawns: Vec<ObjectHandle>,
+}
+
+impl SpawnQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self, prefab: Prefab, transform: &Mat4) -> ObjectHandle {
+        let handle = ObjectHandle::next();
+        self.spawns.push((handle, prefab, *transform));
+        handle
+    }
+
+    pub fn despawn(&mut self, handle: ObjectHandle) {
+        self.despawns.push(handle);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spawns.is_empty() && self.despawns.is_empty()
+    }
+
+    // Spawned, but not in the scene until the next `apply`
+    pub fn is_pending(&self, handle: ObjectHandle) -> bool {
+        self.spawns.iter().any(|(spawned, _, _)| *spawned == handle)
+    }
+
+    // `time` is the engine time the spawned objects count their age from
+    pub fn apply(&mut self, objects: &mut Vec<SceneObject>, events: &EventBus, time: f32) {
+        for (handle, prefab, transform) in self.spawns.drain(..) {
+            let mut object = prefab.build();
+            object.handle = handle;
+            object.set_model(&transform);
+            object.set_spawn_time(time);
+            objects.push(object);
+            events.publish(EngineEvent::ObjectSpawned(handle));
+        }
+        for handle in self.despawns.drain(..) {
+            match objects.iter().position(|o| o.handle == handle) {
+                Some(index) => {
+                    // its buffers and textures go with it, unless other objects share them
+                    objects.remove(index);
+                    events.publish(EngineEvent::ObjectDespawned(handle));
+                }
+                None => eprintln!("Despawning {:?}, which isn't in the scene", handle),
+            }
+        }
     }
 }
 
 #[derive(Clone, Copy)]
 pub struct SceneParameters {
     pub visualize_normals: bool,
+    pub capture_environment: bool, // one-shot, cleared once the capture is done
+    pub capture_frame: bool,       // same, for the GPU debugger
+    pub export_profile: bool,      // same, for the CPU profiler
+    pub export_thumbnails: bool,   // same, for material previews
+    pub audit_textures: bool,      // same, for the texture color space report
+    pub dump_frame_graph: bool,    // same, for the passes and targets of a frame
+    pub frame_selection: bool,     // same, points the camera at the selection or everything
+    pub spawn_object: bool,        // one-shot as well
+    pub despawn_object: bool,      // same
+    pub reload_scene: bool,        // same
     pub start: SystemTime,
 }
 
@@ -219,6 +489,16 @@ impl SceneParameters {
     pub fn init() -> Self {
         Self {
             visualize_normals: false,
+            capture_environment: false,
+            capture_frame: false,
+            export_profile: false,
+            export_thumbnails: false,
+            audit_textures: false,
+            dump_frame_graph: false,
+            frame_selection: false,
+            spawn_object: false,
+            despawn_object: false,
+            reload_scene: false,
             start: SystemTime::now(),
         }
     }
@@ -226,17 +506,47 @@ impl SceneParameters {
 
 pub struct SceneController {
     visualize_normals: bool,
+    capture_environment: bool,
+    capture_frame: bool,
+    export_profile: bool,
+    export_thumbnails: bool,
+    audit_textures: bool,
+    dump_frame_graph: bool,
+    frame_selection: bool,
+    spawn_object: bool,
+    despawn_object: bool,
+    reload_scene: bool,
 }
 
 impl SceneController {
     pub fn new() -> Rc<RefCell<SceneController>> {
         Rc::new(RefCell::new(Self {
             visualize_normals: false,
+            capture_environment: false,
+            capture_frame: false,
+            export_profile: false,
+            export_thumbnails: false,
+            audit_textures: false,
+            dump_frame_graph: false,
+            frame_selection: false,
+            spawn_object: false,
+            despawn_object: false,
+            reload_scene: false,
         }))
     }
     pub fn on_key_pressed(&mut self, keycode: Keycode) {
         match keycode {
             Keycode::N => self.visualize_normals = !self.visualize_normals,
+            Keycode::P => self.capture_environment = true,
+            Keycode::F12 => self.capture_frame = true,
+            Keycode::F9 => self.export_profile = true,
+            Keycode::F8 => self.export_thumbnails = true,
+            Keycode::F7 => self.audit_textures = true,
+            Keycode::F10 => self.dump_frame_graph = true,
+            Keycode::F => self.frame_selection = true,
+            Keycode::B => self.spawn_object = true,
+            Keycode::BACKSPACE => self.despawn_object = true,
+            Keycode::R => self.reload_scene = true,
             _ => (),
         }
     }
@@ -256,8 +566,18 @@ impl<'a> Controller<'a, SceneParameters, SceneController> for Rc<RefCell<SceneCo
         update(&mut (**self).borrow_mut());
     }
     fn process_signals(&'a self, obj: &mut SceneParameters) {
-        let self_obj = (**self).borrow_mut();
+        let mut self_obj = (**self).borrow_mut();
         obj.visualize_normals = self_obj.visualize_normals;
+        obj.capture_environment |= std::mem::take(&mut self_obj.capture_environment);
+        obj.capture_frame |= std::mem::take(&mut self_obj.capture_frame);
+        obj.export_profile |= std::mem::take(&mut self_obj.export_profile);
+        obj.export_thumbnails |= std::mem::take(&mut self_obj.export_thumbnails);
+        obj.audit_textures |= std::mem::take(&mut self_obj.audit_textures);
+        obj.dump_frame_graph |= std::mem::take(&mut self_obj.dump_frame_graph);
+        obj.frame_selection |= std::mem::take(&mut self_obj.frame_selection);
+        obj.spawn_object |= std::mem::take(&mut self_obj.spawn_object);
+        obj.despawn_object |= std::mem::take(&mut self_obj.despawn_object);
+        obj.reload_scene |= std::mem::take(&mut self_obj.reload_scene);
     }
 }
 
@@ -268,81 +588,175 @@ pub struct Scene<'a> {
     pub skybox_shader: ShaderProgram,
     pub outline_shader: ShaderProgram,
     pub debug_shader: ShaderProgram,
+    pub line_shader: ShaderProgram,
     pub camera: Camera,
     pub lighting: &'a Lighting,
     pub params: SceneParameters,
+    pub previous_view_projection: Option<Mat4>, // camera of the previous frame, for motion vectors
+    pub lines: Option<&'a DebugLines>,
+    pub spawns: SpawnQueue,
+    pub hooks: Option<&'a PassHooks>,
+    pub culling: CullingStats, // of the last `compose`
+}
+
+// Summed over the objects, see `SceneObject::host_memory`
+pub fn host_memory(objects: &[SceneObject]) -> MemoryUsage {
+    objects
+        .iter()
+        .fold(MemoryUsage::default(), |usage, object| {
+            usage + object.host_memory()
+        })
 }
 
 impl<'a> Scene<'a> {
-    pub fn mirrored(&'a self) -> Self {
-        Scene {
-            objects: self.objects.clone(),
-            skyboxes: &self.skyboxes,
-            object_shader: self.object_shader,
-            skybox_shader: self.skybox_shader,
-            outline_shader: self.outline_shader,
-            debug_shader: self.debug_shader,
-            camera: self.camera.invert(),
-            lighting: &self.lighting,
-            params: self.params,
-        }
+    // Objects are cloned into every scene. Only their instances are copied each time one is built,
+    // the geometry is shared.
+    pub fn host_memory(&self) -> MemoryUsage {
+        host_memory(&self.objects)
     }
-    pub fn compose(&mut self, ubo: &UniformBuffer) {
-        unsafe {
-            glDisable(GL_STENCIL_TEST);
-            glDisable(GL_CULL_FACE);
-            glDepthFunc(GL_LEQUAL);
-        }
 
-        let view = mat3_to_mat4(&mat4_to_mat3(&self.camera.look_at()));
-        ubo.set_view_mat(&view);
+    // Takes effect once `spawns` is applied at the end of the frame
+    pub fn spawn(&mut self, prefab: Prefab, transform: &Mat4) -> ObjectHandle {
+        self.spawns.spawn(prefab, transform)
+    }
 
-        self.skybox_shader.use_program();
+    pub fn despawn(&mut self, handle: ObjectHandle) {
+        self.spawns.despawn(handle);
+    }
+
+    pub fn projection(&self) -> Mat4 {
+        self.camera.projection(1.0)
+    }
+
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection() * self.camera.look_at()
+    }
 
+    // Expects the skybox matrices to be bound already
+    fn draw_skyboxes(&self, depth_lequal: GLenum) {
+        let _group = DebugGroup::new("Skybox");
+        data::set_face_culling(CullMode::None, Winding::CounterClockwise);
+        self.skybox_shader.use_program();
+        self.skybox_shader
+            .set_1f("farDepth", self.camera.far_clip_depth());
+        self.skybox_shader
+            .set_1f("nearDepth", self.camera.near_clip_depth());
+        data::push_stencil(StencilState::disabled());
         for skybox in self.skyboxes {
+            unsafe {
+                match skybox.get_geometry() {
+                    SkyboxGeometry::Cube => {
+                        glEnable(GL_DEPTH_TEST);
+                        glDepthFunc(depth_lequal);
+                    }
+                    SkyboxGeometry::FullscreenTriangle => glDisable(GL_DEPTH_TEST),
+                }
+            }
             skybox.draw(&self.skybox_shader);
         }
-
         unsafe {
-            glEnable(GL_STENCIL_TEST);
-            glEnable(GL_CULL_FACE);
-            glDepthFunc(GL_LESS);
+            glEnable(GL_DEPTH_TEST);
         }
+        data::pop_stencil();
+    }
 
-        let projection = perspective(1.0, self.camera.get_fov(), 0.1, 100.0);
+    pub fn compose(&mut self, ubo: &UniformBuffer) {
+        profile_scope!("Scene");
+        // Matrices for the whole pass are queued up front so they reach the GPU in one write
+        let projection = self.projection();
         let view = self.camera.look_at();
+        ubo.set_previous_view_projection_mat(
+            &self.previous_view_projection.unwrap_or(projection * view),
+        );
+        ubo.set_projection_mat(&projection);
+        ubo.set_view_mat(&mat3_to_mat4(&mat4_to_mat3(&view)));
+        ubo.clear_spawn_time();
+        let skybox_block = ubo.push_block();
 
         ubo.set_view_mat(&view);
-        ubo.set_projection_mat(&projection);
+        let mut object_blocks = Vec::with_capacity(self.objects.len());
+        for object in self.objects.iter() {
+            ubo.set_model_mat(&object.get_model());
+            ubo.set_spawn_time(object.get_spawn_time());
+            let block = ubo.push_block();
+            let outline_block = if object.has_outline() {
+                ubo.set_model_mat(&scale(&object.get_model(), &vec3(1.1, 1.1, 1.1)));
+                Some(ubo.push_block())
+            } else {
+                None
+            };
+            object_blocks.push((block, outline_block));
+        }
+        ubo.clear_spawn_time();
+        let lines_block = self.lines.map(|_| {
+            ubo.set_model_mat(&Mat4::identity());
+            ubo.push_block()
+        });
+        {
+            profile_scope!("Matrix upload");
+            ubo.flush();
+        }
 
-        self.object_shader.use_program();
+        self.run_hooks(PassHook::BeforeOpaque, ubo);
+
+        let (depth_less, depth_lequal) = if self.camera.is_reversed_z() {
+            (GL_GREATER, GL_GEQUAL)
+        } else {
+            (GL_LESS, GL_LEQUAL)
+        };
+
+        ubo.bind_block(skybox_block);
+        self.draw_skyboxes(depth_lequal);
+        unsafe {
+            glDepthFunc(depth_less);
+        }
+
+        let _objects_group = DebugGroup::new("Objects");
+        profile_scope!("Objects");
+        let log_depth_coefficient = self.camera.log_depth_coefficient();
+        for shader in [
+            &self.debug_shader,
+            &self.outline_shader,
+            &self.line_shader,
+            &self.object_shader,
+        ] {
+            shader.use_program();
+            shader.set_1f("logDepthCoef", log_depth_coefficient);
+            shader.set_3f("cameraPos", &self.camera.get_pos());
+        }
         self.set_lighting_uniforms();
+        self.set_environment_uniforms();
+        let frustum = Frustum::from_view_projection(&self.view_projection());
+        self.culling = CullingStats::default();
         let object_list: &mut Vec<SceneObject> = self.objects.borrow_mut();
-        for object in object_list.iter_mut() {
-            if object.drawable.cull_faces() {
-                unsafe {
-                    glEnable(GL_CULL_FACE);
-                }
-            } else {
-                unsafe {
-                    glDisable(GL_CULL_FACE);
-                }
-            }
-            ubo.set_model_mat(&object.get_model());
-            object.draw(&self.object_shader);
+        for (object, (block, outline_block)) in object_list.iter_mut().zip(object_blocks) {
+            let culling = object.upload_instances(&frustum);
+            self.culling += culling;
+            data::set_face_culling(object.drawable.cull_mode(), object.drawable.winding());
+            ubo.bind_block(block);
+            object.draw(&self.object_shader, culling.drawn);
             if self.params.visualize_normals {
                 self.debug_shader.use_program();
-                object.draw(&self.debug_shader);
+                object.draw(&self.debug_shader, culling.drawn);
                 self.object_shader.use_program();
             }
-            if object.has_outline() {
+            if let Some(outline_block) = outline_block {
+                let _outline_group = DebugGroup::new("Stencil outline");
                 self.outline_shader.use_program();
-                let outline_scale = scale(&object.get_model(), &vec3(1.1, 1.1, 1.1));
-                ubo.set_model_mat(&outline_scale);
+                ubo.bind_block(outline_block);
                 object.draw_outline(&self.outline_shader, object.drawable.as_ref());
                 self.object_shader.use_program();
             }
         }
+        self.run_hooks(PassHook::AfterOpaque, ubo);
+
+        if let (Some(lines), Some(block)) = (self.lines, lines_block) {
+            let _lines_group = DebugGroup::new("Debug lines");
+            ubo.bind_block(block);
+            data::push_stencil(StencilState::disabled());
+            lines.draw(&self.line_shader);
+            data::pop_stencil();
+        }
     }
 
     // fn distance_compare(&self, a: &SceneObject, b: &SceneObject) -> Ordering {
@@ -351,14 +765,95 @@ impl<'a> Scene<'a> {
     //     distance_b.partial_cmp(&distance_a).unwrap()
     // }
 
+    fn run_hooks(&self, hook: PassHook, ubo: &UniformBuffer) {
+        match self.hooks {
+            Some(hooks) if hooks.has(hook) => {
+                let _group = DebugGroup::new(&format!("{:?} hooks", hook));
+                hooks.run(&PassContext::new(
+                    hook,
+                    self.object_shader,
+                    ubo,
+                    Some(&self.camera),
+                ));
+                // hooks are free to change culling
+                data::invalidate_face_culling();
+            }
+            _ => (),
+        }
+    }
+
+    // Only lights that reach into the view are uploaded, packed at the front of the array
     fn set_lighting_uniforms(&self) {
+        let frustum = Frustum::from_view_projection(&self.view_projection());
+        let eye = self.camera.get_pos();
         self.object_shader
             .set_directional_light("dirLight", &self.lighting.dir);
-        for (i, point) in self.lighting.point.iter().enumerate() {
+        let visible = self.lighting.visible_point_lights(&frustum, &eye);
+        let count = visible.len().min(MAX_POINT_LIGHTS);
+        for (i, point) in visible.into_iter().take(count).enumerate() {
             self.object_shader
-                .set_point_light(format!("pointLights[{}]", i).as_str(), &point);
+                .set_point_light(format!("pointLights[{}]", i).as_str(), point);
         }
+        self.object_shader.set_1i("pointLightCount", count as i32);
         self.object_shader
             .set_spotlight("spotlight", &self.lighting.spot);
+        if !self.lighting.is_spotlight_visible(&frustum, &eye) {
+            for name in [
+                "spotlight.ambient",
+                "spotlight.diffuse",
+                "spotlight.specular",
+            ] {
+                self.object_shader.set_3f(name, &Vec3::zeros());
+            }
+        }
+    }
+
+    // Reflections come from the first skybox. The sampler points at its own unit even without
+    // one, so it never aliases a material's 2D texture.
+    fn set_environment_uniforms(&self) {
+        match self.skyboxes.first() {
+            Some(skybox) => {
+                self.object_shader.set_cubemap_at(
+                    "environment",
+                    &skybox.texture,
+                    environment_unit(),
+                );
+                self.object_shader.set_1f(
+                    "environmentMaxLod",
+                    (skybox.texture.get_levels() - 1) as f32,
+                );
+            }
+            None => {
+                self.object_shader
+                    .set_1i("environment", environment_unit() as i32);
+                self.object_shader.set_1f("environmentMaxLod", 0.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_shot_flags_reach_the_parameters_once() {
+        let controller = SceneController::new();
+        let mut params = SceneParameters::init();
+        controller
+            .borrow_mut()
+            .on_signal(SignalType::KeyPressed(Keycode::B));
+        controller
+            .borrow_mut()
+            .on_signal(SignalType::KeyPressed(Keycode::N));
+        controller.process_signals(&mut params);
+        assert!(params.spawn_object);
+        assert!(params.visualize_normals);
+
+        // the scene clears the one-shot flags once it acted on them, the toggles stay
+        params.spawn_object = false;
+        controller.process_signals(&mut params);
+        assert!(!params.spawn_object);
+        assert!(params.visualize_normals);
     }
 }
@@ -2,12 +2,18 @@ use std::cell::RefCell;
 use std::path::Path;
 use std::rc::Rc;
 
+use crate::config::Config;
 use crate::controls::{Controller, SignalType, Slot};
-use crate::data::{Framebuffer, UniformBuffer};
+use crate::data::{ClearOptions, Framebuffer, RenderTarget, UniformBuffer};
+use crate::debug::DebugGroup;
+use crate::framegraph::FrameGraph;
+use crate::hooks::{PassContext, PassHook, PassHooks};
 use crate::meshes::{BasicMesh, Draw};
+use crate::profile_scope;
 use crate::scene::{Scene, SceneObject};
 use crate::shaders::ShaderProgram;
 use crate::spatial::Spatial;
+use crate::textures::{Texture2D, Texture2DMultisample};
 use crate::utils::constrained_step;
 use beryllium::Keycode;
 use gl33::gl_core_types::*;
@@ -17,17 +23,121 @@ use gl33::global_loader::*;
 use nalgebra_glm::*;
 
 const GAMMA: f32 = 2.2;
+const MIN_GAMMA: f32 = 1.0;
+const MAX_GAMMA: f32 = 3.0;
+const EXPOSURE_STEP: f32 = 0.25; // stops
+const MAX_EXPOSURE: f32 = 4.0;
+const WHITE_BALANCE_STEP: f32 = 0.1;
+const PIXEL_ART_RESOLUTION: (u32, u32) = (320, 180);
+const PIXEL_ART_PALETTE_LEVELS: u32 = 8;
+
+// Post-processing applied when a screen's framebuffer is drawn, either to the window or onto
+// another screen
+#[derive(Clone, Copy, Debug)]
+pub struct ScreenParameters {
+    pub sobel_on: bool,
+    pub msaa_on: bool,
+    pub gamma: f32,
+    pub tint: Vec3,
+    pub exposure: f32,       // in stops, applied before gamma correction
+    pub white_balance: Vec2, // temperature (cool to warm) and tint (green to magenta), in -1..1
+    pub blur_radius: u32,
+    pub resolution_scale: f32,
+    // Renders at exactly this size instead, upscaled without filtering
+    pub fixed_resolution: Option<(u32, u32)>,
+    pub palette_levels: u32, // per channel, 0 keeps full color
+    pub depth_texture: bool,
+    pub motion_vectors: bool,
+}
+
+impl ScreenParameters {
+    pub fn new() -> Self {
+        Self {
+            sobel_on: false,
+            msaa_on: false,
+            gamma: GAMMA,
+            tint: vec3(1.0, 1.0, 1.0),
+            exposure: 0.0,
+            white_balance: Vec2::zeros(),
+            blur_radius: 0,
+            resolution_scale: 1.0,
+            fixed_resolution: None,
+            palette_levels: 0,
+            depth_texture: false,
+            motion_vectors: false,
+        }
+    }
+
+    // Screens drawn onto another screen shouldn't gamma correct, since the target will
+    pub fn auxiliary() -> Self {
+        Self {
+            gamma: 1.0,
+            ..Self::new()
+        }
+    }
+
+    // Low resolution with chunky pixels and a reduced palette. MSAA would only blur the pixels.
+    pub fn pixel_art() -> Self {
+        Self {
+            msaa_on: false,
+            fixed_resolution: Some(PIXEL_ART_RESOLUTION),
+            palette_levels: PIXEL_ART_PALETTE_LEVELS,
+            ..Self::new()
+        }
+    }
+
+    fn target_size(&self, window_size: (u32, u32)) -> (u32, u32) {
+        self.fixed_resolution.map_or_else(
+            || Screen::scaled_size(window_size, self.resolution_scale),
+            |(width, height)| (width.max(1), height.max(1)),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransitionKind {
+    Fade,
+    Dissolve,
+    Wipe, // left to right
+}
+
+// Blend from an outgoing scene, rendered into its own target while the transition lasts, to the
+// one drawn normally
+struct Transition {
+    kind: TransitionKind,
+    duration: f32,
+    elapsed: f32,
+    outgoing: RenderTarget,
+}
+
+impl Transition {
+    fn progress(&self) -> f32 {
+        (self.elapsed / self.duration).clamp(0.0, 1.0)
+    }
+}
+
+// The passes a screen clears for on its own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenPass {
+    Scene, // into the framebuffer, before the scene is drawn
+    Post,  // into the window, before the post-processed scene is drawn
+}
 
 pub struct Screen {
     canvas: SceneObject,
-    clear_color: Vec4,
+    scene_clear: ClearOptions,
+    post_clear: ClearOptions,
     fbo: Framebuffer,
+    resolved: RenderTarget,
     shader: ShaderProgram,
-    sobel_on: bool,
-    msaa_on: bool,
-    gamma: f32,
+    params: ScreenParameters,
     ubo: UniformBuffer,
-    window_size: (u32, u32),
+    window_size: (u32, u32), // in window points, see `set_display_scale`
+    display_scale: f32,
+    fbo_size: (u32, u32),
+    transition: Option<Transition>,
+    hooks: Option<Rc<PassHooks>>,
+    overlay: Option<Texture2D>,
 }
 
 impl<'a> Screen {
@@ -38,47 +148,320 @@ impl<'a> Screen {
         shader: ShaderProgram,
         ubo: UniformBuffer,
     ) -> Self {
-        let fbo = Framebuffer::new().unwrap();
-        fbo.setup_with_renderbuffer(window_size);
-        Self {
+        Self::with_parameters(
             canvas,
             clear_color,
+            window_size,
+            shader,
+            ubo,
+            ScreenParameters::new(),
+        )
+    }
+
+    pub fn with_parameters(
+        canvas: SceneObject,
+        clear_color: Vec4,
+        window_size: (u32, u32),
+        shader: ShaderProgram,
+        ubo: UniformBuffer,
+        params: ScreenParameters,
+    ) -> Self {
+        let fbo = Self::make_framebuffer(&params);
+        let fbo_size = params.target_size(window_size);
+        fbo.setup(fbo_size);
+        Self {
+            canvas,
+            scene_clear: ClearOptions::all(clear_color),
+            // the canvas covers the whole window anyway
+            post_clear: ClearOptions {
+                color: Some(vec4(1.0, 1.0, 1.0, 1.0)),
+                ..ClearOptions::none()
+            },
             fbo,
+            resolved: Self::make_target(&params, fbo_size),
             shader,
-            sobel_on: false,
-            msaa_on: false,
-            gamma: GAMMA,
+            params,
             ubo,
             window_size,
+            display_scale: 1.0,
+            fbo_size,
+            transition: None,
+            hooks: None,
+            overlay: None,
         }
     }
-    pub fn clear_color(&self) {
-        unsafe {
-            glClearColor(
-                self.clear_color.x,
-                self.clear_color.y,
-                self.clear_color.z,
-                self.clear_color.w,
-            );
+
+    fn make_framebuffer(params: &ScreenParameters) -> Framebuffer {
+        Framebuffer::with_attachments(params.depth_texture, params.motion_vectors).unwrap()
+    }
+
+    // What post-processing samples from. Fixed resolutions are blown up to the window without
+    // filtering, so every pixel stays a sharp block.
+    fn make_target(params: &ScreenParameters, size: (u32, u32)) -> RenderTarget {
+        let target = RenderTarget::new(size).unwrap();
+        if params.fixed_resolution.is_some() {
+            let texture = target.get_texture();
+            texture.bind();
+            texture.set_filters(GL_NEAREST, GL_NEAREST);
+            Texture2D::clear_binding();
+        }
+        target
+    }
+
+    fn scaled_size(size: (u32, u32), scale: f32) -> (u32, u32) {
+        (
+            ((size.0 as f32 * scale) as u32).max(1),
+            ((size.1 as f32 * scale) as u32).max(1),
+        )
+    }
+
+    pub fn get_parameters(&self) -> &ScreenParameters {
+        &self.params
+    }
+
+    pub fn set_parameters(&mut self, params: ScreenParameters) {
+        let fbo_size = params.target_size(self.get_drawable_size());
+        if params.depth_texture != self.params.depth_texture
+            || params.motion_vectors != self.params.motion_vectors
+        {
+            self.fbo = Self::make_framebuffer(&params);
+            self.fbo.setup(fbo_size);
+        } else if fbo_size != self.fbo_size {
+            self.fbo.setup(fbo_size);
+        }
+        let filtering_changed =
+            params.fixed_resolution.is_some() != self.params.fixed_resolution.is_some();
+        if fbo_size != self.fbo_size || filtering_changed {
+            self.resolved = Self::make_target(&params, fbo_size);
+            if let Some(transition) = &mut self.transition {
+                transition.outgoing = Self::make_target(&params, fbo_size);
+            }
+            self.fbo_size = fbo_size;
         }
+        self.params = params;
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        self.fbo_size
+    }
+
+    // Pixels per window point. High-DPI displays have more than one, and the window's drawable
+    // is that much bigger than the window, so the framebuffer is resized to match.
+    pub fn set_display_scale(&mut self, scale: f32) {
+        self.display_scale = scale.max(1.0);
+        self.set_parameters(self.params);
     }
 
-    pub fn clear_buffers(&self) {
-        // TODO: maybe make more generic
+    // For overlays and picking, which get their sizes and mouse positions in window points
+    pub fn get_display_scale(&self) -> f32 {
+        self.display_scale
+    }
+
+    pub fn get_window_size(&self) -> (u32, u32) {
+        self.window_size
+    }
+
+    // The window in pixels
+    pub fn get_drawable_size(&self) -> (u32, u32) {
+        Self::scaled_size(self.window_size, self.display_scale)
+    }
+
+    // Multisampled scene depth, if the screen was set up with `depth_texture`
+    pub fn get_depth_texture(&self) -> Option<&Texture2DMultisample> {
+        self.fbo.get_depth_texture()
+    }
+
+    // Multisampled motion vectors, if the screen was set up with `motion_vectors`
+    pub fn get_velocity_texture(&self) -> Option<&Texture2DMultisample> {
+        self.fbo.get_velocity_texture()
+    }
+
+    fn set_viewport(size: (u32, u32)) {
         unsafe {
-            glClear(GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT | GL_STENCIL_BUFFER_BIT);
+            glViewport(0, 0, size.0 as i32, size.1 as i32);
+        }
+    }
+
+    // From now on, the scene passed to `draw_outgoing` blends into the one passed to
+    // `draw_on_framebuffer` over `duration` seconds
+    pub fn start_transition(&mut self, kind: TransitionKind, duration: f32) {
+        self.transition = Some(Transition {
+            kind,
+            duration: duration.max(f32::EPSILON),
+            elapsed: 0.0,
+            outgoing: Self::make_target(&self.params, self.fbo_size),
+        });
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    // Returns whether the transition ended, after which the outgoing scene isn't needed anymore
+    pub fn advance_transition(&mut self, dt: f32) -> bool {
+        match &mut self.transition {
+            Some(transition) => {
+                transition.elapsed += dt;
+                if transition.elapsed >= transition.duration {
+                    self.transition = None;
+                    return true;
+                }
+                false
+            }
+            None => false,
         }
     }
 
+    fn set_post_uniforms(&self) {
+        self.shader.use_program();
+        self.shader.set_1f("gamma", self.params.gamma);
+        self.shader
+            .set_texture2D("screenTexture", self.resolved.get_texture());
+        match &self.transition {
+            Some(transition) => {
+                self.shader.set_texture2D_at(
+                    "transition.outgoing",
+                    transition.outgoing.get_texture(),
+                    1,
+                );
+                self.shader
+                    .set_1f("transition.progress", transition.progress());
+                self.shader
+                    .set_1i("transition.kind", transition.kind as i32);
+            }
+            None => self.shader.set_1f("transition.progress", 1.0),
+        }
+        match &self.overlay {
+            Some(overlay) => {
+                self.shader.set_texture2D_at("overlay.image", overlay, 2);
+                self.shader.set_1b("overlay.enabled", true);
+            }
+            None => self.shader.set_1b("overlay.enabled", false),
+        }
+        self.shader.set_1b("applySobel", self.params.sobel_on);
+        self.shader.set_3f("tint", &self.params.tint);
+        self.shader.set_1f("exposure", self.params.exposure);
+        self.shader
+            .set_2f("whiteBalance", &self.params.white_balance);
+        self.shader.set_1i("blurRadius", self.params.blur_radius as i32);
+        self.shader
+            .set_1i("paletteLevels", self.params.palette_levels as i32);
+    }
+
+    pub fn get_clear(&self, pass: ScreenPass) -> &ClearOptions {
+        match pass {
+            ScreenPass::Scene => &self.scene_clear,
+            ScreenPass::Post => &self.post_clear,
+        }
+    }
+
+    // Buffers left uncleared keep what the pass drew last frame. The velocity is cleared either
+    // way, since motion from a previous frame is never right.
+    pub fn set_clear(&mut self, pass: ScreenPass, clear: ClearOptions) {
+        match pass {
+            ScreenPass::Scene => self.scene_clear = clear,
+            ScreenPass::Post => self.post_clear = clear,
+        }
+    }
+
+    // Renders the scene multisampled and resolves it into the single-sample target that every
+    // post effect reads from. With MSAA off, every sample gets the same coverage, so the resolve
+    // is a plain copy.
     pub fn draw_on_framebuffer(&mut self, scene: &mut Scene) {
+        let _group = DebugGroup::new("Scene");
+        self.render(scene);
+        let _resolve_group = DebugGroup::new("MSAA resolve");
+        self.resolved.resolve(&self.fbo);
+    }
+
+    // Same as `draw_on_framebuffer`, for the scene being transitioned away from. Does nothing when
+    // there's no transition going on.
+    pub fn draw_outgoing(&mut self, scene: &mut Scene) {
+        if self.transition.is_none() {
+            return;
+        }
+        let _group = DebugGroup::new("Outgoing scene");
+        self.render(scene);
+        if let Some(transition) = &self.transition {
+            transition.outgoing.resolve(&self.fbo);
+        }
+    }
+
+    fn render(&mut self, scene: &mut Scene) {
         self.fbo.bind();
-        self.clear_color();
-        self.clear_buffers();
+        Self::set_viewport(self.fbo_size);
+        self.scene_clear.apply();
+        self.fbo.clear_velocity();
         unsafe {
             glEnable(GL_DEPTH_TEST);
+            if !self.params.msaa_on {
+                glDisable(GL_MULTISAMPLE);
+            }
         }
         scene.compose(&self.ubo);
-        Framebuffer::clear_binding();
+        unsafe {
+            glEnable(GL_MULTISAMPLE);
+        }
+    }
+
+    // `BeforePost` callbacks run in `draw_on_screen`, drawing into the resolved scene
+    pub fn set_hooks(&mut self, hooks: Rc<PassHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    // Shown over the post-processed frame as is, pixel for pixel
+    pub fn set_overlay(&mut self, overlay: Option<Texture2D>) {
+        self.overlay = overlay;
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    // What the screen draws each frame, in order. `scene_inputs` are targets drawn earlier in the
+    // frame that the scene samples, like a mirror's.
+    pub fn describe_passes(
+        &self,
+        graph: &mut FrameGraph,
+        scene_inputs: &[&str],
+    ) -> Result<(), String> {
+        let samples = self.fbo.get_samples();
+        let depth_format = if self.params.depth_texture {
+            "D24S8 texture"
+        } else {
+            "D24S8"
+        };
+        graph.add_resource("Scene color", self.fbo_size, "RGB8", samples);
+        graph.add_resource("Scene depth", self.fbo_size, depth_format, samples);
+        let mut scene_writes = vec!["Scene color", "Scene depth"];
+        if self.params.motion_vectors {
+            graph.add_resource("Velocity", self.fbo_size, "RG16F", samples);
+            scene_writes.push("Velocity");
+        }
+        graph.add_resource("Resolved scene", self.fbo_size, "RGBA8", 1);
+        graph.add_resource("Window", self.get_drawable_size(), "default", 1);
+
+        let mut post_reads = vec!["Resolved scene"];
+        if self.transition.is_some() {
+            graph.add_resource("Outgoing scene", self.fbo_size, "RGBA8", 1);
+            graph.add_pass("Outgoing scene", &[], &scene_writes)?;
+            graph.add_pass("Outgoing resolve", &["Scene color"], &["Outgoing scene"])?;
+            post_reads.push("Outgoing scene");
+        }
+        graph.add_pass("Scene", scene_inputs, &scene_writes)?;
+        graph.add_pass("MSAA resolve", &["Scene color"], &["Resolved scene"])?;
+        if self
+            .hooks
+            .as_ref()
+            .map_or(false, |h| h.has(PassHook::BeforePost))
+        {
+            graph.add_pass("BeforePost hooks", &["Resolved scene"], &["Resolved scene"])?;
+        }
+        graph.add_pass("Post-processing", &post_reads, &["Window"])
+    }
+
+    pub fn get_resolved_texture(&self) -> &Texture2D {
+        self.resolved.get_texture()
     }
 
     pub fn bind(&self) {
@@ -87,8 +470,7 @@ impl<'a> Screen {
 
     pub fn draw_on_another(&self, other: &Screen, scaling: f32, offset: Vec2) {
         other.fbo.bind();
-        self.ubo.bind_base();
-
+        Self::set_viewport(other.fbo_size);
         let mut transformed_canvas = self.canvas.clone();
         transformed_canvas.scale(&vec3(scaling, scaling, scaling));
         transformed_canvas.translate(&vec3(offset.x, offset.y, 0.0));
@@ -97,33 +479,37 @@ impl<'a> Screen {
             glDisable(GL_DEPTH_TEST);
         }
 
-        self.shader.use_program();
-        self.shader.set_1f("gamma", 1.0);
-        self.shader
-            .set_texture2D_multisample("screenTexture", self.fbo.get_texture());
+        self.set_post_uniforms();
         self.ubo.set_model_mat(&transformed_canvas.get_model());
+        self.ubo.commit();
         transformed_canvas.draw(&self.shader);
     }
 
     pub fn draw_on_screen(&self) {
+        if let Some(hooks) = self.hooks.as_ref().filter(|h| h.has(PassHook::BeforePost)) {
+            let _group = DebugGroup::new("BeforePost hooks");
+            self.resolved.bind();
+            Self::set_viewport(self.fbo_size);
+            hooks.run(&PassContext::new(
+                PassHook::BeforePost,
+                self.shader,
+                &self.ubo,
+                None,
+            ));
+        }
+        let _group = DebugGroup::new("Post-processing");
+        profile_scope!("Post-processing");
         Framebuffer::clear_binding();
-        self.ubo.bind_base();
+        Self::set_viewport(self.get_drawable_size());
 
+        self.post_clear.apply();
         unsafe {
-            glClearColor(1.0, 1.0, 1.0, 1.0);
-            glClear(GL_COLOR_BUFFER_BIT);
             glDisable(GL_DEPTH_TEST);
         }
 
-        self.shader.use_program();
-        self.shader.set_1f("gamma", self.gamma);
-        self.shader
-            .set_texture2D_multisample("screenTexture", self.fbo.get_texture());
-        self.shader
-            .set_1i("sampleCount", self.fbo.get_texture().get_samples() as i32);
-        self.shader.set_1b("applySobel", self.sobel_on);
-        self.shader.set_1b("applyMSAA", self.msaa_on);
+        self.set_post_uniforms();
         self.ubo.set_model_mat(&identity());
+        self.ubo.commit();
         self.canvas.draw(&self.shader);
     }
 }
@@ -132,6 +518,9 @@ pub struct ScreenController {
     sobel_on: bool,
     msaa_on: bool,
     gamma: f32,
+    exposure: f32,
+    white_balance: Vec2,
+    pixel_art: bool,
 }
 
 impl ScreenController {
@@ -140,17 +529,67 @@ impl ScreenController {
             sobel_on: false,
             msaa_on: true,
             gamma: GAMMA,
+            exposure: 0.0,
+            white_balance: Vec2::zeros(),
+            pixel_art: false,
         }))
     }
     pub fn on_key_pressed(&mut self, keycode: Keycode) {
+        let balance_step = |value: f32, step: f32| (value + step).clamp(-1.0, 1.0);
         match keycode {
             Keycode::E => self.sobel_on = !self.sobel_on,
             Keycode::M => self.msaa_on = !self.msaa_on,
-            Keycode::EQUALS => self.gamma = (self.gamma + 0.2).min(3.0),
-            Keycode::MINUS => self.gamma = (self.gamma - 0.2).max(1.0),
+            Keycode::F6 => {
+                self.pixel_art = !self.pixel_art;
+                self.msaa_on = !self.pixel_art;
+            }
+            Keycode::EQUALS => self.gamma = (self.gamma + 0.2).min(MAX_GAMMA),
+            Keycode::MINUS => self.gamma = (self.gamma - 0.2).max(MIN_GAMMA),
+            Keycode::I => self.exposure = (self.exposure + EXPOSURE_STEP).min(MAX_EXPOSURE),
+            Keycode::K => self.exposure = (self.exposure - EXPOSURE_STEP).max(-MAX_EXPOSURE),
+            Keycode::L => {
+                self.white_balance.x = balance_step(self.white_balance.x, WHITE_BALANCE_STEP)
+            }
+            Keycode::J => {
+                self.white_balance.x = balance_step(self.white_balance.x, -WHITE_BALANCE_STEP)
+            }
+            Keycode::O => {
+                self.white_balance.y = balance_step(self.white_balance.y, WHITE_BALANCE_STEP)
+            }
+            Keycode::U => {
+                self.white_balance.y = balance_step(self.white_balance.y, -WHITE_BALANCE_STEP)
+            }
             _ => (),
         }
     }
+
+    // Color adjustments are kept between runs. Saved values are held to the ranges the keys
+    // allow, since the file may have been edited by hand.
+    pub fn load_settings(&mut self, config: &Config) {
+        let setting = |key: &str, current: f32, min: f32, max: f32| {
+            config
+                .get::<f32>(key)
+                .filter(|value| value.is_finite())
+                .unwrap_or(current)
+                .clamp(min, max)
+        };
+        self.gamma = setting("screen.gamma", self.gamma, MIN_GAMMA, MAX_GAMMA);
+        self.exposure = setting(
+            "screen.exposure",
+            self.exposure,
+            -MAX_EXPOSURE,
+            MAX_EXPOSURE,
+        );
+        self.white_balance.x = setting("screen.temperature", self.white_balance.x, -1.0, 1.0);
+        self.white_balance.y = setting("screen.white_tint", self.white_balance.y, -1.0, 1.0);
+    }
+
+    pub fn save_settings(&self, config: &mut Config) {
+        config.set("screen.gamma", self.gamma);
+        config.set("screen.exposure", self.exposure);
+        config.set("screen.temperature", self.white_balance.x);
+        config.set("screen.white_tint", self.white_balance.y);
+    }
 }
 
 impl<'a> Slot for ScreenController {
@@ -168,8 +607,22 @@ impl<'a> Controller<'a, Screen, ScreenController> for Rc<RefCell<ScreenControlle
     }
     fn process_signals(&'a self, obj: &mut Screen) {
         let self_obj = (**self).borrow();
-        obj.sobel_on = self_obj.sobel_on;
-        obj.msaa_on = self_obj.msaa_on;
-        obj.gamma = self_obj.gamma;
+        obj.params.sobel_on = self_obj.sobel_on;
+        obj.params.msaa_on = self_obj.msaa_on;
+        obj.params.gamma = self_obj.gamma;
+        obj.params.exposure = self_obj.exposure;
+        obj.params.white_balance = self_obj.white_balance;
+        if self_obj.pixel_art != obj.params.fixed_resolution.is_some() {
+            let preset = if self_obj.pixel_art {
+                ScreenParameters::pixel_art()
+            } else {
+                ScreenParameters::new()
+            };
+            obj.set_parameters(ScreenParameters {
+                fixed_resolution: preset.fixed_resolution,
+                palette_levels: preset.palette_levels,
+                ..obj.params
+            });
+        }
     }
 }
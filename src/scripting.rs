@@ -0,0 +1,250 @@
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use nalgebra_glm::*;
+use rhai::{Engine, Scope, AST};
+
+use crate::lighting::Lighting;
+use crate::scene::{ObjectHandle, Prefab, SceneObject, SpawnQueue};
+use crate::spatial::Spatial;
+use crate::textures::Material;
+use crate::vfs;
+
+const UPDATE_CALLBACK: &str = "update";
+
+#[derive(Clone, Debug)]
+enum ScriptCommand {
+    Spawn(usize, Vec3, f32),
+    Despawn(usize),
+    SetTransform(usize, Vec3, f32),
+    SetLightPosition(usize, Vec3),
+    SetLightEnabled(usize, bool),
+}
+
+// What the registered functions share with the runtime. Scripts refer to the objects they spawned
+// by index into `handles`.
+#[derive(Default)]
+struct ScriptState {
+    commands: Vec<ScriptCommand>,
+    handles: Vec<Option<ObjectHandle>>,
+    light_count: usize,
+}
+
+struct Script {
+    path: String,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+// Rhai scripts loaded with the scene. Scripts run their top level once when loaded and may define
+// `fn update(time, dt)`, called every frame. Whatever they ask for is queued and applied to the
+// scene by `apply`; their spawns go through `spawns`, which is applied with the scene's.
+pub struct ScriptRuntime {
+    engine: Engine,
+    scripts: Vec<Script>,
+    state: Rc<RefCell<ScriptState>>,
+    material: Material,
+    pub spawns: SpawnQueue,
+}
+
+impl ScriptRuntime {
+    // `material` is the one given to the cubes scripts spawn
+    pub fn new(material: Material) -> Self {
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+        let mut engine = Engine::new();
+        Self::register_api(&mut engine, &state);
+        Self {
+            engine,
+            scripts: vec![],
+            state,
+            material,
+            spawns: SpawnQueue::new(),
+        }
+    }
+
+    fn register_api(engine: &mut Engine, state: &Rc<RefCell<ScriptState>>) {
+        let s = state.clone();
+        engine.register_fn("spawn_cube", move |x: f64, y: f64, z: f64, side: f64| {
+            let mut state = s.borrow_mut();
+            let id = state.handles.len();
+            state.handles.push(None);
+            let position = vec3(x as f32, y as f32, z as f32);
+            state
+                .commands
+                .push(ScriptCommand::Spawn(id, position, side as f32));
+            id as i64
+        });
+        let s = state.clone();
+        engine.register_fn("despawn", move |id: i64| {
+            s.borrow_mut()
+                .commands
+                .push(ScriptCommand::Despawn(id as usize));
+        });
+        let s = state.clone();
+        engine.register_fn(
+            "set_transform",
+            move |id: i64, x: f64, y: f64, z: f64, yaw: f64| {
+                let position = vec3(x as f32, y as f32, z as f32);
+                s.borrow_mut().commands.push(ScriptCommand::SetTransform(
+                    id as usize,
+                    position,
+                    yaw as f32,
+                ));
+            },
+        );
+        let s = state.clone();
+        engine.register_fn("light_count", move || s.borrow().light_count as i64);
+        let s = state.clone();
+        engine.register_fn(
+            "set_light_position",
+            move |light: i64, x: f64, y: f64, z: f64| {
+                let position = vec3(x as f32, y as f32, z as f32);
+                s.borrow_mut()
+                    .commands
+                    .push(ScriptCommand::SetLightPosition(light as usize, position));
+            },
+        );
+        let s = state.clone();
+        engine.register_fn("set_light_enabled", move |light: i64, on: bool| {
+            s.borrow_mut()
+                .commands
+                .push(ScriptCommand::SetLightEnabled(light as usize, on));
+        });
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let source = vfs::read_to_string(path)?;
+        let ast = self
+            .engine
+            .compile(&source)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut scope = Scope::new();
+        self.engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.scripts.push(Script {
+            path: path.display().to_string(),
+            ast,
+            scope,
+        });
+        Ok(())
+    }
+
+    pub fn update(&mut self, lighting: &Lighting, time: f32, dt: f32) {
+        self.state.borrow_mut().light_count = lighting.point.len();
+        for script in &mut self.scripts {
+            if !script
+                .ast
+                .iter_functions()
+                .any(|f| f.name == UPDATE_CALLBACK)
+            {
+                continue;
+            }
+            let args = (time as f64, dt as f64);
+            if let Err(e) =
+                self.engine
+                    .call_fn::<()>(&mut script.scope, &script.ast, UPDATE_CALLBACK, args)
+            {
+                eprintln!("{}: {}", script.path, e);
+            }
+        }
+    }
+
+    // Transforms of objects spawned this frame wait until the spawn went through. Those of objects
+    // that are gone are dropped.
+    pub fn apply(&mut self, objects: &mut [SceneObject], lighting: &mut Lighting) {
+        let mut state = self.state.borrow_mut();
+        let commands = std::mem::take(&mut state.commands);
+        for command in commands {
+            match command {
+                ScriptCommand::Spawn(id, position, side) => {
+                    let prefab = Prefab::Cube(side, self.material.clone());
+                    state.handles[id] = Some(self.spawns.spawn(prefab, &translation(&position)));
+                }
+                ScriptCommand::Despawn(id) => {
+                    match state.handles.get_mut(id).and_then(Option::take) {
+                        Some(handle) => self.spawns.despawn(handle),
+                        None => eprintln!("Scripts despawned object {}, which doesn't exist", id),
+                    }
+                }
+                ScriptCommand::SetTransform(id, position, yaw) => {
+                    let handle = state.handles.get(id).copied().flatten();
+                    let object = objects.iter_mut().find(|o| Some(o.get_handle()) == handle);
+                    match (handle, object) {
+                        (Some(_), Some(object)) => object.set_model(
+                            &(translation(&position) * rotation(yaw, &vec3(0.0, 1.0, 0.0))),
+                        ),
+                        (Some(handle), None) if self.spawns.is_pending(handle) => {
+                            state.commands.push(command)
+                        }
+                        (Some(_), None) => {
+                            eprintln!("Scripts moved object {}, which isn't in the scene", id)
+                        }
+                        (None, _) => (),
+                    }
+                }
+                ScriptCommand::SetLightPosition(light, position) => {
+                    if let Some(light) = lighting.point.get_mut(light) {
+                        light.pos = position;
+                    }
+                }
+                ScriptCommand::SetLightEnabled(light, on) => {
+                    if let Some(light) = lighting.point.get_mut(light) {
+                        light.on = on;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_callback_queues_commands() {
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+        let mut engine = Engine::new();
+        ScriptRuntime::register_api(&mut engine, &state);
+        let ast = engine
+            .compile(
+                "fn update(time, dt) { set_light_position(0, time, 1.0, 0.0); spawn_cube(0.0, 0.0, 0.0, 1.0); }",
+            )
+            .unwrap();
+        let mut scope = Scope::new();
+        engine
+            .call_fn::<()>(&mut scope, &ast, UPDATE_CALLBACK, (2.0_f64, 0.1_f64))
+            .unwrap();
+
+        let state = state.borrow();
+        assert_eq!(state.commands.len(), 2);
+        assert_eq!(state.handles, vec![None]);
+        match state.commands[0] {
+            ScriptCommand::SetLightPosition(0, position) => {
+                assert_eq!(position, vec3(2.0, 1.0, 0.0))
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn transforms_only_wait_for_pending_spawns() {
+        let mut lighting = Lighting::test_rig(vec![]);
+        let mut runtime = ScriptRuntime::new(Material::new(vec![], vec![], 1.0));
+        {
+            let mut state = runtime.state.borrow_mut();
+            state.handles = vec![None];
+            state.commands = vec![
+                ScriptCommand::Spawn(0, Vec3::zeros(), 1.0),
+                ScriptCommand::SetTransform(0, vec3(1.0, 0.0, 0.0), 0.0),
+            ];
+        }
+        runtime.apply(&mut [], &mut lighting);
+        assert_eq!(runtime.state.borrow().commands.len(), 1);
+
+        // the spawn went through, but the object didn't make it into the scene
+        runtime.spawns = SpawnQueue::new();
+        runtime.apply(&mut [], &mut lighting);
+        assert!(runtime.state.borrow().commands.is_empty());
+    }
+}
@@ -4,6 +4,8 @@ use gl33::gl_groups::*;
 use gl33::global_loader::*;
 use nalgebra_glm::vec3;
 use nalgebra_glm::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::ffi::c_void;
 use std::ffi::CString;
 use std::path::Path;
@@ -14,11 +16,159 @@ use crate::helpers;
 use crate::lighting::DirectionalLight;
 use crate::lighting::PointLight;
 use crate::lighting::Spotlight;
+use crate::meshes::{instance_attribute_locations, AttributeSlot};
 use crate::textures::CubeMap;
 use crate::textures::Texture2DMultisample;
-use crate::textures::{Material, Texture2D};
+use crate::textures::{
+    AlphaMode, BlendOp, Material, SplatMap, Texture2D, TextureType, MAX_SPLAT_LAYERS,
+};
 use crate::utils;
 
+thread_local! {
+    static MAX_TEXTURE_UNITS: Cell<Option<u32>> = Cell::new(None);
+    // programs and materials already warned about, so an overflow is reported once and not per draw
+    static UNIT_WARNINGS: RefCell<HashSet<(u32, String)>> = RefCell::new(HashSet::new());
+    // stand-ins for missing diffuse and specular maps, made by the first draw that needs them
+    static PLACEHOLDER_MAPS: (Texture2D, Texture2D) = (
+        placeholder_map(TextureType::Diffuse),
+        placeholder_map(TextureType::Specular),
+    );
+}
+
+// All zeros, so a missing map adds nothing
+fn placeholder_map(ttype: TextureType) -> Texture2D {
+    let map = Texture2D::new(ttype);
+    map.empty_texture();
+    map
+}
+
+// GL_MAX_TEXTURE_IMAGE_UNITS, at least 16 on GL 3.3
+pub fn max_texture_units() -> u32 {
+    MAX_TEXTURE_UNITS.with(|max| match max.get() {
+        Some(units) => units,
+        None => {
+            let mut units = 0;
+            unsafe {
+                glGetIntegerv(GL_MAX_TEXTURE_IMAGE_UNITS, &mut units);
+            }
+            let units = (units as u32).max(16);
+            max.set(Some(units));
+            units
+        }
+    })
+}
+
+// The last unit, kept clear of the ones materials take, which start at 0
+pub fn environment_unit() -> u32 {
+    max_texture_units() - 1
+}
+
+// How many of a material's maps get a texture unit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnitPlan {
+    pub diffuse: usize,
+    pub specular: usize,
+    pub transmission: bool,
+    pub splat_layers: usize, // all of them or none, the mask weights every layer
+    pub splat_normals: usize,
+}
+
+impl UnitPlan {
+    pub fn of(material: &Material) -> Self {
+        let splat = material.get_splat_map();
+        Self {
+            diffuse: material.get_diffuse_maps().len(),
+            specular: material.get_specular_maps().len(),
+            transmission: material.get_transmission().map.is_some(),
+            splat_layers: splat.map_or(0, |splat| splat.layers.len()),
+            splat_normals: splat.map_or(0, |splat| {
+                splat
+                    .layers
+                    .iter()
+                    .filter(|layer| layer.normal.is_some())
+                    .count()
+            }),
+        }
+    }
+
+    // Missing diffuse and specular maps still take a unit each, for their placeholders
+    pub fn units(&self) -> u32 {
+        let splat = if self.splat_layers > 0 {
+            1 + self.splat_layers + self.splat_normals
+        } else {
+            0
+        };
+        (self.diffuse.max(1) + self.specular.max(1) + self.transmission as usize + splat) as u32
+    }
+
+    // Drops the least important maps until the rest fit in `budget` units: splat normals, extra
+    // specular and diffuse maps (last first), the transmission map and then the whole splat map.
+    // The first diffuse and specular maps are always kept.
+    pub fn fit(mut self, budget: u32) -> Self {
+        while self.units() > budget {
+            if self.splat_normals > 0 {
+                self.splat_normals -= 1;
+            } else if self.specular > 1 {
+                self.specular -= 1;
+            } else if self.diffuse > 1 {
+                self.diffuse -= 1;
+            } else if self.transmission {
+                self.transmission = false;
+            } else if self.splat_layers > 0 {
+                self.splat_layers = 0;
+            } else {
+                break;
+            }
+        }
+        self
+    }
+
+    // What `fitted` leaves out of this plan
+    pub fn describe_drops(&self, fitted: &UnitPlan) -> String {
+        let mut dropped = vec![];
+        let counts = [
+            ("splat normal", self.splat_normals - fitted.splat_normals),
+            ("specular", self.specular - fitted.specular),
+            ("diffuse", self.diffuse - fitted.diffuse),
+            (
+                "transmission",
+                (self.transmission && !fitted.transmission) as usize,
+            ),
+        ];
+        for (map, count) in counts {
+            if count > 0 {
+                dropped.push(format!("{} {} map(s)", count, map));
+            }
+        }
+        if self.splat_layers > 0 && fitted.splat_layers == 0 {
+            dropped.push("the splat map".to_string());
+        }
+        dropped.join(", ")
+    }
+}
+
+fn warn_unit_overflow(program: u32, material: &Material, needed: &UnitPlan, fitted: &UnitPlan) {
+    let name = material
+        .get_diffuse_maps()
+        .first()
+        .map(|map| map.get_path())
+        .filter(|path| !path.is_empty())
+        .unwrap_or("untextured material")
+        .to_string();
+    let first_time =
+        UNIT_WARNINGS.with(|warned| warned.borrow_mut().insert((program, name.clone())));
+    if first_time {
+        eprintln!(
+            "Material {} needs {} texture units in program {}, only {} are free; dropping {}",
+            name,
+            needed.units(),
+            program,
+            environment_unit(),
+            needed.describe_drops(fitted)
+        );
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Shader(pub u32);
 
@@ -75,7 +225,16 @@ impl Shader {
     }
 
     pub fn from_source(ty: ShaderType, path: &Path) -> Result<Self, String> {
-        let source = helpers::read_from_file(path);
+        Self::from_source_with_defines(ty, path, &[])
+    }
+
+    // For variants of a shader, which check for the `defines` with #ifdef
+    pub fn from_source_with_defines(
+        ty: ShaderType,
+        path: &Path,
+        defines: &[&str],
+    ) -> Result<Self, String> {
+        let source = with_defines(&helpers::read_from_file(path), defines);
         let obj = Self::new(ty).ok_or_else(|| "Couldn't allocate new shader".to_string())?;
         obj.set_source(&source[..]);
         obj.compile();
@@ -89,12 +248,105 @@ impl Shader {
     }
 }
 
+// Nothing may come before #version, so the defines go right after it
+fn with_defines(source: &str, defines: &[&str]) -> String {
+    if defines.is_empty() {
+        return source.to_string();
+    }
+    let (version, rest) = match source.split_once('\n') {
+        Some((first, rest)) if first.trim_start().starts_with("#version") => (first, rest),
+        _ => ("", source),
+    };
+    let mut out = String::with_capacity(source.len() + defines.len() * 16);
+    if !version.is_empty() {
+        out += version;
+        out += "\n";
+    }
+    for define in defines {
+        out += &format!("#define {}\n", define);
+    }
+    out += rest;
+    out
+}
+
 pub enum ShaderType {
     VertexShader = GL_VERTEX_SHADER.0 as isize,
     GeometryShader = GL_GEOMETRY_SHADER.0 as isize,
     FragmentShader = GL_FRAGMENT_SHADER.0 as isize,
 }
 
+// An input attribute the linked program actually reads
+#[derive(Clone, Debug)]
+pub struct ActiveAttribute {
+    pub name: String,
+    pub location: i32,
+    pub gl_type: GLenum,
+    pub array_size: i32,
+}
+
+impl ActiveAttribute {
+    // Consecutive locations taken, and components read from each of them
+    fn footprint(&self) -> Option<(u32, u32)> {
+        let (locations, components) = [
+            (GL_FLOAT, (1, 1)),
+            (GL_FLOAT_VEC2, (1, 2)),
+            (GL_FLOAT_VEC3, (1, 3)),
+            (GL_FLOAT_VEC4, (1, 4)),
+            (GL_INT, (1, 1)),
+            (GL_INT_VEC2, (1, 2)),
+            (GL_INT_VEC3, (1, 3)),
+            (GL_INT_VEC4, (1, 4)),
+            (GL_FLOAT_MAT2, (2, 2)),
+            (GL_FLOAT_MAT3, (3, 3)),
+            (GL_FLOAT_MAT4, (4, 4)),
+        ]
+        .iter()
+        .find(|(ty, _)| ty.0 == self.gl_type.0)?
+        .1;
+        Some((locations * self.array_size.max(1) as u32, components))
+    }
+}
+
+// Checks that every attribute a program reads is fed by `layout` with at least as many components,
+// reporting all mismatches at once
+pub fn check_attributes(
+    attributes: &[ActiveAttribute],
+    layout: &[AttributeSlot],
+) -> Result<(), String> {
+    let mut errors = vec![];
+    for attribute in attributes {
+        // built-ins like gl_VertexID don't come from buffers
+        if attribute.name.starts_with("gl_") {
+            continue;
+        }
+        let Some((locations, components)) = attribute.footprint() else {
+            errors.push(format!(
+                "{} has an unsupported type ({:#x})",
+                attribute.name, attribute.gl_type.0
+            ));
+            continue;
+        };
+        for location in attribute.location as u32..attribute.location as u32 + locations {
+            match layout.iter().find(|slot| slot.location == location) {
+                None => errors.push(format!(
+                    "{} reads location {}, which the mesh doesn't provide",
+                    attribute.name, location
+                )),
+                Some(slot) if slot.components < components => errors.push(format!(
+                    "{} reads {} components at location {}, but the mesh only provides {}",
+                    attribute.name, components, location, slot.components
+                )),
+                _ => (),
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct ShaderProgram(pub u32);
 impl ShaderProgram {
@@ -111,7 +363,15 @@ impl ShaderProgram {
         glAttachShader(self.0, shader.0);
     }
 
+    // Instance attributes don't have fixed locations in the shaders, they're bound to wherever the
+    // meshes put them. Names a program doesn't declare are ignored.
     pub fn link_program(&self) {
+        for (attribute, location) in instance_attribute_locations() {
+            let name = CString::new(attribute.name).unwrap();
+            unsafe {
+                glBindAttribLocation(self.0, location, name.as_ptr() as *const u8);
+            }
+        }
         glLinkProgram(self.0);
     }
 
@@ -147,11 +407,22 @@ impl ShaderProgram {
     }
 
     pub fn from_vert_frag(vert: &str, frag: &str) -> Result<Self, String> {
+        Self::from_vert_frag_with_defines(vert, frag, &[])
+    }
+
+    // The `defines` go to both stages
+    pub fn from_vert_frag_with_defines(
+        vert: &str,
+        frag: &str,
+        defines: &[&str],
+    ) -> Result<Self, String> {
         let p = Self::new().ok_or_else(|| "Couldn't allocate a program".to_string())?;
-        let v = Shader::from_source(ShaderType::VertexShader, &Path::new(vert))
-            .map_err(|e| format!("Vertex Compile Error: {}", e))?;
-        let f = Shader::from_source(ShaderType::FragmentShader, &Path::new(frag))
-            .map_err(|e| format!("Fragment Compile Error: {}", e))?;
+        let v =
+            Shader::from_source_with_defines(ShaderType::VertexShader, &Path::new(vert), defines)
+                .map_err(|e| format!("Vertex Compile Error: {}", e))?;
+        let f =
+            Shader::from_source_with_defines(ShaderType::FragmentShader, &Path::new(frag), defines)
+                .map_err(|e| format!("Fragment Compile Error: {}", e))?;
         p.attach_shader(&v);
         p.attach_shader(&f);
         p.link_program();
@@ -190,6 +461,51 @@ impl ShaderProgram {
         }
     }
 
+    // Names, locations and types of the attributes left after linking
+    pub fn active_attributes(&self) -> Vec<ActiveAttribute> {
+        let (mut count, mut max_length) = (0, 0);
+        unsafe {
+            glGetProgramiv(self.0, GL_ACTIVE_ATTRIBUTES, &mut count);
+            glGetProgramiv(self.0, GL_ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+        }
+        let mut attributes = vec![];
+        for index in 0..count as u32 {
+            let mut name: Vec<u8> = vec![0; max_length.max(1) as usize];
+            let (mut length, mut size, mut gl_type) = (0, 0, GLenum(0));
+            unsafe {
+                glGetActiveAttrib(
+                    self.0,
+                    index,
+                    max_length,
+                    &mut length,
+                    &mut size,
+                    &mut gl_type,
+                    name.as_mut_ptr(),
+                );
+            }
+            name.truncate(length as usize);
+            let c_name = CString::new(name.clone()).unwrap();
+            let location = unsafe { glGetAttribLocation(self.0, c_name.as_ptr() as *const u8) };
+            attributes.push(ActiveAttribute {
+                name: String::from_utf8_lossy(&name).into_owned(),
+                location,
+                gl_type,
+                array_size: size,
+            });
+        }
+        attributes
+    }
+
+    // Whether a drawable with the given layout can be drawn with this program
+    pub fn validate_attributes(&self, layout: &[AttributeSlot]) -> Result<(), String> {
+        check_attributes(&self.active_attributes(), layout)
+    }
+
+    // False for uniforms the program doesn't declare or never reads
+    pub fn has_uniform(&self, name: &str) -> bool {
+        self.get_uniform_location(name) >= 0
+    }
+
     fn get_uniform_location(&self, name: &str) -> i32 {
         let uniform_name = CString::new(name.as_bytes()).unwrap().into_raw() as *const u8;
         let location: i32;
@@ -219,6 +535,10 @@ impl ShaderProgram {
         let location = self.get_uniform_location(name);
         unsafe { glUniform3f(location, value.x, value.y, value.z) }
     }
+    pub fn set_2f(&self, name: &str, value: &Vec2) {
+        let location = self.get_uniform_location(name);
+        unsafe { glUniform2f(location, value.x, value.y) }
+    }
     pub fn set_matrix_4fv(&self, name: &str, value: &Mat4) {
         let location = self.get_uniform_location(name);
         unsafe { glUniformMatrix4fv(location, 1, 0, value.as_ptr()) }
@@ -229,14 +549,18 @@ impl ShaderProgram {
     }
     #[allow(non_snake_case)]
     pub fn set_texture2D(&self, texture_name: &str, value: &Texture2D) {
+        self.set_texture2D_at(texture_name, value, 0);
+    }
+    #[allow(non_snake_case)]
+    pub fn set_texture2D_at(&self, texture_name: &str, value: &Texture2D, unit: u32) {
         unsafe {
-            glActiveTexture(GLenum(GL_TEXTURE0.0 as u32));
+            glActiveTexture(GLenum(GL_TEXTURE0.0 + unit));
         }
         value.bind();
-        self.set_1i(texture_name, 0 as i32);
-        // unsafe {
-        //     glActiveTexture(GLenum(GL_TEXTURE0.0 as u32));
-        // }
+        self.set_1i(texture_name, unit as i32);
+        unsafe {
+            glActiveTexture(GLenum(GL_TEXTURE0.0 as u32));
+        }
     }
     #[allow(non_snake_case)]
     pub fn set_texture2D_multisample(&self, texture_name: &str, value: &Texture2DMultisample) {
@@ -250,18 +574,26 @@ impl ShaderProgram {
         // }
     }
     pub fn set_cubemap(&self, texture_name: &str, value: &CubeMap) {
+        self.set_cubemap_at(texture_name, value, 0);
+    }
+    pub fn set_cubemap_at(&self, texture_name: &str, value: &CubeMap, unit: u32) {
         unsafe {
-            glActiveTexture(GLenum(GL_TEXTURE0.0 as u32));
+            glActiveTexture(GLenum(GL_TEXTURE0.0 + unit));
         }
         value.bind();
-        self.set_1i(texture_name, 0 as i32);
-        // unsafe {
-        //     glActiveTexture(GLenum(GL_TEXTURE0.0 as u32));
-        // }
+        self.set_1i(texture_name, unit as i32);
+        unsafe {
+            glActiveTexture(GLenum(GL_TEXTURE0.0 as u32));
+        }
     }
     pub fn set_material(&self, material_name: &str, value: &Material) {
-        let diffuse_vector = value.get_diffuse_maps();
-        let specular_vector = value.get_specular_maps();
+        let needed = UnitPlan::of(value);
+        let plan = needed.fit(environment_unit());
+        if plan != needed {
+            warn_unit_overflow(self.0, value, &needed, &plan);
+        }
+        let diffuse_vector = &value.get_diffuse_maps()[..plan.diffuse];
+        let specular_vector = &value.get_specular_maps()[..plan.specular];
         let loaded_diffuse = diffuse_vector.len().max(1) as i32;
         let loaded_specular = specular_vector.len().max(1) as i32;
         let mut tex_count = 0;
@@ -288,9 +620,7 @@ impl ShaderProgram {
             unsafe {
                 glActiveTexture(GLenum(GL_TEXTURE0.0 + tex_count as u32));
             }
-            let diff = Texture2D::new(crate::textures::TextureType::Diffuse);
-            diff.empty_texture();
-            diff.bind();
+            PLACEHOLDER_MAPS.with(|(diff, _)| diff.bind());
             let name = format!("{}.diffuseTextures[0]", material_name);
             self.set_1i(&name, tex_count as i32);
             tex_count += 1;
@@ -299,37 +629,164 @@ impl ShaderProgram {
             unsafe {
                 glActiveTexture(GLenum(GL_TEXTURE0.0 + tex_count as u32));
             }
-            let spec = Texture2D::new(crate::textures::TextureType::Specular);
-            spec.empty_texture();
-            spec.bind();
+            PLACEHOLDER_MAPS.with(|(_, spec)| spec.bind());
             let name = format!("{}.specularTextures[0]", material_name);
             self.set_1i(&name, tex_count as i32);
+            tex_count += 1;
         }
+        let blends = [
+            ("diffuseBlend", value.get_diffuse_blends(), plan.diffuse),
+            ("specularBlend", value.get_specular_blends(), plan.specular),
+        ];
+        for (array, blends, kept) in blends {
+            let blends = &blends[..blends.len().min(kept)];
+            for (i, blend) in blends.iter().enumerate() {
+                let name = format!("{}.{}[{}]", material_name, array, i);
+                self.set_1i(&format!("{}.op", name), blend.op as i32);
+                self.set_1f(&format!("{}.weight", name), blend.weight);
+            }
+            // placeholder maps stand in for missing ones and are averaged as is
+            if blends.is_empty() {
+                let name = format!("{}.{}[0]", material_name, array);
+                self.set_1i(&format!("{}.op", name), BlendOp::Average as i32);
+                self.set_1f(&format!("{}.weight", name), 1.0);
+            }
+        }
+        let transmission = value.get_transmission();
+        match transmission.map.as_ref().filter(|_| plan.transmission) {
+            Some(map) => {
+                unsafe {
+                    glActiveTexture(GLenum(GL_TEXTURE0.0 + tex_count as u32));
+                }
+                map.bind();
+                self.set_1i(&format!("{}.transmissionMap", material_name), tex_count);
+                tex_count += 1;
+            }
+            // any 2D unit keeps the sampler valid, it isn't read
+            None => self.set_1i(&format!("{}.transmissionMap", material_name), 0),
+        }
+        self.set_1b(
+            &format!("{}.hasTransmissionMap", material_name),
+            plan.transmission,
+        );
+        self.set_1f(
+            &format!("{}.transmission", material_name),
+            transmission.strength,
+        );
+        self.set_3f(
+            &format!("{}.transmissionColor", material_name),
+            &transmission.color,
+        );
+        self.set_1f(
+            &format!("{}.thickness", material_name),
+            transmission.thickness,
+        );
+        self.set_splat_map(
+            &format!("{}.splat", material_name),
+            value.get_splat_map().filter(|_| plan.splat_layers > 0),
+            plan.splat_normals,
+            tex_count as u32,
+        );
+
+        self.set_1b(
+            &format!("{}.triplanar", material_name),
+            value.get_triplanar().is_some(),
+        );
+        self.set_1f(
+            &format!("{}.triplanarTiling", material_name),
+            value.get_triplanar().unwrap_or(1.0),
+        );
+
+        self.set_1b(
+            &format!("{}.dithered", material_name),
+            value.get_alpha_mode() == AlphaMode::Dither,
+        );
 
         self.set_1f(
             &format!("{}.shininess", material_name),
             value.get_shininess(),
         );
+        self.set_1b(
+            &format!("{}.screenSpace", material_name),
+            value.is_screen_space(),
+        );
+        self.set_1f(
+            &format!("{}.reflectivity", material_name),
+            value.get_reflectivity(),
+        );
+        self.set_1f(
+            &format!("{}.roughness", material_name),
+            value.get_roughness(),
+        );
         self.set_1i(&format!("{}.loadedDiffuse", material_name), loaded_diffuse);
         self.set_1i(
             &format!("{}.loadedSpecular", material_name),
             loaded_specular,
         );
     }
+    // Textures go in consecutive units from `first_unit`. Samplers of missing textures are left on
+    // unit 0, which always has a 2D texture, and aren't read. Only the first `max_normals` normal
+    // maps are bound, the other layers are shaded as if they had none.
+    fn set_splat_map(
+        &self,
+        name: &str,
+        value: Option<&SplatMap>,
+        max_normals: usize,
+        first_unit: u32,
+    ) {
+        self.set_1b(&format!("{}.enabled", name), value.is_some());
+        let Some(splat) = value else {
+            self.set_1i(&format!("{}.mask", name), 0);
+            for i in 0..MAX_SPLAT_LAYERS {
+                self.set_1i(&format!("{}.albedo[{}]", name, i), 0);
+                self.set_1i(&format!("{}.normal[{}]", name, i), 0);
+            }
+            return;
+        };
+        let mut unit = first_unit;
+        let mut normals = 0;
+        self.set_texture2D_at(&format!("{}.mask", name), &splat.mask, unit);
+        unit += 1;
+        for i in 0..MAX_SPLAT_LAYERS {
+            let layer = splat.layers.get(i);
+            match layer {
+                Some(layer) => {
+                    self.set_texture2D_at(&format!("{}.albedo[{}]", name, i), &layer.albedo, unit);
+                    unit += 1;
+                    self.set_1f(&format!("{}.tiling[{}]", name, i), layer.tiling);
+                }
+                None => self.set_1i(&format!("{}.albedo[{}]", name, i), 0),
+            }
+            let normal = layer
+                .and_then(|layer| layer.normal.as_ref())
+                .filter(|_| normals < max_normals);
+            match normal {
+                Some(normal) => {
+                    self.set_texture2D_at(&format!("{}.normal[{}]", name, i), normal, unit);
+                    unit += 1;
+                    normals += 1;
+                }
+                None => self.set_1i(&format!("{}.normal[{}]", name, i), 0),
+            }
+            self.set_1b(&format!("{}.hasNormal[{}]", name, i), normal.is_some());
+        }
+        self.set_1i(&format!("{}.layers", name), splat.layers.len() as i32);
+        self.set_1b(&format!("{}.triplanar", name), splat.triplanar);
+    }
     pub fn set_directional_light(&self, name: &str, value: &DirectionalLight) {
         self.set_3f(format!("{}.direction", name).as_str(), &value.dir);
-        self.set_3f(format!("{}.ambient", name).as_str(), &value.amb);
-        self.set_3f(format!("{}.diffuse", name).as_str(), &value.diff);
-        self.set_3f(format!("{}.specular", name).as_str(), &value.spec);
+        self.set_3f(format!("{}.ambient", name).as_str(), &value.get_amb());
+        self.set_3f(format!("{}.diffuse", name).as_str(), &value.get_diff());
+        self.set_3f(format!("{}.specular", name).as_str(), &value.get_spec());
     }
     pub fn set_point_light(&self, name: &str, value: &PointLight) {
         self.set_3f(format!("{}.position", name).as_str(), &value.pos);
         self.set_1f(format!("{}.constant", name).as_str(), value.att.x);
         self.set_1f(format!("{}.linear", name).as_str(), value.att.y);
         self.set_1f(format!("{}.quadratic", name).as_str(), value.att.z);
-        self.set_3f(format!("{}.ambient", name).as_str(), &value.amb);
-        self.set_3f(format!("{}.diffuse", name).as_str(), &value.diff);
-        self.set_3f(format!("{}.specular", name).as_str(), &value.spec);
+        self.set_3f(format!("{}.ambient", name).as_str(), &value.get_amb());
+        self.set_3f(format!("{}.diffuse", name).as_str(), &value.get_diff());
+        self.set_3f(format!("{}.specular", name).as_str(), &value.get_spec());
     }
     pub fn set_spotlight(&self, name: &str, value: &Spotlight) {
         self.set_3f(format!("{}.position", name).as_str(), &value.pos);
@@ -344,3 +801,109 @@ impl ShaderProgram {
         self.set_1f(format!("{}.gammaCos", name).as_str(), value.gamma.cos());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meshes::{instance_attributes, VERTEX_ATTRIBUTES};
+
+    fn attribute(name: &str, location: i32, gl_type: GLenum) -> ActiveAttribute {
+        ActiveAttribute {
+            name: name.to_string(),
+            location,
+            gl_type,
+            array_size: 1,
+        }
+    }
+
+    #[test]
+    fn mesh_layout_feeds_object_shader() {
+        let mut layout = VERTEX_ATTRIBUTES.to_vec();
+        layout.extend(instance_attributes());
+        let attributes = [
+            attribute("aPos", 0, GL_FLOAT_VEC3),
+            attribute("aTexCoord", 2, GL_FLOAT_VEC2),
+            attribute("aInstRotation", 3, GL_FLOAT_VEC4),
+            attribute("aInstPosScale", 4, GL_FLOAT_VEC4),
+            attribute("aPrevInstRotation", 5, GL_FLOAT_VEC4),
+            attribute("aPrevInstPosScale", 6, GL_FLOAT_VEC4),
+            attribute("aInstDrawDistance", 7, GL_FLOAT_VEC2),
+            attribute("gl_VertexID", -1, GL_INT),
+        ];
+        assert_eq!(check_attributes(&attributes, &layout), Ok(()));
+    }
+
+    #[test]
+    fn defines_follow_the_version() {
+        let source = "#version 430 core\nvoid main() {}\n";
+        assert_eq!(with_defines(source, &[]), source);
+        assert_eq!(
+            with_defines(source, &["LOG_DEPTH", "A 1"]),
+            "#version 430 core\n#define LOG_DEPTH\n#define A 1\nvoid main() {}\n"
+        );
+        assert_eq!(
+            with_defines("void main() {}", &["B"]),
+            "#define B\nvoid main() {}"
+        );
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        let layout = VERTEX_ATTRIBUTES.to_vec();
+        let attributes = [
+            attribute("aColor", 1, GL_FLOAT_VEC4),
+            attribute("aInstModel", 3, GL_FLOAT_MAT4),
+        ];
+        let errors = check_attributes(&attributes, &layout).unwrap_err();
+        assert_eq!(errors.lines().count(), 5);
+        assert!(errors.contains("aColor reads 4 components at location 1"));
+        assert!(errors.contains("aInstModel reads location 6"));
+    }
+
+    #[test]
+    fn instance_attributes_follow_vertex_attributes() {
+        let locations: Vec<_> = instance_attribute_locations()
+            .into_iter()
+            .map(|(attribute, location)| (attribute.name, location))
+            .collect();
+        let first = VERTEX_ATTRIBUTES.len() as u32;
+        assert_eq!(
+            locations,
+            vec![
+                ("aInstRotation", first),
+                ("aInstPosScale", first + 1),
+                ("aPrevInstRotation", first + 2),
+                ("aPrevInstPosScale", first + 3),
+                ("aInstDrawDistance", first + 4),
+            ]
+        );
+        assert_eq!(instance_attributes().len(), 5);
+    }
+
+    #[test]
+    fn least_important_maps_are_dropped_first() {
+        let needed = UnitPlan {
+            diffuse: 4,
+            specular: 3,
+            transmission: true,
+            splat_layers: 4,
+            splat_normals: 4,
+        };
+        assert_eq!(needed.units(), 17);
+        let fitted = needed.fit(12);
+        assert_eq!(fitted.units(), 12);
+        assert_eq!(fitted.splat_normals, 0);
+        assert_eq!(fitted.specular, 2);
+        assert_eq!((fitted.diffuse, fitted.splat_layers), (4, 4));
+        assert_eq!(
+            needed.describe_drops(&fitted),
+            "4 splat normal map(s), 1 specular map(s)"
+        );
+
+        // past the extra maps, the splat map goes as a whole
+        let fitted = needed.fit(4);
+        assert_eq!(fitted.splat_layers, 0);
+        assert_eq!(fitted.units(), 2);
+        assert_eq!(needed.fit(20), needed);
+    }
+}
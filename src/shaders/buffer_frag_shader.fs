@@ -5,7 +5,10 @@ in VERTEX {
     vec2 texCoords;
 } fs_in;
 
-out vec4 fragColor;
+flat in float instanceFade;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 velocity;
 
 #define NR_DIFFUSE_TEXTURES 3
 #define NR_SPECULAR_TEXTURES 3
@@ -21,7 +24,23 @@ struct Material {
 uniform Material material;
 uniform vec3 outlineColor;
 
+// Same pattern the objects fade out with, so outlines go along with them
+float bayerThreshold() {
+    const float bayer[16] = float[](
+         0.0,  8.0,  2.0, 10.0,
+        12.0,  4.0, 14.0,  6.0,
+         3.0, 11.0,  1.0,  9.0,
+        15.0,  7.0, 13.0,  5.0
+    );
+    ivec2 cell = ivec2(gl_FragCoord.xy) % 4;
+    return (bayer[cell.y * 4 + cell.x] + 0.5) / 16.0;
+}
+
 void main() {
+    if (instanceFade < bayerThreshold()) {
+        discard;
+    }
+    velocity = vec2(0.0);
     float texture_alpha = 0.0;
     for (int i = 0; i < material.loadedDiffuse; i++) {
         texture_alpha = max(texture_alpha, texture(material . Diffuse[i], fs_in.texCoords).a);
@@ -1,6 +1,8 @@
 #version 430 core
-out vec4 fragColor;
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 velocity;
 
 void main() {
+    velocity = vec2(0.0);
     fragColor = vec4(1.0, 1.0, 0.0, 1.0);
 }
\ No newline at end of file
@@ -20,17 +20,31 @@ layout (std140, binding = 0) uniform Matrices {
     mat4 modelMat;
     mat4 viewMat;
     mat4 projMat;
+    mat4 prevViewProjMat;
+    float time;       // engine seconds, scaled and paused with the clock
+    float deltaTime;
+    float objectTime; // since the drawn object was spawned
+    float realTime;
 };
 
+uniform float logDepthCoef;
+
 const float MAGNITUDE = 0.1;
 
+vec4 toLogDepth(vec4 position) {
+    if (logDepthCoef > 0.0) {
+        position.z = (log2(max(1e-6, 1.0 + position.w)) * logDepthCoef - 1.0) * position.w;
+    }
+    return position;
+}
+
 void generate_line(int index, vec3 normal) {
     mat4 calcMat = projMat * viewMat;
-    gl_Position = calcMat * vec4(gs_in[index].pos, 1.0);
+    gl_Position = toLogDepth(calcMat * vec4(gs_in[index].pos, 1.0));
     EmitVertex();
 
-    gl_Position = calcMat * (vec4(gs_in[index].pos, 1.0) + 
-                                        normalize(vec4(normal, 0.0)) * MAGNITUDE);
+    gl_Position = toLogDepth(calcMat * (vec4(gs_in[index].pos, 1.0) + 
+                                        normalize(vec4(normal, 0.0)) * MAGNITUDE));
     EmitVertex();
     EndPrimitive();
 }
@@ -0,0 +1,11 @@
+#version 430 core
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 velocity;
+
+in vec3 color;
+
+void main()
+{
+    velocity = vec2(0.0);
+    fragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,27 @@
+#version 430 core
+layout(location = 0) in vec3 aPos;
+layout(location = 1) in vec3 aColor;
+
+layout (std140, binding = 0) uniform Matrices {
+    mat4 modelMat;
+    mat4 viewMat;
+    mat4 projMat;
+    mat4 prevViewProjMat;
+    float time;       // engine seconds, scaled and paused with the clock
+    float deltaTime;
+    float objectTime; // since the drawn object was spawned
+    float realTime;
+};
+
+out vec3 color;
+
+uniform float logDepthCoef;
+
+// Lines are given in world space
+void main() {
+    color = aColor;
+    gl_Position = projMat * viewMat * vec4(aPos, 1.0);
+    if (logDepthCoef > 0.0) {
+        gl_Position.z = (log2(max(1e-6, 1.0 + gl_Position.w)) * logDepthCoef - 1.0) * gl_Position.w;
+    }
+}
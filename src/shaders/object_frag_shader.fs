@@ -8,12 +8,49 @@ in VERTEX {
 #define NR_DIFFUSE_TEXTURES 3
 #define NR_SPECULAR_TEXTURES 3
 
+#define BLEND_AVERAGE 0
+#define BLEND_MULTIPLY 1
+#define BLEND_ADD 2
+#define BLEND_MASK 3
+
+struct MapBlend {
+    int op;
+    float weight;
+};
+
+#define NR_SPLAT_LAYERS 4
+
+struct Splat {
+    bool enabled;
+    sampler2D mask; // a layer per channel
+    sampler2D albedo[NR_SPLAT_LAYERS];
+    sampler2D normal[NR_SPLAT_LAYERS];
+    bool hasNormal[NR_SPLAT_LAYERS];
+    float tiling[NR_SPLAT_LAYERS];
+    int layers;
+    bool triplanar;
+};
+
 struct Material {
     sampler2D diffuseTextures[NR_DIFFUSE_TEXTURES];
     sampler2D specularTextures[NR_SPECULAR_TEXTURES];
+    MapBlend diffuseBlend[NR_DIFFUSE_TEXTURES];
+    MapBlend specularBlend[NR_SPECULAR_TEXTURES];
     float shininess;
     int loadedDiffuse;
     int loadedSpecular;
+    bool screenSpace;
+    float reflectivity;
+    float roughness;
+    float transmission;
+    vec3 transmissionColor;
+    float thickness;
+    bool hasTransmissionMap;
+    sampler2D transmissionMap;
+    Splat splat;
+    bool triplanar; // diffuse, specular and transmission maps projected in world space
+    float triplanarTiling;
+    bool dithered; // alpha is dithered instead of blended, so no sorting is needed
 };
 
 struct DirLight {
@@ -52,50 +89,184 @@ layout (std140, binding = 0) uniform Matrices {
     mat4 modelMat;
     mat4 viewMat;
     mat4 projMat;
+    mat4 prevViewProjMat;
+    float time;       // engine seconds, scaled and paused with the clock
+    float deltaTime;
+    float objectTime; // since the drawn object was spawned
+    float realTime;
 };
 
 #define NR_POINT_LIGHTS 4
 uniform DirLight dirLight;
 uniform PointLight pointLights[NR_POINT_LIGHTS];
+uniform int pointLightCount; // lights past it were culled this frame
 uniform Spotlight spotlight;
 
 uniform Material material;
 
-out vec4 fragColor;
+// per object, on top of the material
+struct Surface {
+    vec3 tint;
+    float opacity;
+    float emissive;
+};
+uniform Surface surface;
+
+in vec4 currentClip;
+in vec4 previousClip;
+#ifdef LOG_DEPTH
+in float logDepthW;
+#endif
+flat in float instanceFade;
+flat in int ditherOffset;
+
+#ifdef LOG_DEPTH
+uniform float logDepthCoef;
+#endif
+uniform vec3 cameraPos;
+
+in vec3 geo_normal; // world space
+
+uniform samplerCube environment;
+uniform float environmentMaxLod;
+
+// Rougher materials read blurrier mips of the environment
+vec3 environmentReflection() {
+    vec3 viewDir = normalize(fs_in.pos - cameraPos);
+    vec3 reflected = reflect(viewDir, normalize(geo_normal));
+    return textureLod(environment, reflected, material.roughness * environmentMaxLod).rgb;
+}
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 velocity;
 
 vec4 diff_tex_values[NR_DIFFUSE_TEXTURES];
 vec4 spec_tex_values[NR_SPECULAR_TEXTURES];
+// the maps of each kind blended together
+vec4 diffuse_color;
+vec4 specular_color;
+
+// Layers sampled maps onto the first one. Both kinds of maps have the same count, so this works
+// for either.
+vec4 blendLayers(vec4 layers[NR_DIFFUSE_TEXTURES], MapBlend blends[NR_DIFFUSE_TEXTURES], int count) {
+    vec4 color = layers[0];
+    float averaged = blends[0].weight;
+    for (int i = 1; i < count; i++) {
+        vec4 layer = layers[i];
+        float weight = blends[i].weight;
+        if (blends[i].op == BLEND_AVERAGE) {
+            averaged += weight;
+            color.rgb = mix(color.rgb, layer.rgb, averaged > 0.0 ? weight / averaged : 0.0);
+            color.a = max(color.a, layer.a);
+        } else if (blends[i].op == BLEND_MULTIPLY) {
+            color.rgb *= mix(vec3(1.0), layer.rgb, weight);
+        } else if (blends[i].op == BLEND_ADD) {
+            color.rgb += layer.rgb * weight;
+        } else if (blends[i].op == BLEND_MASK) {
+            color.rgb = mix(color.rgb, layer.rgb, layer.a * weight);
+        }
+    }
+    return color;
+}
+
+// Blend of the three axis-aligned projections, favoring the one the surface faces the most
+vec3 triplanarWeights(vec3 normal) {
+    vec3 weights = pow(abs(normal), vec3(4.0));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+vec4 sampleTriplanar(sampler2D tex, vec3 pos, vec3 normal, float tiling) {
+    vec3 weights = triplanarWeights(normal);
+    return texture(tex, pos.zy * tiling) * weights.x
+        + texture(tex, pos.xz * tiling) * weights.y
+        + texture(tex, pos.xy * tiling) * weights.z;
+}
+
+// Whiteout blend of a tangent space normal map projected along each axis, in world space
+vec3 triplanarNormal(sampler2D tex, vec3 pos, vec3 normal, float tiling) {
+    vec3 weights = triplanarWeights(normal);
+    vec3 tx = texture(tex, pos.zy * tiling).xyz * 2.0 - 1.0;
+    vec3 ty = texture(tex, pos.xz * tiling).xyz * 2.0 - 1.0;
+    vec3 tz = texture(tex, pos.xy * tiling).xyz * 2.0 - 1.0;
+    tx = vec3(tx.xy + normal.zy, abs(tx.z) * normal.x);
+    ty = vec3(ty.xy + normal.xz, abs(ty.z) * normal.y);
+    tz = vec3(tz.xy + normal.xy, abs(tz.z) * normal.z);
+    return normalize(tx.zyx * weights.x + ty.xzy * weights.y + tz.xyz * weights.z);
+}
+
+// Tangent frame from screen space derivatives, since meshes don't carry tangents
+mat3 cotangentFrame(vec3 normal, vec3 pos, vec2 uv) {
+    vec3 dp1 = dFdx(pos);
+    vec3 dp2 = dFdy(pos);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invmax = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    return mat3(tangent * invmax, bitangent * invmax, normal);
+}
+
+// A material map at the fragment, from its texture coordinates or projected along the world axes
+vec4 sampleMap(sampler2D tex) {
+    if (material.triplanar) {
+        return sampleTriplanar(tex, fs_in.pos, normalize(geo_normal), material.triplanarTiling);
+    }
+    return texture(tex, fs_in.texCoords);
+}
+
+vec4 splatWeights() {
+    vec4 weights = texture(material.splat.mask, fs_in.texCoords);
+    for (int i = material.splat.layers; i < NR_SPLAT_LAYERS; i++) {
+        weights[i] = 0.0;
+    }
+    return weights / max(weights.x + weights.y + weights.z + weights.w, 1e-4);
+}
+
+vec4 splatAlbedo(vec4 weights, vec3 worldNormal) {
+    vec4 color = vec4(0.0);
+    // every layer is sampled, branching on the weights would break the implicit derivatives
+    for (int i = 0; i < material.splat.layers; i++) {
+        float tiling = material.splat.tiling[i];
+        vec4 layer = material.splat.triplanar
+            ? sampleTriplanar(material.splat.albedo[i], fs_in.pos, worldNormal, tiling)
+            : texture(material.splat.albedo[i], fs_in.texCoords * tiling);
+        color += layer * weights[i];
+    }
+    return color;
+}
+
+// In world space. Layers without a normal map keep the geometric normal.
+vec3 splatNormal(vec4 weights, vec3 worldNormal) {
+    mat3 frame = cotangentFrame(worldNormal, fs_in.pos, fs_in.texCoords);
+    vec3 normal = vec3(0.0);
+    for (int i = 0; i < material.splat.layers; i++) {
+        vec3 layer = worldNormal;
+        float tiling = material.splat.tiling[i];
+        if (material.splat.hasNormal[i] && material.splat.triplanar) {
+            layer = triplanarNormal(material.splat.normal[i], fs_in.pos, worldNormal, tiling);
+        } else if (material.splat.hasNormal[i]) {
+            vec3 tangentNormal = texture(material.splat.normal[i], fs_in.texCoords * tiling).xyz * 2.0 - 1.0;
+            layer = normalize(frame * tangentNormal);
+        }
+        normal += layer * weights[i];
+    }
+    return normalize(normal);
+}
 
 vec4 calculateLightValue(float diff_str, float spec_str, vec3 amb_color, vec3 diff_color, vec3 spec_color, float shininess) {
-    vec4 final_ambient = vec4(0.0);
-    vec4 final_diffuse = vec4(0.0);
-    vec4 final_specular = vec4(0.0);
-
-    for (int i = 0; i < material.loadedDiffuse; i++) {
-        vec4 diff_tex = diff_tex_values[i];
-        vec4 ambient = vec4(amb_color, 1.0) * diff_tex;
-        final_ambient.rgb += ambient.rgb;
-        final_ambient.a = max(final_ambient.a, ambient.a);
-
-        vec4 diffuse = vec4(diff_color, 1.0) * diff_str * diff_tex;
-        diffuse.a = min(diffuse.a, 1.0);
-        final_diffuse.rgb += diffuse.rgb;
-        final_diffuse.a = max(final_diffuse.a, diffuse.a);
-    }
-    for (int i = 0; i < material.loadedSpecular; i++) {
-        vec4 spec_tex = spec_tex_values[i];
-        vec4 specular = vec4(spec_color, 1.0) * spec_str * spec_tex;
-        specular.a = min(specular.a, 1.0);
-        final_specular.rgb += specular.rgb;
-        final_specular.a = max(final_specular.a, specular.a);
-    }
-    final_ambient.rgb /= material.loadedDiffuse;
-    final_diffuse.rgb /= material.loadedDiffuse;
-    final_specular.rgb /= material.loadedSpecular;
+    vec4 ambient = vec4(amb_color, 1.0) * diffuse_color;
+
+    vec4 diffuse = vec4(diff_color, 1.0) * diff_str * diffuse_color;
+    diffuse.a = min(diffuse.a, 1.0);
+
+    vec4 specular = vec4(spec_color, 1.0) * spec_str * specular_color;
+    specular.a = min(specular.a, 1.0);
 
     vec4 final_light;
-    final_light.rgb = final_ambient.rgb + final_diffuse.rgb + final_specular.rgb;
-    final_light.a = max(final_ambient.a, max(final_diffuse.a, final_specular.a));
+    final_light.rgb = ambient.rgb + diffuse.rgb + specular.rgb;
+    final_light.a = max(ambient.a, max(diffuse.a, specular.a));
 
     return final_light;
 }
@@ -144,19 +315,94 @@ vec4 calculateSpotlight(Spotlight light, vec3 normal, vec3 fragPos, vec3 viewDir
     return spotlight_value;
 }
 
+// Diffuse light reaching the side facing away from the viewer, let through a thin surface. Light
+// crossing at a grazing angle goes through more material and is attenuated more.
+vec3 transmittedLight(vec3 lightDir, vec3 lightColor, vec3 backNormal) {
+    float facing = dot(backNormal, lightDir);
+    if (facing <= 0.0) {
+        return vec3(0.0);
+    }
+    float attenuation = exp(-material.thickness / max(facing, 0.05));
+    return lightColor * facing * attenuation;
+}
+
+vec3 calculateTransmission(vec3 albedo) {
+    vec3 toCamera = cameraPos - fs_in.pos;
+    vec3 normal = normalize(geo_normal);
+    // the side facing away from the camera, whichever way the surface was modeled
+    vec3 backNormal = dot(normal, toCamera) > 0.0 ? -normal : normal;
+
+    vec3 light = transmittedLight(normalize(-dirLight.direction), dirLight.diffuse, backNormal);
+    for (int i = 0; i < pointLightCount; i++) {
+        vec3 toLight = pointLights[i].position - fs_in.pos;
+        float dist = length(toLight);
+        float falloff = 1.0 / (pointLights[i].constant + pointLights[i].linear * dist + pointLights[i].quadratic * dist * dist);
+        light += transmittedLight(toLight / dist, pointLights[i].diffuse, backNormal) * falloff;
+    }
+    vec3 toSpot = normalize(spotlight.position - fs_in.pos);
+    float theta = dot(toSpot, normalize(-spotlight.direction));
+    float spotIntensity = max((theta - spotlight.gammaCos) / (spotlight.phiCos - spotlight.gammaCos), 0.0);
+    light += transmittedLight(toSpot, spotlight.diffuse, backNormal) * spotIntensity;
+
+    vec3 tint = material.transmissionColor;
+    if (material.hasTransmissionMap) {
+        tint *= sampleMap(material.transmissionMap).rgb;
+    }
+    return light * tint * albedo * material.transmission;
+}
+
+// 4x4 ordered dither threshold for the fragment, in (0, 1). Shifting the pattern keeps overlapping
+// surfaces from discarding the very same pixels.
+float bayerThreshold(int offset) {
+    const float bayer[16] = float[](
+         0.0,  8.0,  2.0, 10.0,
+        12.0,  4.0, 14.0,  6.0,
+         3.0, 11.0,  1.0,  9.0,
+        15.0,  7.0, 13.0,  5.0
+    );
+    ivec2 cell = (ivec2(gl_FragCoord.xy) + ivec2(offset, offset / 4)) % 4;
+    return (bayer[cell.y * 4 + cell.x] + 0.5) / 16.0;
+}
+
 void main() {
+    if (instanceFade < bayerThreshold(0)) {
+        discard;
+    }
+
+#ifdef LOG_DEPTH
+    // writing any depth turns early depth testing off, so only this variant does
+    gl_FragDepth = log2(logDepthW) * logDepthCoef * 0.5;
+#endif
+
+    // screen space motion since the previous frame, in texture coordinates
+    velocity = (currentClip.xy / currentClip.w - previousClip.xy / previousClip.w) * 0.5;
+
+    if (material.screenSpace) {
+        fragColor = texture(material.diffuseTextures[0], gl_FragCoord.xy / textureSize(material.diffuseTextures[0], 0));
+        return;
+    }
+
     for (int i = 0; i < material.loadedDiffuse; i++)
-        diff_tex_values[i] = texture(material.diffuseTextures[i], fs_in.texCoords);
+        diff_tex_values[i] = sampleMap(material.diffuseTextures[i]);
     for (int i = 0; i < material.loadedSpecular; i++)
-        spec_tex_values[i] = texture(material.specularTextures[i], fs_in.texCoords);
+        spec_tex_values[i] = sampleMap(material.specularTextures[i]);
+    diffuse_color = blendLayers(diff_tex_values, material.diffuseBlend, material.loadedDiffuse);
+    specular_color = blendLayers(spec_tex_values, material.specularBlend, material.loadedSpecular);
 
     vec3 norm = normalize(fs_in.normal);
+    if (material.splat.enabled) {
+        vec3 worldNormal = normalize(geo_normal);
+        vec4 weights = splatWeights();
+        diffuse_color = splatAlbedo(weights, worldNormal);
+        // lighting normals are in view space
+        norm = normalize(mat3(viewMat) * splatNormal(weights, worldNormal));
+    }
     vec3 viewPos = vec3(viewMat[3][0], viewMat[3][1], viewMat[3][2]);
     vec3 viewDir = normalize(viewPos - fs_in.pos);
 
     vec4 result = calculateDirectionalLight(dirLight, norm, viewDir);
 
-    for (int i = 0; i < NR_POINT_LIGHTS; i++) {
+    for (int i = 0; i < pointLightCount; i++) {
         vec4 pointlight_value = calculatePointLight(pointLights[i], norm, fs_in.pos, viewDir);
         result.rgb += pointlight_value.rgb;
         result.a = max(result.a, pointlight_value.a);
@@ -166,6 +412,24 @@ void main() {
     result.rgb += spotlight_value.rgb;
     result.a = max(result.a, spotlight_value.a);
 
+    if (material.transmission > 0.0) {
+        result.rgb += calculateTransmission(diffuse_color.rgb);
+    }
+
+    if (material.reflectivity > 0.0) {
+        result.rgb = mix(result.rgb, environmentReflection(), material.reflectivity);
+    }
+
+    result.rgb = result.rgb * surface.tint + diffuse_color.rgb * surface.emissive * surface.tint;
+    result.a *= surface.opacity;
+
+    if (material.dithered) {
+        if (result.a < bayerThreshold(ditherOffset)) {
+            discard;
+        }
+        result.a = 1.0;
+    }
+
     if (result.a < 0.1) {
         discard;
     } else {
@@ -2,13 +2,22 @@
 layout(location = 0) in vec3 aPos;
 layout(location = 1) in vec3 aNormal;
 layout(location = 2) in vec2 aTexCoord;
-layout(location = 3) in mat4 aInstModel;
-layout(location = 7) in mat3 aInstNormal;
+// located after the per-vertex attributes when the program is linked
+in vec4 aInstRotation; // quaternion
+in vec4 aInstPosScale; // position and uniform scale
+in vec4 aPrevInstRotation;
+in vec4 aPrevInstPosScale;
+in vec2 aInstDrawDistance; // max distance, 0 draws at any distance, and how long before it instances start fading out
 
 layout (std140, binding = 0) uniform Matrices {
     mat4 modelMat;
     mat4 viewMat;
     mat4 projMat;
+    mat4 prevViewProjMat;
+    float time;       // engine seconds, scaled and paused with the clock
+    float deltaTime;
+    float objectTime; // since the drawn object was spawned
+    float realTime;
 };
 
 out VERTEX {
@@ -18,6 +27,14 @@ out VERTEX {
 } vs_out;
 
 out vec3 geo_normal;
+out vec4 currentClip;
+out vec4 previousClip;
+out float logDepthW;
+flat out float instanceFade;
+flat out int ditherOffset; // per instance, so dithered instances don't hide each other entirely
+
+uniform float logDepthCoef; // 0 unless logarithmic depth is on
+uniform vec3 cameraPos;
 
 mat3 extractRotation(mat4 modelMatrix) {
     // Extract the upper-left 3x3 part of the model matrix
@@ -31,15 +48,69 @@ mat3 extractRotation(mat4 modelMatrix) {
     return rotationMatrix;
 }
 
+// Instances only carry rotation and scale in their upper 3x3, whose columns are orthogonal, so the
+// inverse transpose is just each column divided by its squared length
+mat3 instanceNormalMatrix(mat4 instModel) {
+    mat3 normalMatrix = mat3(instModel);
+
+    normalMatrix[0] /= dot(normalMatrix[0], normalMatrix[0]);
+    normalMatrix[1] /= dot(normalMatrix[1], normalMatrix[1]);
+    normalMatrix[2] /= dot(normalMatrix[2], normalMatrix[2]);
+
+    return normalMatrix;
+}
+
+vec3 rotateByQuat(vec4 q, vec3 v) {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+mat4 instanceMatrix(vec4 rotation, vec4 posScale) {
+    return mat4(
+        vec4(rotateByQuat(rotation, vec3(1.0, 0.0, 0.0)) * posScale.w, 0.0),
+        vec4(rotateByQuat(rotation, vec3(0.0, 1.0, 0.0)) * posScale.w, 0.0),
+        vec4(rotateByQuat(rotation, vec3(0.0, 0.0, 1.0)) * posScale.w, 0.0),
+        vec4(posScale.xyz, 1.0)
+    );
+}
+
+float distanceFade(mat4 instModel) {
+    if (aInstDrawDistance.x <= 0.0) {
+        return 1.0;
+    }
+    vec3 origin = vec3(modelMat * instModel[3]);
+    float dist = distance(origin, cameraPos);
+    return clamp((aInstDrawDistance.x - dist) / max(aInstDrawDistance.y, 1e-4), 0.0, 1.0);
+}
+
 void main() {
+    mat4 instModel = instanceMatrix(aInstRotation, aInstPosScale);
+    mat4 prevInstModel = instanceMatrix(aPrevInstRotation, aPrevInstPosScale);
+    instanceFade = distanceFade(instModel);
+    // from the position rather than the instance ID, which changes as instances get culled
+    ivec3 cell = ivec3(floor(aInstPosScale.xyz * 4.0));
+    ditherOffset = (cell.x + cell.y * 3 + cell.z * 7) & 15;
+    if (instanceFade <= 0.0) {
+        // the whole instance ends up outside the clip volume
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+
     gl_Position = vec4(aPos, 1.0);
-    vec4 out_pos_4 = modelMat * aInstModel * gl_Position;
+    vec4 out_pos_4 = modelMat * instModel * gl_Position;
     gl_Position = projMat * viewMat * out_pos_4;
     vs_out.pos = vec3(out_pos_4);
+    currentClip = gl_Position;
+    previousClip = prevViewProjMat * modelMat * prevInstModel * vec4(aPos, 1.0);
+
+    // per vertex logarithmic depth, so clipping agrees with what the fragment shader writes
+    logDepthW = 1.0 + gl_Position.w;
+    if (logDepthCoef > 0.0) {
+        gl_Position.z = (log2(max(1e-6, logDepthW)) * logDepthCoef - 1.0) * gl_Position.w;
+    }
 
     mat3 normal_mat = transpose(inverse(mat3(viewMat * modelMat)));
-    vs_out.normal = normal_mat * aInstNormal * aNormal;
-    geo_normal = extractRotation(modelMat) * extractRotation(aInstModel) * aNormal;
+    vs_out.normal = normal_mat * instanceNormalMatrix(instModel) * aNormal;
+    geo_normal = extractRotation(modelMat) * extractRotation(instModel) * aNormal;
     
     vs_out.texCoords = aTexCoord;
 }
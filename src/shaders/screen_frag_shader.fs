@@ -3,48 +3,103 @@ in vec2 texCoords;
 
 out vec4 fragColor;
 
-uniform sampler2DMS screenTexture;
-uniform int sampleCount;
-uniform bool applySobel, applyMSAA;
+uniform sampler2D screenTexture;
+uniform bool applySobel;
 uniform float gamma;
+uniform vec3 tint;
+uniform float exposure; // stops
+uniform vec2 whiteBalance; // temperature, green-magenta tint
+uniform int blurRadius;
+uniform int paletteLevels; // per channel, off below 2
 
-const float offset = 1.0 / 600.0;
+#define TRANSITION_FADE 0
+#define TRANSITION_DISSOLVE 1
+#define TRANSITION_WIPE 2
+
+struct Transition {
+    sampler2D outgoing;
+    float progress; // 1 once only the incoming scene is left
+    int kind;
+};
+
+uniform Transition transition;
+
+// An image shown as is in the bottom left corner, its colors are already the final ones
+struct Overlay {
+    sampler2D image;
+    bool enabled;
+};
+
+uniform Overlay overlay;
 
 const float kernel[3][3] = float[][](
     float[](2,2,2),
     float[](2,-15,2),
     float[](2,2,2));
 
+vec4 fetchTexel(ivec2 texelCoords) {
+    texelCoords = clamp(texelCoords, ivec2(0), textureSize(screenTexture, 0) - 1);
+    return texelFetch(screenTexture, texelCoords, 0);
+}
+
+float hash(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+vec4 blendTransition(vec4 incoming) {
+    if (transition.progress >= 1.0) {
+        return incoming;
+    }
+    vec4 outgoing = texture(transition.outgoing, texCoords);
+    if (transition.kind == TRANSITION_DISSOLVE) {
+        return hash(floor(gl_FragCoord.xy)) < transition.progress ? incoming : outgoing;
+    } else if (transition.kind == TRANSITION_WIPE) {
+        // a soft edge a few percent of the screen wide
+        float edge = smoothstep(transition.progress - 0.02, transition.progress + 0.02, texCoords.x);
+        return mix(incoming, outgoing, edge);
+    }
+    return mix(outgoing, incoming, transition.progress);
+}
+
+// Cheap white balance: warmer boosts red over blue, magenta takes green away
+vec3 whiteBalanceGain() {
+    vec3 temperature = vec3(1.0 + 0.2 * whiteBalance.x, 1.0, 1.0 - 0.2 * whiteBalance.x);
+    vec3 greenMagenta = vec3(1.0, 1.0 - 0.2 * whiteBalance.y, 1.0);
+    return temperature * greenMagenta;
+}
+
 void main() {
+    ivec2 center = ivec2(texCoords * textureSize(screenTexture, 0));
     fragColor = vec4(0);
-    if (applySobel && applyMSAA) {
-        for (int s = 0; s < sampleCount; s++) {
-            vec4 sampleColor = vec4(0);
-            for (int i = 0; i < 3; i++) {
-                for (int j = 0; j < 3; j++) {
-                    ivec2 texelCoords = ivec2((texCoords + ivec2(i - 1, j - 1) * offset) * textureSize(screenTexture));
-                    sampleColor += texelFetch(screenTexture, texelCoords, s) * kernel[i][j];
-                }
-            }
-            fragColor += sampleColor / sampleCount;
-        }
-
-    } else if (applySobel && !applyMSAA) {
+    if (applySobel) {
         for (int i = 0; i < 3; i++) {
             for (int j = 0; j < 3; j++) {
-                ivec2 texelCoords = ivec2((texCoords + ivec2(i - 1, j - 1) * offset) * textureSize(screenTexture));
-                fragColor += texelFetch(screenTexture, texelCoords, 0) * kernel[i][j];
+                fragColor += fetchTexel(center + ivec2(i - 1, j - 1)) * kernel[i][j];
             }
         }
-    } else if (applyMSAA) {
-        for (int s = 0; s < sampleCount; s++) {
-            ivec2 texelCoords = ivec2(texCoords * textureSize(screenTexture));
-            vec4 sampleColor = texelFetch(screenTexture, texelCoords, s);
-            fragColor += sampleColor / sampleCount;
+    } else if (blurRadius > 0) {
+        for (int i = -blurRadius; i <= blurRadius; i++) {
+            for (int j = -blurRadius; j <= blurRadius; j++) {
+                fragColor += fetchTexel(center + ivec2(i, j));
+            }
         }
+        fragColor /= (2 * blurRadius + 1) * (2 * blurRadius + 1);
     } else {
-        ivec2 texelCoords = ivec2(texCoords * textureSize(screenTexture));
-        fragColor = texelFetch(screenTexture, texelCoords, 0);
+        fragColor = texture(screenTexture, texCoords);
     }
+    fragColor = blendTransition(fragColor);
+    fragColor.rgb *= tint;
+    fragColor.rgb *= exp2(exposure) * whiteBalanceGain();
     fragColor.rgb = pow(fragColor.rgb, vec3(1.0/gamma));
-}
\ No newline at end of file
+    if (paletteLevels > 1) {
+        // quantized after gamma so the steps look even
+        float steps = float(paletteLevels - 1);
+        fragColor.rgb = floor(clamp(fragColor.rgb, 0.0, 1.0) * steps + 0.5) / steps;
+    }
+    if (overlay.enabled) {
+        ivec2 texel = ivec2(gl_FragCoord.xy);
+        if (all(lessThan(texel, textureSize(overlay.image, 0)))) {
+            fragColor = texelFetch(overlay.image, texel, 0);
+        }
+    }
+}
@@ -6,6 +6,11 @@ layout (std140, binding = 0) uniform Matrices {
     mat4 modelMat;
     mat4 viewMat;
     mat4 projMat;
+    mat4 prevViewProjMat;
+    float time;       // engine seconds, scaled and paused with the clock
+    float deltaTime;
+    float objectTime; // since the drawn object was spawned
+    float realTime;
 };
 
 out vec2 texCoords;
@@ -1,5 +1,6 @@
 #version 430 core
-out vec4 fragColor;
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 velocity;
 
 in vec3 texCoords;
 
@@ -7,5 +8,6 @@ uniform samplerCube skybox;
 
 void main()
 {
+    velocity = vec2(0.0);
     fragColor = texture(skybox, texCoords);
 }
\ No newline at end of file
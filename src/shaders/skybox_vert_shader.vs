@@ -7,9 +7,30 @@ layout (std140, binding = 0) uniform Matrices {
     mat4 modelMat;
     mat4 viewMat;
     mat4 projMat;
+    mat4 prevViewProjMat;
+    float time;       // engine seconds, scaled and paused with the clock
+    float deltaTime;
+    float objectTime; // since the drawn object was spawned
+    float realTime;
 };
 
+uniform float farDepth; // clip space depth of the far plane, which depends on the depth convention
+uniform float nearDepth;
+uniform bool fullscreen; // one triangle over the screen instead of the cube, aPos is ignored
+
 void main() {
+    if (fullscreen) {
+        // (-1, -1), (3, -1) and (-1, 3), which cover the whole clip square
+        vec2 corner = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+        // the view has no translation here, so the corner's point on the near plane is also the
+        // direction to look up. The far plane may be at infinity, the near one never is.
+        vec4 near = inverse(projMat * viewMat) * vec4(corner, nearDepth, 1.0);
+        texCoords = near.xyz / near.w;
+        gl_Position = vec4(corner, farDepth, 1.0);
+        return;
+    }
+
     texCoords = aPos;
-    gl_Position = (projMat * viewMat * vec4(aPos, 1.0)).xyww;
+    gl_Position = projMat * viewMat * vec4(aPos, 1.0);
+    gl_Position.z = farDepth * gl_Position.w;
 }
\ No newline at end of file
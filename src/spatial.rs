@@ -1,12 +1,45 @@
+use std::collections::HashMap;
+
+use nalgebra::UnitQuaternion;
 use nalgebra_glm::*;
 
+// Splits a model matrix into translation, rotation and scale, assuming it has no shear. A mirrored
+// basis is represented with a negative x scale.
+pub fn decompose(model: &Mat4) -> (Vec3, UnitQuaternion<f32>, Vec3) {
+    let mut basis = mat4_to_mat3(model);
+    let mut scale = vec3(
+        basis.column(0).norm(),
+        basis.column(1).norm(),
+        basis.column(2).norm(),
+    );
+    if basis.determinant() < 0.0 {
+        scale.x = -scale.x;
+    }
+    for i in 0..3 {
+        if scale[i] != 0.0 {
+            let column = basis.column(i) / scale[i];
+            basis.set_column(i, &column);
+        }
+    }
+    (model.column(3).xyz(), UnitQuaternion::from_matrix(&basis), scale)
+}
+
+pub fn compose(position: &Vec3, rotation: &UnitQuaternion<f32>, scale: &Vec3) -> Mat4 {
+    let mut model = rotation.to_homogeneous();
+    for i in 0..3 {
+        model.set_column(i, &(model.column(i) * scale[i]));
+    }
+    model.set_column(3, &vec4(position.x, position.y, position.z, 1.0));
+    model
+}
+
 pub trait Spatial {
-    fn get_model(&self) -> &Mat4;
+    fn get_model(&self) -> Mat4;
     fn get_normal(&mut self) -> &Mat3;
     fn set_model(&mut self, model: &Mat4);
     #[inline(always)]
     fn rotate(&mut self, angle: f32, axis: &Vec3) {
-        let mut model = *self.get_model();
+        let mut model = self.get_model();
         let translation = &Vec4::from_column_slice(model.column(3).as_slice());
         let rotation = rotation(angle, axis);
         model.set_column(3, &vec4(0.0, 0.0, 0.0, *model.get((3, 3)).unwrap()));
@@ -16,7 +49,7 @@ pub trait Spatial {
     }
     #[inline(always)]
     fn apply_rotation(&mut self, rotation: &Mat4) {
-        let mut model = *self.get_model();
+        let mut model = self.get_model();
         let translation = &Vec4::from_column_slice(model.column(3).as_slice());
         model.set_column(3, &vec4(0.0, 0.0, 0.0, *model.get((3, 3)).unwrap()));
         model = rotation * model;
@@ -24,15 +57,63 @@ pub trait Spatial {
         self.set_model(&model);
     }
     #[inline(always)]
+    fn rotate_quat(&mut self, rotation: &UnitQuaternion<f32>) {
+        self.apply_rotation(&rotation.to_homogeneous());
+    }
+    fn get_rotation(&self) -> UnitQuaternion<f32> {
+        decompose(&self.get_model()).1
+    }
+    fn set_rotation(&mut self, rotation: &UnitQuaternion<f32>) {
+        let (position, _, scale) = decompose(&self.get_model());
+        self.set_model(&compose(&position, rotation, &scale));
+    }
+    fn get_position(&self) -> Vec3 {
+        self.get_model().column(3).xyz()
+    }
+    fn set_position(&mut self, position: &Vec3) {
+        let mut model = self.get_model();
+        model.set_column(3, &vec4(position.x, position.y, position.z, 1.0));
+        self.set_model(&model);
+    }
+    fn get_scale(&self) -> Vec3 {
+        decompose(&self.get_model()).2
+    }
+    fn set_scale(&mut self, scale: &Vec3) {
+        let (position, rotation, _) = decompose(&self.get_model());
+        self.set_model(&compose(&position, &rotation, scale));
+    }
+    // Turns the object so its local +Z axis points at `target`, keeping local +Y as close to `up`
+    // as possible
+    fn look_at(&mut self, target: &Vec3, up: &Vec3) {
+        let direction = target - self.get_position();
+        if direction.norm() > f32::EPSILON {
+            self.set_rotation(&UnitQuaternion::face_towards(&direction, up));
+        }
+    }
+    // Applies the smallest rotation that takes the object's local +Y axis to `normal`, so its
+    // heading around the new up axis is preserved
+    fn align_to(&mut self, normal: &Vec3) {
+        let current_up = self.get_rotation() * vec3(0.0, 1.0, 0.0);
+        let rotation = UnitQuaternion::rotation_between(&current_up, normal).unwrap_or_else(|| {
+            // the vectors are opposite, so any perpendicular axis works
+            let mut axis = cross(&current_up, &vec3(1.0, 0.0, 0.0));
+            if axis.norm() < f32::EPSILON {
+                axis = cross(&current_up, &vec3(0.0, 0.0, 1.0));
+            }
+            UnitQuaternion::from_scaled_axis(normalize(&axis) * std::f32::consts::PI)
+        });
+        self.rotate_quat(&rotation);
+    }
+    #[inline(always)]
     fn scale(&mut self, factors: &Vec3) {
-        let mut model = *self.get_model();
+        let mut model = self.get_model();
         let to_origin = -vec4_to_vec3(&Vec4::from_column_slice(model.column(3).as_slice()));
         model = translation(&-to_origin) * scaling(&factors) * translation(&to_origin) * model;
         self.set_model(&model);
     }
     #[inline(always)]
     fn apply_scaling(&mut self, scaling: &Mat4) {
-        let mut model = *self.get_model();
+        let mut model = self.get_model();
         let translation = &Vec4::from_column_slice(model.column(3).as_slice());
         model.set_column(3, &vec4(0.0, 0.0, 0.0, *model.get((3, 3)).unwrap()));
         model = scaling * model;
@@ -41,8 +122,615 @@ pub trait Spatial {
     }
     #[inline(always)]
     fn translate(&mut self, offset: &Vec3) {
-        let mut model = *self.get_model();
+        let mut model = self.get_model();
         model.set_column(3, &(model.column(3) + vec3_to_vec4(offset)));
         self.set_model(&model);
     }
 }
+
+// Plain model matrix with a lazily recomputed normal matrix. Anything that changes the model goes
+// through `set_model`, which flags the normal matrix for recalculation on the next `get_normal`.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    model: Mat4,
+    normal: Mat3,
+    dirty_normal: bool,
+}
+
+impl Transform {
+    pub fn new() -> Self {
+        Transform {
+            model: Mat4::identity(),
+            normal: Mat3::identity(),
+            dirty_normal: false,
+        }
+    }
+
+    pub fn is_normal_dirty(&self) -> bool {
+        self.dirty_normal
+    }
+}
+
+impl Spatial for Transform {
+    fn get_model(&self) -> Mat4 {
+        self.model
+    }
+    fn get_normal(&mut self) -> &Mat3 {
+        if self.dirty_normal {
+            self.normal = mat4_to_mat3(&self.model.try_inverse().unwrap().transpose());
+            self.dirty_normal = false;
+        }
+        &self.normal
+    }
+    fn set_model(&mut self, model: &Mat4) {
+        self.model = *model;
+        self.dirty_normal = true;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    // Centered on the points' box rather than the tightest fit, which is plenty for culling and
+    // spacing things out
+    pub fn from_points<I: IntoIterator<Item = Vec3>>(points: I) -> Self {
+        let points: Vec<Vec3> = points.into_iter().collect();
+        if points.is_empty() {
+            return Self::new(Vec3::zeros(), 0.0);
+        }
+        let (min, max) = points.iter().fold((points[0], points[0]), |(min, max), p| {
+            (min.inf(p), max.sup(p))
+        });
+        let center = (min + max) / 2.0;
+        let radius = points
+            .iter()
+            .map(|p| distance(&center, p))
+            .fold(0.0, f32::max);
+        Self::new(center, radius)
+    }
+
+    pub fn transformed(&self, model: &Mat4) -> Self {
+        let (position, rotation, scale) = decompose(model);
+        let center = position + rotation * self.center.component_mul(&scale);
+        Self::new(center, self.radius * scale.abs().max())
+    }
+
+    pub fn intersects(&self, other: &BoundingSphere) -> bool {
+        distance2(&self.center, &other.center) < (self.radius + other.radius).powi(2)
+    }
+}
+
+// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    // None without any points
+    pub fn from_points<I: IntoIterator<Item = Vec3>>(points: I) -> Option<Self> {
+        points
+            .into_iter()
+            .fold(None, |aabb: Option<Aabb>, p| match aabb {
+                Some(aabb) => Some(Self::new(aabb.min.inf(&p), aabb.max.sup(&p))),
+                None => Some(Self::new(p, p)),
+            })
+    }
+
+    pub fn around_sphere(sphere: &BoundingSphere) -> Self {
+        let extent = vec3(sphere.radius, sphere.radius, sphere.radius);
+        Self::new(sphere.center - extent, sphere.center + extent)
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            vec3(a.x, a.y, a.z),
+            vec3(b.x, a.y, a.z),
+            vec3(a.x, b.y, a.z),
+            vec3(b.x, b.y, a.z),
+            vec3(a.x, a.y, b.z),
+            vec3(b.x, a.y, b.z),
+            vec3(a.x, b.y, b.z),
+            vec3(b.x, b.y, b.z),
+        ]
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(self.min.inf(&other.min), self.max.sup(&other.max))
+    }
+
+    // Box around the transformed box, which may be larger than the transformed contents' own
+    pub fn transformed(&self, model: &Mat4) -> Self {
+        Self::from_points(
+            self.corners()
+                .iter()
+                .map(|corner| (model * corner.push(1.0)).xyz()),
+        )
+        .unwrap()
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::new(self.center(), length(&self.size()) / 2.0)
+    }
+}
+
+// Side planes of a view frustum, normals pointing inwards. Near and far are left out since they
+// depend on the depth convention; every side plane goes through the eye, so nothing fully behind
+// it passes anyway.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 4],
+}
+
+impl Frustum {
+    pub fn from_view_projection(view_projection: &Mat4) -> Self {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+        ]
+        .map(|plane| plane / length(&plane.xyz()));
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| dot(&plane.xyz(), &sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    // Conservative: boxes outside near a corner of the frustum can still pass
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner farthest along the plane's normal
+            let corner = vec3(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            dot(&plane.xyz(), &corner) + plane.w >= 0.0
+        })
+    }
+}
+
+// Uniform grid of bounding spheres, bucketed by center, to find overlaps without testing every
+// pair. Works best with cells about as big as the typical sphere.
+pub struct SphereGrid {
+    cell_size: f32,
+    max_radius: f32,
+    cells: HashMap<(i32, i32, i32), Vec<BoundingSphere>>,
+}
+
+impl SphereGrid {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(f32::EPSILON),
+            max_radius: 0.0,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, point: &Vec3) -> (i32, i32, i32) {
+        let cell = floor(&(point / self.cell_size));
+        (cell.x as i32, cell.y as i32, cell.z as i32)
+    }
+
+    pub fn insert(&mut self, sphere: BoundingSphere) {
+        self.max_radius = self.max_radius.max(sphere.radius);
+        let cell = self.cell(&sphere.center);
+        self.cells.entry(cell).or_default().push(sphere);
+    }
+
+    pub fn overlaps(&self, sphere: &BoundingSphere) -> bool {
+        // anything overlapping has its center within both radii
+        let reach = Vec3::repeat(sphere.radius + self.max_radius);
+        let (min, max) = (
+            self.cell(&(sphere.center - reach)),
+            self.cell(&(sphere.center + reach)),
+        );
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    if let Some(cell) = self.cells.get(&(x, y, z)) {
+                        if cell.iter().any(|other| other.intersects(sphere)) {
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_mat4_eq(a: &Mat4, b: &Mat4) {
+        assert!((a - b).abs().max() < EPSILON, "{a} != {b}");
+    }
+
+    fn transformed() -> Transform {
+        let mut transform = Transform::new();
+        transform.rotate(0.7, &normalize(&vec3(1.0, 2.0, 3.0)));
+        transform.scale(&vec3(2.0, 2.0, 2.0));
+        transform.translate(&vec3(3.0, -1.0, 5.0));
+        transform
+    }
+
+    #[test]
+    fn new_transform_is_clean() {
+        let mut transform = Transform::new();
+        assert!(!transform.is_normal_dirty());
+        assert_eq!(*transform.get_normal(), Mat3::identity());
+    }
+
+    #[test]
+    fn set_model_flags_normal() {
+        let mut transform = Transform::new();
+        transform.set_model(&scaling(&vec3(2.0, 4.0, 8.0)));
+        assert!(transform.is_normal_dirty());
+    }
+
+    #[test]
+    fn transformations_flag_normal() {
+        let mut transform = Transform::new();
+        transform.translate(&vec3(1.0, 0.0, 0.0));
+        assert!(transform.is_normal_dirty());
+        transform.get_normal();
+        transform.rotate(1.0, &vec3(0.0, 1.0, 0.0));
+        assert!(transform.is_normal_dirty());
+        transform.get_normal();
+        transform.scale(&vec3(2.0, 2.0, 2.0));
+        assert!(transform.is_normal_dirty());
+    }
+
+    #[test]
+    fn get_normal_recomputes_and_clears() {
+        let mut transform = Transform::new();
+        transform.set_model(&scaling(&vec3(2.0, 4.0, 8.0)));
+        let normal = *transform.get_normal();
+        assert!(!transform.is_normal_dirty());
+        let expected = Mat3::from_diagonal(&vec3(0.5, 0.25, 0.125));
+        assert!((normal - expected).abs().max() < EPSILON);
+    }
+
+    #[test]
+    fn normal_follows_latest_model() {
+        let mut transform = Transform::new();
+        transform.set_model(&scaling(&vec3(2.0, 2.0, 2.0)));
+        transform.get_normal();
+        transform.set_model(&Mat4::identity());
+        assert!((*transform.get_normal() - Mat3::identity()).abs().max() < EPSILON);
+    }
+
+    #[test]
+    fn translate_moves_only_the_origin() {
+        let mut transform = transformed();
+        let before = transform.get_model();
+        transform.translate(&vec3(1.0, 2.0, 3.0));
+        let after = transform.get_model();
+        assert_eq!(mat4_to_mat3(&before), mat4_to_mat3(&after));
+        assert!((after.column(3) - before.column(3) - vec4(1.0, 2.0, 3.0, 0.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn translations_commute() {
+        let mut a = Transform::new();
+        a.translate(&vec3(1.0, 0.0, 0.0));
+        a.translate(&vec3(0.0, 2.0, 0.0));
+        let mut b = Transform::new();
+        b.translate(&vec3(0.0, 2.0, 0.0));
+        b.translate(&vec3(1.0, 0.0, 0.0));
+        assert_mat4_eq(&a.get_model(), &b.get_model());
+    }
+
+    #[test]
+    fn rotate_keeps_translation() {
+        let mut transform = transformed();
+        let before = transform.get_model();
+        transform.rotate(1.3, &vec3(0.0, 1.0, 0.0));
+        assert!((transform.get_model().column(3) - before.column(3)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn rotate_is_about_the_world_axis() {
+        let mut transform = Transform::new();
+        transform.rotate(std::f32::consts::FRAC_PI_2, &vec3(0.0, 0.0, 1.0));
+        let x = transform.get_model() * vec4(1.0, 0.0, 0.0, 1.0);
+        assert!((x - vec4(0.0, 1.0, 0.0, 1.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn rotate_is_reversible() {
+        let mut transform = transformed();
+        let before = transform.get_model();
+        let axis = normalize(&vec3(-1.0, 0.5, 2.0));
+        transform.rotate(0.9, &axis);
+        transform.rotate(-0.9, &axis);
+        assert_mat4_eq(&transform.get_model(), &before);
+    }
+
+    #[test]
+    fn rotate_matches_apply_rotation() {
+        let axis = normalize(&vec3(0.3, -1.0, 0.2));
+        let mut a = transformed();
+        a.rotate(0.4, &axis);
+        let mut b = transformed();
+        b.apply_rotation(&rotation(0.4, &axis));
+        assert_mat4_eq(&a.get_model(), &b.get_model());
+    }
+
+    #[test]
+    fn rotate_quat_matches_apply_rotation() {
+        let axis = normalize(&vec3(1.0, 1.0, 0.0));
+        let mut a = transformed();
+        a.rotate_quat(&UnitQuaternion::from_scaled_axis(axis * 2.1));
+        let mut b = transformed();
+        b.apply_rotation(&rotation(2.1, &axis));
+        assert_mat4_eq(&a.get_model(), &b.get_model());
+    }
+
+    #[test]
+    fn decompose_round_trips() {
+        let model = transformed().get_model();
+        let (position, rotation, scale) = decompose(&model);
+        assert_mat4_eq(&compose(&position, &rotation, &scale), &model);
+    }
+
+    #[test]
+    fn get_rotation_ignores_scale_and_translation() {
+        let axis = normalize(&vec3(1.0, 2.0, 3.0));
+        let transform = transformed();
+        let expected = UnitQuaternion::from_scaled_axis(axis * 0.7);
+        assert!(transform.get_rotation().angle_to(&expected) < EPSILON);
+    }
+
+    #[test]
+    fn set_rotation_keeps_scale_and_translation() {
+        let mut transform = transformed();
+        let before = transform.get_model();
+        let rotation = UnitQuaternion::from_scaled_axis(vec3(0.0, 1.5, 0.0));
+        transform.set_rotation(&rotation);
+        let (position, new_rotation, scale) = decompose(&transform.get_model());
+        assert!((position - before.column(3).xyz()).norm() < EPSILON);
+        assert!((scale - vec3(2.0, 2.0, 2.0)).norm() < EPSILON);
+        assert!(new_rotation.angle_to(&rotation) < EPSILON);
+    }
+
+    #[test]
+    fn set_rotation_slerps() {
+        let mut transform = Transform::new();
+        let start = UnitQuaternion::identity();
+        let end = UnitQuaternion::from_scaled_axis(vec3(0.0, 0.0, std::f32::consts::PI / 2.0));
+        transform.set_rotation(&start.slerp(&end, 0.5));
+        let x = transform.get_model() * vec4(1.0, 0.0, 0.0, 0.0);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((x - vec4(half, half, 0.0, 0.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn rotation_preserves_determinant() {
+        let mut transform = transformed();
+        let before = transform.get_model().determinant();
+        transform.rotate(1.1, &normalize(&vec3(2.0, -1.0, 1.0)));
+        assert!((transform.get_model().determinant() - before).abs() < EPSILON * before.abs());
+    }
+
+    // `scale` conjugates by the current translation, so it scales about the object's own origin
+    // rather than the world origin, exactly like `apply_scaling` does
+    #[test]
+    fn scale_keeps_translation() {
+        let mut transform = transformed();
+        let before = transform.get_model();
+        transform.scale(&vec3(0.5, 3.0, 1.5));
+        assert!((transform.get_model().column(3) - before.column(3)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn scale_matches_apply_scaling() {
+        let factors = vec3(0.5, 3.0, 1.5);
+        let mut a = transformed();
+        a.scale(&factors);
+        let mut b = transformed();
+        b.apply_scaling(&scaling(&factors));
+        assert_mat4_eq(&a.get_model(), &b.get_model());
+    }
+
+    #[test]
+    fn scale_multiplies_the_determinant() {
+        let mut transform = transformed();
+        let before = transform.get_model().determinant();
+        transform.scale(&vec3(2.0, 3.0, 4.0));
+        let after = transform.get_model().determinant();
+        assert!((after - before * 24.0).abs() < EPSILON * after.abs());
+    }
+
+    #[test]
+    fn uniform_scale_commutes_with_rotation() {
+        let axis = normalize(&vec3(1.0, -2.0, 0.5));
+        let mut a = transformed();
+        a.scale(&vec3(3.0, 3.0, 3.0));
+        a.rotate(0.6, &axis);
+        let mut b = transformed();
+        b.rotate(0.6, &axis);
+        b.scale(&vec3(3.0, 3.0, 3.0));
+        assert_mat4_eq(&a.get_model(), &b.get_model());
+    }
+
+    #[test]
+    fn translation_is_not_affected_by_later_rotation_or_scale() {
+        let mut transform = Transform::new();
+        transform.translate(&vec3(1.0, 2.0, 3.0));
+        transform.rotate(1.0, &vec3(0.0, 1.0, 0.0));
+        transform.scale(&vec3(4.0, 4.0, 4.0));
+        assert!((transform.get_model().column(3) - vec4(1.0, 2.0, 3.0, 1.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn scale_then_translate_does_not_scale_the_offset() {
+        let mut transform = Transform::new();
+        transform.scale(&vec3(10.0, 10.0, 10.0));
+        transform.translate(&vec3(1.0, 0.0, 0.0));
+        let origin = transform.get_model() * vec4(0.0, 0.0, 0.0, 1.0);
+        assert!((origin - vec4(1.0, 0.0, 0.0, 1.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn position_accessors() {
+        let mut transform = transformed();
+        assert!((transform.get_position() - vec3(3.0, -1.0, 5.0)).norm() < EPSILON);
+        let basis = mat4_to_mat3(&transform.get_model());
+        transform.set_position(&vec3(-1.0, 0.0, 2.0));
+        assert!((transform.get_position() - vec3(-1.0, 0.0, 2.0)).norm() < EPSILON);
+        assert_eq!(mat4_to_mat3(&transform.get_model()), basis);
+    }
+
+    #[test]
+    fn scale_accessors() {
+        let mut transform = transformed();
+        assert!((transform.get_scale() - vec3(2.0, 2.0, 2.0)).norm() < EPSILON);
+        let rotation = transform.get_rotation();
+        transform.set_scale(&vec3(1.0, 3.0, 0.5));
+        assert!((transform.get_scale() - vec3(1.0, 3.0, 0.5)).norm() < EPSILON);
+        assert!(transform.get_rotation().angle_to(&rotation) < EPSILON);
+        assert!((transform.get_position() - vec3(3.0, -1.0, 5.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn mirrored_scale_is_negative() {
+        let mut transform = Transform::new();
+        transform.set_model(&scaling(&vec3(-2.0, 1.0, 1.0)));
+        assert!((transform.get_scale() - vec3(-2.0, 1.0, 1.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn look_at_points_forward_axis_at_target() {
+        let mut transform = transformed();
+        let target = vec3(-2.0, 4.0, 1.0);
+        transform.look_at(&target, &vec3(0.0, 1.0, 0.0));
+        let forward = transform.get_rotation() * vec3(0.0, 0.0, 1.0);
+        let expected = normalize(&(target - transform.get_position()));
+        assert!((forward - expected).norm() < EPSILON);
+        assert!((transform.get_scale() - vec3(2.0, 2.0, 2.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn look_at_own_position_is_ignored() {
+        let mut transform = transformed();
+        let before = transform.get_model();
+        transform.look_at(&transform.get_position(), &vec3(0.0, 1.0, 0.0));
+        assert_mat4_eq(&transform.get_model(), &before);
+    }
+
+    #[test]
+    fn align_to_sets_up_axis() {
+        let mut transform = transformed();
+        let normal = normalize(&vec3(1.0, 1.0, 0.0));
+        transform.align_to(&normal);
+        let up = transform.get_rotation() * vec3(0.0, 1.0, 0.0);
+        assert!((up - normal).norm() < EPSILON);
+    }
+
+    #[test]
+    fn align_to_opposite_normal() {
+        let mut transform = Transform::new();
+        transform.align_to(&vec3(0.0, -1.0, 0.0));
+        let up = transform.get_rotation() * vec3(0.0, 1.0, 0.0);
+        assert!((up - vec3(0.0, -1.0, 0.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn sphere_contains_its_points() {
+        let points = [
+            vec3(1.0, 0.0, 0.0),
+            vec3(3.0, 2.0, 0.0),
+            vec3(2.0, 1.0, 4.0),
+        ];
+        let sphere = BoundingSphere::from_points(points);
+        for p in points {
+            assert!(distance(&sphere.center, &p) <= sphere.radius + EPSILON);
+        }
+    }
+
+    #[test]
+    fn transformed_sphere_follows_scale_and_translation() {
+        let sphere = BoundingSphere::new(vec3(1.0, 0.0, 0.0), 1.0);
+        let model = translation(&vec3(0.0, 5.0, 0.0)) * scaling(&vec3(2.0, 3.0, 2.0));
+        let moved = sphere.transformed(&model);
+        assert!(distance(&moved.center, &vec3(2.0, 5.0, 0.0)) < EPSILON);
+        assert!((moved.radius - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn grid_finds_overlaps_across_cells() {
+        let mut grid = SphereGrid::new(1.0);
+        grid.insert(BoundingSphere::new(vec3(0.0, 0.0, 0.0), 2.0));
+        assert!(grid.overlaps(&BoundingSphere::new(vec3(2.5, 0.0, 0.0), 0.6)));
+        assert!(!grid.overlaps(&BoundingSphere::new(vec3(2.5, 0.0, 0.0), 0.4)));
+        assert_eq!(grid.len(), 1);
+    }
+
+    #[test]
+    fn frustum_keeps_spheres_that_reach_into_the_view() {
+        let view = look_at(&Vec3::zeros(), &vec3(0.0, 0.0, -1.0), &vec3(0.0, 1.0, 0.0));
+        let frustum = Frustum::from_view_projection(&(perspective(1.0, 1.5, 0.1, 100.0) * view));
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(vec3(0.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(vec3(0.0, 0.0, 10.0), 1.0)));
+        // off to the side, but big enough to light what's in view
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(vec3(20.0, 0.0, -5.0), 1.0)));
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(vec3(20.0, 0.0, -5.0), 20.0)));
+        let unit_box =
+            |center: Vec3| Aabb::new(center - vec3(1.0, 1.0, 1.0), center + vec3(1.0, 1.0, 1.0));
+        assert!(frustum.intersects_aabb(&unit_box(vec3(0.0, 0.0, -10.0))));
+        assert!(!frustum.intersects_aabb(&unit_box(vec3(0.0, 0.0, 10.0))));
+        assert!(!frustum.intersects_aabb(&unit_box(vec3(20.0, 0.0, -5.0))));
+    }
+}
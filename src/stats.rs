@@ -0,0 +1,334 @@
+use std::collections::VecDeque;
+use std::ops::{Add, AddAssign};
+use std::time::Duration;
+
+use crate::events::{EngineEvent, Subscriber};
+use crate::profiler::ProfileFrame;
+
+const HISTORY_SIZE: usize = 300;
+const SPIKE_FACTOR: f32 = 2.0;
+const MIN_SPIKE_SAMPLES: usize = 30; // percentiles are meaningless before this many frames
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameTiming {
+    pub total: Duration,
+    pub passes: Vec<(&'static str, Duration)>,
+}
+
+impl FrameTiming {
+    pub fn get_pass(&self, pass: &str) -> Option<Duration> {
+        self.passes
+            .iter()
+            .find(|(name, _)| *name == pass)
+            .map(|(_, time)| *time)
+    }
+}
+
+// Bytes held on the host by scene data, by what it's for. Counts allocated capacity, not just
+// what's in use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub vertices: usize,
+    pub indices: usize,
+    pub instances: usize,
+}
+
+impl MemoryUsage {
+    pub fn of_vec<T>(vec: &Vec<T>) -> usize {
+        vec.capacity() * std::mem::size_of::<T>()
+    }
+
+    pub fn total(&self) -> usize {
+        self.vertices + self.indices + self.instances
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            vertices: self.vertices + other.vertices,
+            indices: self.indices + other.indices,
+            instances: self.instances + other.instances,
+        }
+    }
+}
+
+impl AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+// Instances that went to the GPU and those left out by frustum culling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+impl AddAssign for CullingStats {
+    fn add_assign(&mut self, other: Self) {
+        self.drawn += other.drawn;
+        self.culled += other.culled;
+    }
+}
+
+fn kib(bytes: usize) -> f32 {
+    bytes as f32 / 1024.0
+}
+
+pub struct FrameStats {
+    history: VecDeque<FrameTiming>,
+    capacity: usize,
+    current: FrameTiming,
+    totals: Vec<(&'static str, Duration)>,
+    cycles: u32,
+    spike_factor: f32,
+    spikes: u32,
+    objects_spawned: u32,
+    objects_despawned: u32,
+    scene_memory: MemoryUsage,
+    cloned_memory: usize,
+    culling: CullingStats,
+    pub dump_on_spike: bool,
+    pub dump_frames: usize,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self::with_capacity(HISTORY_SIZE)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            current: FrameTiming::default(),
+            totals: vec![],
+            cycles: 0,
+            spike_factor: SPIKE_FACTOR,
+            spikes: 0,
+            objects_spawned: 0,
+            objects_despawned: 0,
+            scene_memory: MemoryUsage::default(),
+            cloned_memory: 0,
+            culling: CullingStats::default(),
+            dump_on_spike: false,
+            dump_frames: 10,
+        }
+    }
+
+    pub fn set_spike_factor(&mut self, factor: f32) {
+        self.spike_factor = factor.max(1.0);
+    }
+
+    pub fn record(&mut self, pass: &'static str, time: Duration) {
+        match self.current.passes.iter_mut().find(|(name, _)| *name == pass) {
+            Some((_, total)) => *total += time,
+            None => self.current.passes.push((pass, time)),
+        }
+    }
+
+    // Closes the frame being recorded and returns whether it was a spike, i.e. whether it took
+    // longer than the spike factor times the median of the frames before it.
+    pub fn end_frame(&mut self, total: Duration) -> bool {
+        let mut frame = std::mem::take(&mut self.current);
+        frame.total = total;
+
+        let spike = self.history.len() >= MIN_SPIKE_SAMPLES
+            && total.as_secs_f32() > self.percentile(50.0).as_secs_f32() * self.spike_factor;
+
+        for (pass, time) in &frame.passes {
+            match self.totals.iter_mut().find(|(name, _)| name == pass) {
+                Some((_, sum)) => *sum += *time,
+                None => self.totals.push((pass, *time)),
+            }
+        }
+        self.cycles += 1;
+
+        if self.history.len() == self.capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+
+        if spike {
+            self.spikes += 1;
+            if self.dump_on_spike {
+                std::println!("{}", self.dump(self.dump_frames));
+            }
+        }
+        spike
+    }
+
+    // Same, with the timings the profiler took of the frame. Its top-level scopes are the passes.
+    pub fn end_profiled_frame(&mut self, frame: &ProfileFrame) -> bool {
+        for scope in frame.scopes.iter().filter(|scope| scope.depth == 0) {
+            self.record(scope.name, scope.duration);
+        }
+        self.end_frame(frame.duration)
+    }
+
+    // Nearest-rank percentile of the total frame time over the kept history
+    pub fn percentile(&self, p: f32) -> Duration {
+        if self.history.is_empty() {
+            return Duration::ZERO;
+        }
+        let mut times: Vec<Duration> = self.history.iter().map(|frame| frame.total).collect();
+        times.sort();
+        let rank = ((p.clamp(0.0, 100.0) / 100.0) * times.len() as f32).ceil() as usize;
+        times[rank.clamp(1, times.len()) - 1]
+    }
+
+    pub fn average(&self, pass: &str) -> Duration {
+        if self.cycles == 0 {
+            return Duration::ZERO;
+        }
+        self.totals
+            .iter()
+            .find(|(name, _)| *name == pass)
+            .map_or(Duration::ZERO, |(_, sum)| *sum / self.cycles)
+    }
+
+    // What the scene holds, and how much of it is copied to build each frame's `Scene`
+    pub fn record_memory(&mut self, scene: MemoryUsage, cloned: usize) {
+        self.scene_memory = scene;
+        self.cloned_memory = cloned;
+    }
+
+    pub fn get_scene_memory(&self) -> MemoryUsage {
+        self.scene_memory
+    }
+
+    pub fn record_culling(&mut self, culling: CullingStats) {
+        self.culling = culling;
+    }
+
+    pub fn get_culling(&self) -> CullingStats {
+        self.culling
+    }
+
+    pub fn get_spikes(&self) -> u32 {
+        self.spikes
+    }
+
+    pub fn get_last_frame(&self) -> Option<&FrameTiming> {
+        self.history.back()
+    }
+
+    pub fn dump(&self, frames: usize) -> String {
+        let mut out = std::format!("Last {} frames:\n", frames.min(self.history.len()));
+        let skipped = self.history.len().saturating_sub(frames);
+        for (i, frame) in self.history.iter().skip(skipped).enumerate() {
+            out += &std::format!("  [{}] total: {:?}", skipped + i, frame.total);
+            for (pass, time) in &frame.passes {
+                out += &std::format!(", {pass}: {time:?}");
+            }
+            out += "\n";
+        }
+        out
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for (pass, _) in &self.totals {
+            out += &std::format!("{pass} time: {:?}\n", self.average(pass));
+        }
+        out += &std::format!(
+            "Frame time p50/p95/p99: {:?} / {:?} / {:?}\n",
+            self.percentile(50.0),
+            self.percentile(95.0),
+            self.percentile(99.0)
+        );
+        out += &std::format!("Spikes: {}\n", self.spikes);
+        out += &std::format!(
+            "Scene memory: {:.1} KiB (vertices {:.1}, indices {:.1}, instances {:.1})\n",
+            kib(self.scene_memory.total()),
+            kib(self.scene_memory.vertices),
+            kib(self.scene_memory.indices),
+            kib(self.scene_memory.instances)
+        );
+        out += &std::format!("Cloned per frame: {:.1} KiB\n", kib(self.cloned_memory));
+        out += &std::format!(
+            "Instances drawn/culled: {} / {}\n",
+            self.culling.drawn,
+            self.culling.culled
+        );
+        out += &std::format!(
+            "Objects spawned/despawned: {} / {}\n",
+            self.objects_spawned,
+            self.objects_despawned
+        );
+        out
+    }
+}
+
+impl Subscriber for FrameStats {
+    fn on_event(&mut self, event: &EngineEvent) {
+        match event {
+            EngineEvent::ObjectSpawned(_) => self.objects_spawned += 1,
+            EngineEvent::ObjectDespawned(_) => self.objects_despawned += 1,
+            // frames drawn at another size say nothing about what a spike is now
+            EngineEvent::WindowResized(..) => self.history.clear(),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let mut stats = FrameStats::with_capacity(100);
+        assert_eq!(stats.percentile(50.0), Duration::ZERO);
+        // out of order, they're sorted before ranking
+        for millis in (1..=100).rev() {
+            stats.end_frame(ms(millis));
+        }
+        assert_eq!(stats.percentile(50.0), ms(50));
+        assert_eq!(stats.percentile(95.0), ms(95));
+        assert_eq!(stats.percentile(99.0), ms(99));
+
+        let mut stats = FrameStats::with_capacity(10);
+        for millis in 1..=10 {
+            stats.end_frame(ms(millis));
+        }
+        assert_eq!(stats.percentile(50.0), ms(5));
+        assert_eq!(stats.percentile(95.0), ms(10));
+        assert_eq!(stats.percentile(99.0), ms(10));
+    }
+
+    #[test]
+    fn no_spikes_before_enough_samples() {
+        let mut stats = FrameStats::new();
+        for _ in 1..MIN_SPIKE_SAMPLES {
+            stats.end_frame(ms(1));
+        }
+        assert!(!stats.end_frame(ms(100)));
+        assert_eq!(stats.get_spikes(), 0);
+    }
+
+    #[test]
+    fn spikes_are_slower_than_the_factor_times_the_median() {
+        let mut stats = FrameStats::new();
+        for _ in 0..MIN_SPIKE_SAMPLES {
+            stats.end_frame(ms(10));
+        }
+        assert!(!stats.end_frame(ms(19)));
+        assert!(stats.end_frame(ms(21)));
+        assert_eq!(stats.get_spikes(), 1);
+
+        // after a resize the history starts over, so there's no median to compare with
+        stats.on_event(&EngineEvent::WindowResized(800, 600));
+        assert_eq!(stats.percentile(50.0), Duration::ZERO);
+        assert!(!stats.end_frame(ms(100)));
+        assert_eq!(stats.get_spikes(), 1);
+    }
+}
@@ -4,10 +4,12 @@ use gl33::gl_groups::*;
 use gl33::global_loader::*;
 use nalgebra_glm::*;
 use stb_image::stb_image::bindgen::*;
+use std::cell::Cell;
 use std::ffi::c_void;
-use std::ffi::CString;
-use std::os::unix::prelude::OsStrExt;
 use std::path::Path;
+use std::rc::Rc;
+
+use crate::vfs;
 
 const EMPTY_DATA: [u8; 4] = [0; 4];
 
@@ -16,13 +18,23 @@ pub enum TextureType {
     Diffuse,
     Specular,
     Attachment,
+    Data, // values that aren't colors, like normal maps and masks
+}
+
+// How a texture's values come out when sampled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,   // decoded to linear, for colors picked by eye
+    Linear, // as stored, for data like specular intensity, masks or normals
 }
 
+// Clones share the same GL texture
 #[derive(Debug, Clone)]
 pub struct Texture2D {
-    id: u32,
+    texture: Rc<TextureName>,
     ttype: TextureType,
     path: String,
+    size: Cell<(u32, u32)>, // of the base level, 0 until something is uploaded
 }
 
 impl Texture2D {
@@ -32,19 +44,27 @@ impl Texture2D {
             glGenTextures(1, &mut texture);
         }
         Self {
-            id: texture,
+            texture: Rc::new(TextureName(texture)),
             ttype,
             path: String::new(),
+            size: Cell::new((0, 0)),
         }
     }
     pub fn load(&mut self, path: &Path) {
         let (mut width, mut height, mut nr_channels): (i32, i32, i32) = (0, 0, 0);
-        let path_string = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let file = match vfs::read(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
         unsafe {
-            glBindTexture(GL_TEXTURE_2D, self.id);
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
             stbi_set_flip_vertically_on_load(1);
-            let data = stbi_load(
-                path_string.as_ptr(),
+            let data = stbi_load_from_memory(
+                file.as_ptr(),
+                file.len() as i32,
                 &mut width,
                 &mut height,
                 &mut nr_channels,
@@ -70,11 +90,12 @@ impl Texture2D {
             stbi_image_free(data as *mut c_void);
             glBindTexture(GL_TEXTURE_2D, 0);
         }
+        self.size.set((width as u32, height as u32));
         self.path = path.display().to_string();
     }
     pub fn empty_texture(&self) {
         unsafe {
-            glBindTexture(GL_TEXTURE_2D, self.id);
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
             glTexImage2D(
                 GL_TEXTURE_2D,
                 0,
@@ -88,6 +109,29 @@ impl Texture2D {
             );
             glBindTexture(GL_TEXTURE_2D, 0);
         }
+        self.size.set((1, 1));
+    }
+    // Uninitialized storage, e.g. for render targets. Mipmaps aren't allocated, so the filters are
+    // set to plain linear to keep the texture complete.
+    pub fn allocate(&self, size: (u32, u32)) {
+        unsafe {
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
+            glTexImage2D(
+                GL_TEXTURE_2D,
+                0,
+                self.get_internal_format().0 as i32,
+                size.0 as i32,
+                size.1 as i32,
+                0,
+                GL_RGBA,
+                GL_UNSIGNED_BYTE,
+                std::ptr::null(),
+            );
+        }
+        self.set_filters(GL_LINEAR, GL_LINEAR);
+        self.set_wrapping(GL_CLAMP_TO_EDGE);
+        Self::clear_binding();
+        self.size.set(size);
     }
     pub fn from_color(&self, color: &Vec3) {
         let data: [u8; 4] = [
@@ -97,7 +141,7 @@ impl Texture2D {
             255,
         ];
         unsafe {
-            glBindTexture(GL_TEXTURE_2D, self.id);
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
             glTexImage2D(
                 GL_TEXTURE_2D,
                 0,
@@ -111,11 +155,107 @@ impl Texture2D {
             );
             glBindTexture(GL_TEXTURE_2D, 0);
         }
+        self.size.set((1, 1));
+    }
+
+    // A color image from memory, see `upload_rgba8`
+    pub fn from_rgba8(width: u32, height: u32, data: &[u8]) -> Result<Self, String> {
+        let texture = Self::new(TextureType::Diffuse);
+        texture.upload_rgba8(width, height, data)?;
+        Ok(texture)
+    }
+
+    // Replaces the whole texture with tightly packed RGBA rows, bottom row first like the images
+    // `load` flips. Data textures such as masks go through here, their type keeps them linear.
+    pub fn upload_rgba8(&self, width: u32, height: u32, data: &[u8]) -> Result<(), String> {
+        Self::check_rgba8_len(width, height, data)?;
+        unsafe {
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
+            glTexImage2D(
+                GL_TEXTURE_2D,
+                0,
+                self.get_internal_format().0 as i32,
+                width as i32,
+                height as i32,
+                0,
+                GL_RGBA,
+                GL_UNSIGNED_BYTE,
+                data.as_ptr() as *const c_void,
+            );
+            glGenerateMipmap(GL_TEXTURE_2D);
+            glBindTexture(GL_TEXTURE_2D, 0);
+        }
+        self.size.set((width, height));
+        Ok(())
+    }
+
+    // Replaces a region of a texture set up with `upload_rgba8`. Mipmaps are regenerated, so
+    // textures updated every frame are better off with plain linear filters.
+    pub fn update_rgba8(
+        &self,
+        offset: (u32, u32),
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(), String> {
+        Self::check_rgba8_len(width, height, data)?;
+        Self::check_region(offset, (width, height), self.size.get())?;
+        unsafe {
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
+            glTexSubImage2D(
+                GL_TEXTURE_2D,
+                0,
+                offset.0 as i32,
+                offset.1 as i32,
+                width as i32,
+                height as i32,
+                GL_RGBA,
+                GL_UNSIGNED_BYTE,
+                data.as_ptr() as *const c_void,
+            );
+            glGenerateMipmap(GL_TEXTURE_2D);
+            glBindTexture(GL_TEXTURE_2D, 0);
+        }
+        Ok(())
+    }
+
+    fn check_rgba8_len(width: u32, height: u32, data: &[u8]) -> Result<(), String> {
+        let expected = width as usize * height as usize * 4;
+        if data.len() == expected {
+            Ok(())
+        } else {
+            Err(format!(
+                "Expected {} bytes for a {}x{} RGBA image, got {}",
+                expected,
+                width,
+                height,
+                data.len()
+            ))
+        }
+    }
+
+    fn check_region(
+        offset: (u32, u32),
+        size: (u32, u32),
+        bounds: (u32, u32),
+    ) -> Result<(), String> {
+        let end = (
+            offset.0 as u64 + size.0 as u64,
+            offset.1 as u64 + size.1 as u64,
+        );
+        if end.0 <= bounds.0 as u64 && end.1 <= bounds.1 as u64 {
+            Ok(())
+        } else {
+            Err(format!(
+                "A {}x{} region at {:?} doesn't fit in a {}x{} texture",
+                size.0, size.1, offset, bounds.0, bounds.1
+            ))
+        }
     }
 
     pub fn bind(&self) {
         unsafe {
-            glBindTexture(GL_TEXTURE_2D, self.id);
+            glBindTexture(GL_TEXTURE_2D, self.texture.0);
         }
     }
 
@@ -146,16 +286,30 @@ impl Texture2D {
     }
 
     pub fn get_id(&self) -> u32 {
-        self.id
+        self.texture.0
     }
     pub fn get_type(&self) -> TextureType {
         self.ttype
     }
+    // Empty for textures that weren't loaded from a file
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+    // Follows from the internal format, which only diffuse textures get as sRGB
+    pub fn get_color_space(&self) -> ColorSpace {
+        match self.ttype {
+            TextureType::Diffuse => ColorSpace::Srgb,
+            TextureType::Specular | TextureType::Attachment | TextureType::Data => {
+                ColorSpace::Linear
+            }
+        }
+    }
     pub fn get_internal_format(&self) -> GLenum {
         match self.ttype {
             TextureType::Diffuse => GL_SRGB_ALPHA,
             TextureType::Specular => GL_RGBA,
             TextureType::Attachment => GL_RGBA,
+            TextureType::Data => GL_RGBA,
         }
     }
 
@@ -167,10 +321,24 @@ impl Texture2D {
     }
 }
 
+// Owns a GL texture name, deleted along with the last handle to it
+#[derive(Debug)]
+struct TextureName(u32);
+
+impl Drop for TextureName {
+    fn drop(&mut self) {
+        unsafe {
+            glDeleteTextures(1, &self.0);
+        }
+    }
+}
+
+// Clones share the same GL texture
 #[derive(Clone, Debug)]
 pub struct CubeMap {
-    id: u32,
+    texture: Rc<TextureName>,
     ttype: TextureType,
+    levels: u32,
 }
 
 impl CubeMap {
@@ -179,19 +347,30 @@ impl CubeMap {
         unsafe {
             glGenTextures(1, &mut texture);
         }
-        Self { id: texture, ttype }
+        Self {
+            texture: Rc::new(TextureName(texture)),
+            ttype,
+            levels: 1,
+        }
     }
     pub fn load(&mut self, paths: [&str; 6]) {
         unsafe {
-            glBindTexture(GL_TEXTURE_CUBE_MAP, self.id);
+            glBindTexture(GL_TEXTURE_CUBE_MAP, self.texture.0);
         }
         let (mut width, mut height, mut nr_channels): (i32, i32, i32) = (0, 0, 0);
         for i in 0..6 {
-            let path_string = CString::new(paths[i]).unwrap();
+            let file = match vfs::read(Path::new(paths[i])) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
             unsafe {
                 // stbi_set_flip_vertically_on_load(1);
-                let data = stbi_load(
-                    path_string.as_ptr(),
+                let data = stbi_load_from_memory(
+                    file.as_ptr(),
+                    file.len() as i32,
                     &mut width,
                     &mut height,
                     &mut nr_channels,
@@ -212,18 +391,40 @@ impl CubeMap {
                     GL_UNSIGNED_BYTE,
                     data as *const c_void,
                 );
-                glGenerateMipmap(GL_TEXTURE_CUBE_MAP);
                 stbi_image_free(data as *mut c_void);
             }
         }
+        self.levels = (width.max(height).max(1) as f32).log2().floor() as u32 + 1;
+        self.generate_filtered_mips();
         unsafe {
             glBindTexture(GL_TEXTURE_CUBE_MAP, 0);
         }
     }
 
+    // Each level is a 2x2 box filter of the one above, i.e. a progressively blurrier environment,
+    // which stands in for a proper roughness prefilter. Relies on seamless cubemap filtering, which
+    // is turned on along with the rest of the GL state, so the blurry levels don't show the face
+    // edges.
+    fn generate_filtered_mips(&self) {
+        unsafe {
+            glBindTexture(GL_TEXTURE_CUBE_MAP, self.texture.0);
+            glGenerateMipmap(GL_TEXTURE_CUBE_MAP);
+            glTexParameteri(
+                GL_TEXTURE_CUBE_MAP,
+                GL_TEXTURE_MAX_LEVEL,
+                self.levels as i32 - 1,
+            );
+        }
+    }
+
+    // Mip levels available for roughness lookups
+    pub fn get_levels(&self) -> u32 {
+        self.levels
+    }
+
     pub fn bind(&self) {
         unsafe {
-            glBindTexture(GL_TEXTURE_CUBE_MAP, self.id);
+            glBindTexture(GL_TEXTURE_CUBE_MAP, self.texture.0);
         }
     }
 
@@ -263,29 +464,268 @@ impl CubeMap {
     }
 
     pub fn get_id(&self) -> u32 {
-        self.id
+        self.texture.0
     }
     pub fn get_type(&self) -> TextureType {
         self.ttype
     }
 }
 
+// How a map is layered onto the ones before it. The first map of a kind is the base and only its
+// weight matters, for averaging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+    Average = 0, // weighted by `weight` against the maps averaged so far
+    Multiply = 1,
+    Add = 2,
+    Mask = 3, // interpolates towards the map by its alpha
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapBlend {
+    pub op: BlendOp,
+    pub weight: f32,
+}
+
+impl MapBlend {
+    pub fn new(op: BlendOp, weight: f32) -> Self {
+        Self { op, weight }
+    }
+
+    // What maps did before blends could be picked: all of them averaged equally
+    pub fn average() -> Self {
+        Self::new(BlendOp::Average, 1.0)
+    }
+}
+
+pub const MAX_SPLAT_LAYERS: usize = 4;
+
+// A texture layer of a splat map. Normal maps are tangent space.
+#[derive(Clone, Debug)]
+pub struct SplatLayer {
+    pub albedo: Texture2D,
+    pub normal: Option<Texture2D>,
+    pub tiling: f32, // repeats per texture coordinate unit, or per world unit when triplanar
+}
+
+impl SplatLayer {
+    pub fn load(albedo: &Path, normal: Option<&Path>, tiling: f32) -> Self {
+        Self {
+            albedo: Texture2D::setup_new(TextureType::Diffuse, albedo, GL_REPEAT),
+            normal: normal.map(|path| Texture2D::setup_new(TextureType::Data, path, GL_REPEAT)),
+            tiling,
+        }
+    }
+}
+
+// Up to four tiling layers blended by the channels of a mask (layer 0 by red, 1 by green, etc.),
+// which spans the mesh's texture coordinates. Triplanar layers are projected in world space
+// instead, so steep slopes don't stretch them.
+#[derive(Clone, Debug)]
+pub struct SplatMap {
+    pub mask: Texture2D,
+    pub layers: Vec<SplatLayer>,
+    pub triplanar: bool,
+}
+
+impl SplatMap {
+    pub fn new(mask: Texture2D, layers: Vec<SplatLayer>, triplanar: bool) -> Result<Self, String> {
+        if layers.is_empty() || layers.len() > MAX_SPLAT_LAYERS {
+            return Err(format!(
+                "Splat maps take 1 to {} layers, got {}",
+                MAX_SPLAT_LAYERS,
+                layers.len()
+            ));
+        }
+        Ok(Self {
+            mask,
+            layers,
+            triplanar,
+        })
+    }
+
+    // Terrain chunks share layers and only differ in their masks
+    pub fn load_chunk(&self, mask: &Path) -> Self {
+        Self {
+            mask: Texture2D::setup_new(TextureType::Data, mask, GL_CLAMP_TO_EDGE),
+            ..self.clone()
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Material {
     diffuse_maps: Vec<Texture2D>,
     specular_maps: Vec<Texture2D>,
+    diffuse_blends: Vec<MapBlend>,
+    specular_blends: Vec<MapBlend>,
     shininess: f32,
+    screen_space: bool,
+    reflectivity: f32,
+    roughness: f32,
+    transmission: Transmission,
+    splat: Option<SplatMap>,
+    triplanar: Option<f32>,
+    alpha_mode: AlphaMode,
+}
+
+// How the alpha of a material's fragments is applied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    // Blended over what's behind, which only looks right when drawn back to front
+    Blend,
+    // Fragments are kept or discarded by an ordered dither pattern scaled by the alpha, so they
+    // can be drawn in any order. Meant for instanced objects, whose instances can't be sorted
+    // within a draw, at the cost of a stippled look.
+    Dither,
+}
+
+// Light coming through thin surfaces (leaves, paper, windows) from behind. The map, if any, tints
+// the transmitted light per texel on top of `color`.
+#[derive(Clone, Debug)]
+pub struct Transmission {
+    pub strength: f32, // 0 disables it
+    pub color: Vec3,
+    pub thickness: f32, // how quickly light dies out going through, more at grazing angles
+    pub map: Option<Texture2D>,
+}
+
+impl Transmission {
+    pub fn none() -> Self {
+        Self {
+            strength: 0.0,
+            color: vec3(1.0, 1.0, 1.0),
+            thickness: 0.0,
+            map: None,
+        }
+    }
 }
 
 impl Material {
     pub fn new(diff: Vec<Texture2D>, spec: Vec<Texture2D>, shininess: f32) -> Self {
         Material {
+            diffuse_blends: vec![MapBlend::average(); diff.len()],
+            specular_blends: vec![MapBlend::average(); spec.len()],
             diffuse_maps: diff,
             specular_maps: spec,
             shininess,
+            screen_space: false,
+            reflectivity: 0.0,
+            roughness: 1.0,
+            transmission: Transmission::none(),
+            splat: None,
+            triplanar: None,
+            alpha_mode: AlphaMode::Blend,
+        }
+    }
+
+    // Takes its color from the splat map's layers instead of diffuse maps
+    pub fn from_splat_map(splat: SplatMap, spec: Vec<Texture2D>, shininess: f32) -> Self {
+        Material {
+            splat: Some(splat),
+            ..Self::new(vec![], spec, shininess)
+        }
+    }
+
+    pub fn get_splat_map(&self) -> Option<&SplatMap> {
+        self.splat.as_ref()
+    }
+
+    // Samples the maps projected along the world axes instead of at the texture coordinates, for
+    // meshes without usable ones. `tiling` is in repeats per world unit, None goes back to UVs.
+    pub fn set_triplanar(&mut self, tiling: Option<f32>) {
+        self.triplanar = tiling.filter(|tiling| *tiling > 0.0);
+    }
+
+    pub fn get_triplanar(&self) -> Option<f32> {
+        self.triplanar
+    }
+
+    // How much of the environment map shows on the surface, and how blurry it looks. Roughness
+    // goes from 0 (mirror-like) to 1 (the smallest mip of the environment).
+    pub fn set_reflections(&mut self, reflectivity: f32, roughness: f32) {
+        self.reflectivity = reflectivity.clamp(0.0, 1.0);
+        self.roughness = roughness.clamp(0.0, 1.0);
+    }
+
+    pub fn get_reflectivity(&self) -> f32 {
+        self.reflectivity
+    }
+
+    pub fn get_roughness(&self) -> f32 {
+        self.roughness
+    }
+
+    pub fn set_transmission(&mut self, transmission: Transmission) {
+        self.transmission = Transmission {
+            strength: transmission.strength.clamp(0.0, 1.0),
+            thickness: transmission.thickness.max(0.0),
+            ..transmission
+        };
+    }
+
+    pub fn get_transmission(&self) -> &Transmission {
+        &self.transmission
+    }
+
+    pub fn set_alpha_mode(&mut self, alpha_mode: AlphaMode) {
+        self.alpha_mode = alpha_mode;
+    }
+
+    pub fn get_alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    // Unlit material that samples `texture` at the fragment's screen position instead of its
+    // texture coordinates. The texture must have the same size as the target being drawn to.
+    pub fn from_render_target(texture: Texture2D) -> Self {
+        Material {
+            diffuse_maps: vec![texture],
+            specular_maps: vec![],
+            diffuse_blends: vec![MapBlend::average()],
+            specular_blends: vec![],
+            shininess: 1.0,
+            screen_space: true,
+            reflectivity: 0.0,
+            roughness: 1.0,
+            transmission: Transmission::none(),
+            splat: None,
+            triplanar: None,
+            alpha_mode: AlphaMode::Blend,
         }
     }
 
+    pub fn is_screen_space(&self) -> bool {
+        self.screen_space
+    }
+
+    // Adds a layer on top of the diffuse maps there are
+    pub fn add_diffuse_map(&mut self, map: Texture2D, blend: MapBlend) {
+        self.diffuse_maps.push(map);
+        self.diffuse_blends.push(blend);
+    }
+
+    pub fn add_specular_map(&mut self, map: Texture2D, blend: MapBlend) {
+        self.specular_maps.push(map);
+        self.specular_blends.push(blend);
+    }
+
+    pub fn set_diffuse_blend(&mut self, map: usize, blend: MapBlend) {
+        self.diffuse_blends[map] = blend;
+    }
+
+    pub fn set_specular_blend(&mut self, map: usize, blend: MapBlend) {
+        self.specular_blends[map] = blend;
+    }
+
+    pub fn get_diffuse_blends(&self) -> &Vec<MapBlend> {
+        &self.diffuse_blends
+    }
+
+    pub fn get_specular_blends(&self) -> &Vec<MapBlend> {
+        &self.specular_blends
+    }
+
     pub fn get_diffuse_maps(&self) -> &Vec<Texture2D> {
         &self.diffuse_maps
     }
@@ -331,6 +771,36 @@ impl Texture2DMultisample {
         Self::clear_binding();
     }
 
+    pub fn create_texture_with_format(&self, size: (u32, u32), format: GLenum) {
+        self.bind();
+        unsafe {
+            glTexImage2DMultisample(
+                GL_TEXTURE_2D_MULTISAMPLE,
+                self.samples as i32,
+                format,
+                size.0 as i32,
+                size.1 as i32,
+                GL_TRUE.0 as u8,
+            );
+        }
+        Self::clear_binding();
+    }
+
+    pub fn create_depth_stencil_texture(&self, size: (u32, u32)) {
+        self.bind();
+        unsafe {
+            glTexImage2DMultisample(
+                GL_TEXTURE_2D_MULTISAMPLE,
+                self.samples as i32,
+                GL_DEPTH24_STENCIL8,
+                size.0 as i32,
+                size.1 as i32,
+                GL_TRUE.0 as u8,
+            );
+        }
+        Self::clear_binding();
+    }
+
     pub fn bind(&self) {
         unsafe {
             glBindTexture(GL_TEXTURE_2D_MULTISAMPLE, self.id);
@@ -349,4 +819,9 @@ impl Texture2DMultisample {
     pub fn get_samples(&self) -> u32 {
         self.samples
     }
+    pub fn delete(&self) {
+        unsafe {
+            glDeleteTextures(1, &self.id);
+        }
+    }
 }
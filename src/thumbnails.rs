@@ -0,0 +1,156 @@
+use std::ffi::c_void;
+use std::path::Path;
+
+use gl33::gl_enumerations::*;
+use gl33::global_loader::*;
+use image::{ImageBuffer, Rgba};
+use nalgebra_glm::*;
+
+use crate::camera::Camera;
+use crate::data::{ClearOptions, Framebuffer, RenderTarget, UniformBuffer};
+use crate::debug::DebugGroup;
+use crate::lighting::{DirectionalLight, Lighting, PointLight, ShadowSettings, Spotlight};
+use crate::meshes::BasicMesh;
+use crate::scene::{Scene, SceneObject, SceneParameters, SpawnQueue};
+use crate::shaders::ShaderProgram;
+use crate::stats::CullingStats;
+use crate::textures::Material;
+
+const SPHERE_SEGMENTS: u32 = 48;
+const SPHERE_RINGS: u32 = 24;
+const CAMERA_DISTANCE: f32 = 2.6;
+const BACKGROUND: Vec4 = Vec4::new(0.18, 0.18, 0.18, 1.0);
+const GAMMA: f32 = 2.2; // same as the screen's default, thumbnails skip post-processing
+
+// Renders materials onto a unit sphere under a fixed lighting rig, so previews of different
+// materials can be compared side by side
+pub struct MaterialPreview {
+    target: RenderTarget,
+    lighting: Lighting,
+    camera: Camera,
+}
+
+impl MaterialPreview {
+    pub fn new(size: u32) -> Result<Self, String> {
+        let target =
+            RenderTarget::new((size, size)).ok_or("Couldn't make the material preview target")?;
+        let mut camera = Camera::new(vec3(0.0, 0.0, -CAMERA_DISTANCE));
+        camera.set_clip_planes(0.1, 10.0);
+        Ok(Self {
+            target,
+            lighting: Self::lighting_rig(),
+            camera,
+        })
+    }
+
+    // A warm key light from above the camera, a dim cool fill from the side and a rim light
+    // behind the sphere. The flashlight and the remaining lamps are off.
+    fn lighting_rig() -> Lighting {
+        let attenuation = vec3(1.0, 0.09, 0.032);
+        let lamps = vec![
+            PointLight::from_temperature(vec3(2.5, 0.5, -2.0), 9000.0, 500.0, attenuation),
+            PointLight::from_temperature(vec3(0.0, 2.0, 2.5), 6500.0, 1500.0, attenuation),
+        ];
+        let mut spot = Spotlight::from_temperature(
+            Vec3::zeros(),
+            vec3(0.0, 0.0, 1.0),
+            6500.0,
+            0.0,
+            attenuation,
+            0.1,
+            0.2,
+        );
+        spot.on = false;
+        Lighting {
+            dir: DirectionalLight::from_temperature(vec3(1.0, -1.0, 1.0), 4500.0, 1000.0),
+            point: lamps,
+            spot,
+            shadows: ShadowSettings::new(),
+            cull_distance: f32::INFINITY,
+        }
+    }
+
+    // RGBA rows, top first. Uses the UBO like any other pass, so it has to run between its
+    // `begin_frame` and `end_frame`.
+    pub fn render(
+        &self,
+        material: &Material,
+        object_shader: ShaderProgram,
+        ubo: &UniformBuffer,
+    ) -> Vec<u8> {
+        let _group = DebugGroup::new("Material preview");
+        let mut sphere = BasicMesh::sphere(1.0, SPHERE_SEGMENTS, SPHERE_RINGS);
+        sphere.material = material.clone();
+        // nothing but the object shader is used without skyboxes, outlines or lines
+        let mut scene = Scene {
+            objects: vec![SceneObject::from(sphere)],
+            skyboxes: &vec![],
+            object_shader,
+            skybox_shader: object_shader,
+            outline_shader: object_shader,
+            debug_shader: object_shader,
+            line_shader: object_shader,
+            camera: self.camera,
+            lighting: &self.lighting,
+            params: SceneParameters::init(),
+            previous_view_projection: None,
+            lines: None,
+            spawns: SpawnQueue::new(),
+            hooks: None,
+            culling: CullingStats::default(),
+        };
+
+        let size = self.target.get_size();
+        let mut pixels = vec![0u8; (size.0 * size.1 * 4) as usize];
+        self.target.bind();
+        unsafe {
+            glViewport(0, 0, size.0 as i32, size.1 as i32);
+        }
+        ClearOptions::all(BACKGROUND).apply();
+        unsafe {
+            glEnable(GL_DEPTH_TEST);
+        }
+        scene.compose(ubo);
+        unsafe {
+            glReadPixels(
+                0,
+                0,
+                size.0 as i32,
+                size.1 as i32,
+                GL_RGBA,
+                GL_UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut c_void,
+            );
+        }
+        Framebuffer::clear_binding();
+        scene.objects.clear();
+
+        let row = (size.0 * 4) as usize;
+        let mut image: Vec<u8> = pixels.chunks(row).rev().flatten().copied().collect();
+        for pixel in image.chunks_mut(4) {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as f32 / 255.0).powf(1.0 / GAMMA) * 255.0).round() as u8;
+            }
+        }
+        image
+    }
+
+    pub fn save(
+        &self,
+        material: &Material,
+        object_shader: ShaderProgram,
+        ubo: &UniformBuffer,
+        path: &Path,
+    ) -> Result<(), String> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)
+                .map_err(|e| format!("Couldn't create {}: {}", directory.display(), e))?;
+        }
+        let size = self.target.get_size();
+        let pixels = self.render(material, object_shader, ubo);
+        let image = ImageBuffer::<Rgba<u8>, _>::from_raw(size.0, size.1, pixels).unwrap();
+        image
+            .save(path)
+            .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+    }
+}
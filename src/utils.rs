@@ -2,29 +2,38 @@ use beryllium::Keycode;
 use rand::Rng;
 use std::ops::{Add, Rem, Sub};
 use std::rc::Rc;
+use std::time::Instant;
 use std::{cell::RefCell, fs};
 
-use nalgebra_glm::{rotation, vec3, Mat4, Vec3};
+use nalgebra::UnitQuaternion;
+use nalgebra_glm::{translation, vec3, Vec3};
 
 use crate::{
     controls::{Controller, SignalType, Slot},
-    scene::{Instance, SceneObject},
-    spatial::Spatial,
+    instances::Instance,
+    scene::SceneObject,
+    spatial::{BoundingSphere, Spatial, SphereGrid},
 };
 
+// Spins and drifts an object at constant rates, picking a new random axis and direction every
+// `ang_interval` and `lin_interval` seconds (never, if zero). Rates are per second, so how far
+// objects move only depends on the timestep it's stepped with, not on the frame rate.
 pub struct RandomTransform {
     axis: Vec3,
     dir: Vec3,
-    ang_step: f32,
-    lin_step: f32,
-    ang_upd_rate: u32,
-    lin_upd_rate: u32,
-    rotation: Mat4,
-    translation: Vec3,
+    ang_speed: f32, // radians per second
+    lin_speed: f32, // units per second
+    ang_interval: f32,
+    lin_interval: f32,
 }
 
 impl RandomTransform {
-    pub fn continuous(ang_step: f32, lin_step: f32, ang_rate: u32, lin_rate: u32) -> Self {
+    pub fn continuous(
+        ang_speed: f32,
+        lin_speed: f32,
+        ang_interval: f32,
+        lin_interval: f32,
+    ) -> Self {
         let mut rng = rand::thread_rng();
         let axis = vec3(
             rng.gen_range(-1.0..=1.0),
@@ -41,34 +50,64 @@ impl RandomTransform {
         RandomTransform {
             axis,
             dir,
-            ang_step,
-            lin_step,
-            ang_upd_rate: ang_rate,
-            lin_upd_rate: lin_rate,
-            rotation: rotation(ang_step, &axis),
-            translation: lin_step * dir,
+            ang_speed,
+            lin_speed,
+            ang_interval,
+            lin_interval,
         }
     }
+    fn random_offset(range_x: (f32, f32), range_y: (f32, f32), range_z: (f32, f32)) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let offset_x = rng.gen_range(range_x.0..=range_x.1);
+        let offset_y = rng.gen_range(range_y.0..=range_y.1);
+        let offset_z = rng.gen_range(range_z.0..=range_z.1);
+        vec3(offset_x, offset_y, offset_z)
+    }
     pub fn position(
         obj: &mut impl Spatial,
         range_x: (f32, f32),
         range_y: (f32, f32),
         range_z: (f32, f32),
     ) {
-        let mut rng = rand::thread_rng();
-        let offset_x = rng.gen_range(range_x.0..=range_x.1);
-        let offset_y = rng.gen_range(range_y.0..=range_y.1);
-        let offset_z = rng.gen_range(range_z.0..=range_z.1);
-        obj.translate(&vec3(offset_x, offset_y, offset_z));
+        obj.translate(&Self::random_offset(range_x, range_y, range_z));
+    }
+    // Like `position`, but retries offsets until `bounds` (in the object's space) lands clear of
+    // everything already in `placed`. After `max_attempts` the last offset is kept regardless.
+    // Either way the new bounds are added to `placed`, and whether they're clear is returned.
+    pub fn position_clear(
+        obj: &mut impl Spatial,
+        bounds: &BoundingSphere,
+        placed: &mut SphereGrid,
+        max_attempts: u32,
+        range_x: (f32, f32),
+        range_y: (f32, f32),
+        range_z: (f32, f32),
+    ) -> bool {
+        let model = obj.get_model();
+        let mut offset = Vec3::zeros();
+        let mut sphere = bounds.transformed(&model);
+        let mut clear = false;
+        for _ in 0..max_attempts.max(1) {
+            offset = Self::random_offset(range_x, range_y, range_z);
+            sphere = bounds.transformed(&(translation(&offset) * model));
+            if !placed.overlaps(&sphere) {
+                clear = true;
+                break;
+            }
+        }
+        obj.translate(&offset);
+        placed.insert(sphere);
+        clear
     }
     #[inline(always)]
-    pub fn rotate(&self, obj: &mut impl Spatial) {
-        obj.apply_rotation(&self.rotation);
-        // obj.rotate(self.ang_step, &self.axis);
+    pub fn rotate(&self, obj: &mut impl Spatial, dt: f32) {
+        obj.rotate_quat(&UnitQuaternion::from_scaled_axis(
+            self.axis * self.ang_speed * dt,
+        ));
     }
     #[inline(always)]
-    pub fn translate(&self, obj: &mut impl Spatial) {
-        obj.translate(&self.translation);
+    pub fn translate(&self, obj: &mut impl Spatial, dt: f32) {
+        obj.translate(&(self.dir * self.lin_speed * dt));
     }
     pub fn update_axis(&mut self) {
         let mut rng = rand::thread_rng();
@@ -78,7 +117,6 @@ impl RandomTransform {
             rng.gen_range(-1.0..=1.0),
         )
         .normalize();
-        self.rotation = rotation(self.ang_step, &self.axis);
     }
     pub fn update_dir(&mut self) {
         let mut rng = rand::thread_rng();
@@ -88,12 +126,12 @@ impl RandomTransform {
             rng.gen_range(-1.0..=1.0),
         )
         .normalize();
-        self.translation = self.lin_step * self.dir;
     }
 }
 
 pub struct RTController {
-    tick_list: Vec<(u32, u32)>, // ang, lin
+    timers: Vec<(f32, f32)>, // seconds until the next axis and direction change
+    last_tick: Option<Instant>,
 }
 
 impl<'a> Slot for RTController {
@@ -102,12 +140,40 @@ impl<'a> Slot for RTController {
 
 impl RTController {
     pub fn new() -> Rc<RefCell<RTController>> {
-        Rc::new(RefCell::new(Self { tick_list: vec![] }))
+        Rc::new(RefCell::new(Self {
+            timers: vec![],
+            last_tick: None,
+        }))
+    }
+
+    pub fn set_rts(&mut self, rts: &Vec<RandomTransform>) {
+        self.timers.clear();
+        self.add_rts(rts);
     }
 
     pub fn add_rts(&mut self, rts: &Vec<RandomTransform>) {
         for rt in rts {
-            self.tick_list.push((rt.ang_upd_rate, rt.lin_upd_rate));
+            self.timers.push((rt.ang_interval, rt.lin_interval));
+        }
+    }
+
+    // Counts `elapsed` seconds down on every timer, re-randomizing the transforms that ran out
+    fn tick(&mut self, rts: &mut [RandomTransform], elapsed: f32) {
+        for (timers, rt) in self.timers.iter_mut().zip(rts.iter_mut()) {
+            if rt.ang_interval > 0.0 {
+                timers.0 -= elapsed;
+                if timers.0 <= 0.0 {
+                    rt.update_axis();
+                    timers.0 += rt.ang_interval;
+                }
+            }
+            if rt.lin_interval > 0.0 {
+                timers.1 -= elapsed;
+                if timers.1 <= 0.0 {
+                    rt.update_dir();
+                    timers.1 += rt.lin_interval;
+                }
+            }
         }
     }
 }
@@ -118,21 +184,13 @@ impl<'a> Controller<'a, Vec<RandomTransform>, RTController> for Rc<RefCell<RTCon
     }
     fn process_signals(&'a self, obj: &mut Vec<RandomTransform>) {
         let mut self_obj = (**self).borrow_mut();
-        for i in 0..self_obj.tick_list.len() {
-            if self_obj.tick_list[i].0 == 1 {
-                obj[i].update_axis();
-                self_obj.tick_list[i].0 = obj[i].ang_upd_rate;
-            } else if self_obj.tick_list[i].0 > 1 {
-                self_obj.tick_list[i].0 -= 1;
-            }
-
-            if self_obj.tick_list[i].1 == 1 {
-                obj[i].update_dir();
-                self_obj.tick_list[i].1 = obj[i].lin_upd_rate;
-            } else if self_obj.tick_list[i].1 > 1 {
-                self_obj.tick_list[i].1 -= 1;
-            }
-        }
+        // measured here rather than passed in, since signals aren't processed every frame
+        let now = Instant::now();
+        let elapsed = self_obj
+            .last_tick
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        self_obj.last_tick = Some(now);
+        self_obj.tick(obj, elapsed);
     }
 }
 
@@ -144,3 +202,70 @@ pub fn constrained_step<T: Sub<Output = T> + Rem<Output = T> + Add<Output = T> +
 ) -> T {
     (curr_value - min + (step % modulus) + modulus) % modulus + min
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::Transform;
+    use nalgebra_glm::distance;
+
+    #[test]
+    fn motion_only_depends_on_elapsed_time() {
+        let rt = RandomTransform::continuous(2.0, 3.0, 0.0, 0.0);
+        let mut coarse = Transform::new();
+        let mut fine = Transform::new();
+        rt.rotate(&mut coarse, 0.5);
+        rt.translate(&mut coarse, 0.5);
+        for _ in 0..10 {
+            rt.rotate(&mut fine, 0.05);
+            rt.translate(&mut fine, 0.05);
+        }
+        let a = coarse.get_model();
+        let b = fine.get_model();
+        assert!((a - b).abs().max() < 1e-4, "{a} != {b}");
+        assert!((distance(&a.column(3).xyz(), &vec3(0.0, 0.0, 0.0)) - 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn scattered_spheres_dont_overlap() {
+        let bounds = BoundingSphere::new(vec3(0.0, 0.0, 0.0), 1.0);
+        let mut placed = SphereGrid::new(2.0);
+        let mut transforms = vec![];
+        for _ in 0..50 {
+            let mut transform = Transform::new();
+            let clear = RandomTransform::position_clear(
+                &mut transform,
+                &bounds,
+                &mut placed,
+                100,
+                (-50.0, 50.0),
+                (-50.0, 50.0),
+                (-50.0, 50.0),
+            );
+            assert!(clear);
+            transforms.push(transform.get_model().column(3).xyz());
+        }
+        for (i, a) in transforms.iter().enumerate() {
+            for b in &transforms[i + 1..] {
+                assert!(distance(a, b) >= 2.0);
+            }
+        }
+        assert_eq!(placed.len(), 50);
+    }
+
+    #[test]
+    fn timers_count_seconds() {
+        let mut rts = vec![RandomTransform::continuous(1.0, 1.0, 1.0, 0.0)];
+        let mut controller = RTController {
+            timers: vec![],
+            last_tick: None,
+        };
+        controller.add_rts(&rts);
+        controller.tick(&mut rts, 0.4);
+        controller.tick(&mut rts, 0.4);
+        assert!((controller.timers[0].0 - 0.2).abs() < 1e-5);
+        controller.tick(&mut rts, 0.4);
+        assert!((controller.timers[0].0 - 0.8).abs() < 1e-5);
+        assert_eq!(controller.timers[0].1, 0.0);
+    }
+}
@@ -0,0 +1,366 @@
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use zip::ZipArchive;
+
+// Virtual filesystem used by every asset loader. Directories and zip archives (.zip/.pak) are
+// mounted under logical roots such as "textures" or "models"; a path like "textures/wall.jpg" is
+// looked up in the most recently mounted source for that root first. Paths that don't match any
+// mount fall back to the real filesystem, so plain relative paths keep working.
+
+enum MountSource {
+    Directory(PathBuf),
+    Archive(RefCell<ZipArchive<File>>),
+}
+
+struct Mount {
+    root: String,
+    source: MountSource,
+}
+
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+thread_local! {
+    static VFS: RefCell<Vfs> = RefCell::new(Vfs::new());
+}
+
+fn normalize(path: &Path) -> String {
+    let mut logical = path.to_string_lossy().replace('\\', "/");
+    while let Some(stripped) = logical.strip_prefix("./") {
+        logical = stripped.to_string();
+    }
+    logical.trim_matches('/').to_string()
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self { mounts: vec![] }
+    }
+
+    pub fn mount_dir<P: AsRef<Path>>(&mut self, root: &str, dir: P) {
+        self.mounts.push(Mount {
+            root: normalize(Path::new(root)),
+            source: MountSource::Directory(dir.as_ref().to_path_buf()),
+        });
+    }
+
+    pub fn mount_archive<P: AsRef<Path>>(&mut self, root: &str, archive: P) -> Result<(), String> {
+        let path = archive.as_ref();
+        let file = File::open(path)
+            .map_err(|e| format!("Couldn't open archive {}: {}", path.display(), e))?;
+        let archive = ZipArchive::new(file)
+            .map_err(|e| format!("Couldn't read archive {}: {}", path.display(), e))?;
+        self.mounts.push(Mount {
+            root: normalize(Path::new(root)),
+            source: MountSource::Archive(RefCell::new(archive)),
+        });
+        Ok(())
+    }
+
+    pub fn unmount(&mut self, root: &str) {
+        let root = normalize(Path::new(root));
+        self.mounts.retain(|mount| mount.root != root);
+    }
+
+    // Mounts matching `path`, newest first, along with the path relative to each mount
+    fn candidates<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a Mount, &'a str)> {
+        self.mounts.iter().rev().filter_map(move |mount| {
+            if mount.root.is_empty() {
+                Some((mount, path))
+            } else {
+                path.strip_prefix(mount.root.as_str())
+                    .and_then(|rest| rest.strip_prefix('/'))
+                    .map(|rest| (mount, rest))
+            }
+        })
+    }
+
+    // Real file backing `path`, if it isn't inside an archive. Loaders that need to follow
+    // references to sibling files (e.g. .obj -> .mtl) prefer this over `read`.
+    pub fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        let logical = normalize(path);
+        for (mount, relative) in self.candidates(&logical) {
+            match &mount.source {
+                MountSource::Directory(dir) => {
+                    let real = dir.join(relative);
+                    if real.is_file() {
+                        return Some(real);
+                    }
+                }
+                MountSource::Archive(archive) => {
+                    if archive.borrow().file_names().any(|name| name == relative) {
+                        return None;
+                    }
+                }
+            }
+        }
+        if path.is_file() {
+            Some(path.to_path_buf())
+        } else {
+            None
+        }
+    }
+
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        let logical = normalize(path);
+        for (mount, relative) in self.candidates(&logical) {
+            match &mount.source {
+                MountSource::Directory(dir) => {
+                    if let Ok(data) = fs::read(dir.join(relative)) {
+                        return Ok(data);
+                    }
+                }
+                MountSource::Archive(archive) => {
+                    let mut archive = archive.borrow_mut();
+                    if let Ok(mut file) = archive.by_name(relative) {
+                        let mut data = Vec::with_capacity(file.size() as usize);
+                        file.read_to_end(&mut data)
+                            .map_err(|e| format!("Couldn't read {}: {}", logical, e))?;
+                        return Ok(data);
+                    }
+                }
+            }
+        }
+        fs::read(path).map_err(|e| format!("Unable to read file {}: {}", path.display(), e))
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        let logical = normalize(path);
+        let mounted = self
+            .candidates(&logical)
+            .any(|(mount, relative)| match &mount.source {
+                MountSource::Directory(dir) => dir.join(relative).is_file(),
+                MountSource::Archive(archive) => {
+                    archive.borrow().file_names().any(|name| name == relative)
+                }
+            });
+        mounted || path.is_file()
+    }
+
+    // Copies `path` and the files next to it out of the archive holding it, under `destination`,
+    // for loaders that follow references to sibling files but can only open real ones. Returns
+    // where `path` ended up, or the real file if it isn't in an archive.
+    pub fn extract(&self, path: &Path, destination: &Path) -> Result<PathBuf, String> {
+        let logical = normalize(path);
+        for (mount, relative) in self.candidates(&logical) {
+            match &mount.source {
+                MountSource::Directory(dir) => {
+                    let real = dir.join(relative);
+                    if real.is_file() {
+                        return Ok(real);
+                    }
+                }
+                MountSource::Archive(archive) => {
+                    let mut archive = archive.borrow_mut();
+                    if !archive.file_names().any(|name| name == relative) {
+                        continue;
+                    }
+                    let prefix = relative.rfind('/').map_or("", |i| &relative[..=i]);
+                    let siblings: Vec<String> = archive
+                        .file_names()
+                        .filter(|name| {
+                            name.strip_prefix(prefix)
+                                .is_some_and(|file| !file.is_empty() && !file.contains('/'))
+                        })
+                        .map(String::from)
+                        .collect();
+                    let target = destination.join(&logical);
+                    let directory = target.parent().unwrap_or(destination);
+                    fs::create_dir_all(directory)
+                        .map_err(|e| format!("Couldn't create {}: {}", directory.display(), e))?;
+                    for name in siblings {
+                        let mut file = archive
+                            .by_name(&name)
+                            .map_err(|e| format!("Couldn't read {}: {}", name, e))?;
+                        let mut data = Vec::with_capacity(file.size() as usize);
+                        file.read_to_end(&mut data)
+                            .map_err(|e| format!("Couldn't read {}: {}", name, e))?;
+                        let extracted = directory.join(&name[prefix.len()..]);
+                        fs::write(&extracted, data).map_err(|e| {
+                            format!("Couldn't write {}: {}", extracted.display(), e)
+                        })?;
+                    }
+                    return Ok(target);
+                }
+            }
+        }
+        if path.is_file() {
+            Ok(path.to_path_buf())
+        } else {
+            Err(format!("No file {} to extract", logical))
+        }
+    }
+}
+
+pub fn mount_dir<P: AsRef<Path>>(root: &str, dir: P) {
+    VFS.with(|vfs| vfs.borrow_mut().mount_dir(root, dir));
+}
+
+pub fn mount_archive<P: AsRef<Path>>(root: &str, archive: P) -> Result<(), String> {
+    VFS.with(|vfs| vfs.borrow_mut().mount_archive(root, archive))
+}
+
+pub fn unmount(root: &str) {
+    VFS.with(|vfs| vfs.borrow_mut().unmount(root));
+}
+
+pub fn resolve(path: &Path) -> Option<PathBuf> {
+    VFS.with(|vfs| vfs.borrow().resolve(path))
+}
+
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    VFS.with(|vfs| vfs.borrow().read(path))
+}
+
+pub fn read_to_string(path: &Path) -> Result<String, String> {
+    let data = read(path)?;
+    String::from_utf8(data).map_err(|e| format!("{} is not valid UTF-8: {}", path.display(), e))
+}
+
+pub fn exists(path: &Path) -> bool {
+    VFS.with(|vfs| vfs.borrow().exists(path))
+}
+
+pub fn extract(path: &Path, destination: &Path) -> Result<PathBuf, String> {
+    VFS.with(|vfs| vfs.borrow().extract(path, destination))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    // An empty directory of its own for each test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tungus_vfs_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn zip(path: &Path, files: &[(&str, &str)]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn read_string(vfs: &Vfs, path: &str) -> Option<String> {
+        vfs.read(Path::new(path))
+            .ok()
+            .map(|data| String::from_utf8(data).unwrap())
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(
+            normalize(Path::new("./textures\\wall.jpg")),
+            "textures/wall.jpg"
+        );
+        assert_eq!(normalize(Path::new("././models/rock/")), "models/rock");
+        assert_eq!(normalize(Path::new("/scripts/a.rhai")), "scripts/a.rhai");
+    }
+
+    #[test]
+    fn newer_mounts_shadow_older_ones() {
+        let dir = scratch("shadowing");
+        write(&dir.join("old/wall.txt"), "old");
+        write(&dir.join("old/only_old.txt"), "only old");
+        write(&dir.join("new/wall.txt"), "new");
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("textures", dir.join("old"));
+        vfs.mount_dir("./textures/", dir.join("new"));
+
+        assert_eq!(
+            read_string(&vfs, "textures/wall.txt").as_deref(),
+            Some("new")
+        );
+        assert_eq!(
+            read_string(&vfs, "./textures/wall.txt").as_deref(),
+            Some("new")
+        );
+        // missing from the newer mount, so the older one has it
+        assert_eq!(
+            read_string(&vfs, "textures/only_old.txt").as_deref(),
+            Some("only old")
+        );
+        // the root has to be a whole path component
+        assert_eq!(read_string(&vfs, "texturesx/wall.txt"), None);
+        assert_eq!(read_string(&vfs, "wall.txt"), None);
+
+        vfs.unmount("textures");
+        assert!(!vfs.exists(Path::new("textures/wall.txt")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unmounted_paths_fall_back_to_the_real_filesystem() {
+        let dir = scratch("fallback");
+        let real = dir.join("loose.txt");
+        write(&real, "loose");
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("textures", dir.join("missing"));
+
+        assert_eq!(
+            read_string(&vfs, real.to_str().unwrap()).as_deref(),
+            Some("loose")
+        );
+        assert!(vfs.exists(&real));
+        assert_eq!(vfs.resolve(&real), Some(real.clone()));
+        assert_eq!(vfs.extract(&real, &dir.join("out")), Ok(real.clone()));
+        assert!(vfs.read(&dir.join("nowhere.txt")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn archives_extract_a_file_with_its_siblings() {
+        let dir = scratch("archive");
+        let archive = dir.join("assets.pak");
+        zip(
+            &archive,
+            &[
+                ("rock/rock.obj", "obj"),
+                ("rock/rock.mtl", "mtl"),
+                ("rock/textures/rock.png", "png"),
+                ("other.obj", "other"),
+            ],
+        );
+        let mut vfs = Vfs::new();
+        vfs.mount_archive("models", &archive).unwrap();
+
+        let obj = Path::new("models/rock/rock.obj");
+        assert_eq!(
+            read_string(&vfs, "models/rock/rock.obj").as_deref(),
+            Some("obj")
+        );
+        assert!(vfs.exists(obj));
+        // nothing real to hand out
+        assert_eq!(vfs.resolve(obj), None);
+
+        let out = dir.join("out");
+        let extracted = vfs.extract(obj, &out).unwrap();
+        assert_eq!(extracted, out.join("models/rock/rock.obj"));
+        assert_eq!(fs::read_to_string(&extracted).unwrap(), "obj");
+        assert_eq!(
+            fs::read_to_string(out.join("models/rock/rock.mtl")).unwrap(),
+            "mtl"
+        );
+        // only the files right next to it
+        assert!(!out.join("models/rock/textures").exists());
+        assert!(!out.join("models/other.obj").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}